@@ -0,0 +1,177 @@
+use crate::color::*;
+use crate::device::*;
+use crate::geometry::*;
+use std::io::{self, Write};
+
+/// Generates the frame that should be visible on the display once the right transpositions have
+/// been found.
+///
+/// The top left pixel is red, the rest of the top row is a green gradient running to the right
+/// and the rest of the left column is a blue gradient running down. This makes both the origin and
+/// the orientation of the axes recognizable.
+fn calibration_frame(dimensions: Dimensions) -> Vec<Pixel> {
+    let ramp = |i: usize, len: usize| (64 + 191 * i / len.max(2).saturating_sub(1)) as u8;
+    (0..dimensions.size())
+        .map(|i| {
+            let (x, y) = (i % dimensions.w, i / dimensions.w);
+            match (x, y) {
+                (0, 0) => Pixel { r: 255, g: 0, b: 0 },
+                (x, 0) => Pixel {
+                    r: 0,
+                    g: ramp(x, dimensions.w),
+                    b: 0,
+                },
+                (0, y) => Pixel {
+                    r: 0,
+                    g: 0,
+                    b: ramp(y, dimensions.h),
+                },
+                _ => Pixel::default(),
+            }
+        })
+        .collect()
+}
+
+/// Lists the transpositions for every corner the wiring may start at, combined with wiring that is
+/// progressive or serpentine over either axis.
+///
+/// Candidates that would result in the same arrangement as an earlier one are left out.
+fn candidates(dimensions: Dimensions) -> Vec<(Vec<&'static str>, Vec<usize>)> {
+    let mut candidates: Vec<(Vec<&'static str>, Vec<usize>)> = Vec::new();
+    for wiring in [None, Some("zigzag_x"), Some("zigzag_y")] {
+        for mirror in [
+            &[][..],
+            &["mirror_x"],
+            &["mirror_y"],
+            &["mirror_x", "mirror_y"],
+        ] {
+            let ops: Vec<_> = mirror.iter().copied().chain(wiring).collect();
+            let table = crate::transposition_table(&dimensions, ops.iter().copied()).unwrap();
+            if candidates.iter().all(|(_, t)| *t != table) {
+                candidates.push((ops, table));
+            }
+        }
+    }
+    candidates
+}
+
+/// Interactively finds the transpositions needed to show frames correctly on the output.
+///
+/// The calibration frame is shown using each candidate in turn until the user confirms that the
+/// display matches the preview printed to `term`. Returns the selected transpositions.
+pub fn detect_layout(
+    output: &mut dyn Output,
+    dimensions: Dimensions,
+    correction: &Correction,
+    mut answers: impl io::BufRead,
+    mut term: impl io::Write,
+) -> io::Result<Option<Vec<&'static str>>> {
+    let frame: Vec<Pixel> = calibration_frame(dimensions)
+        .into_iter()
+        .map(|pix| correction.correct(pix))
+        .collect();
+
+    let mut buf = Vec::new();
+    writeln!(buf, "The display should look like this:")?;
+    crate::simulator::render_ansi(
+        &mut buf,
+        &calibration_frame(dimensions),
+        dimensions.w,
+        dimensions.w,
+        dimensions.h,
+        crate::simulator::ColorMode::TrueColor,
+        crate::simulator::Density::Half,
+    )?;
+    term.write_all(&buf)?;
+
+    let candidates = candidates(dimensions);
+    for (i, (ops, table)) in candidates.iter().enumerate() {
+        let mut transposed = vec![Pixel::default(); frame.len()];
+        for (&to, &pix) in table.iter().zip(&frame) {
+            transposed[to] = pix;
+        }
+        output.output_frame(&transposed)?;
+
+        write!(
+            term,
+            "[{}/{}] Does the display match? [y/N/q] ",
+            i + 1,
+            candidates.len()
+        )?;
+        term.flush()?;
+        let mut answer = String::new();
+        if answers.read_line(&mut answer)? == 0 {
+            break;
+        }
+        match answer.trim() {
+            "y" | "Y" => {
+                let args = match ops.is_empty() {
+                    true => "no transpositions are needed".to_string(),
+                    false => format!("--transpose {}", ops.join(" ")),
+                };
+                writeln!(term, "Found it! Use: {}", args)?;
+                return Ok(Some(ops.clone()));
+            }
+            "q" | "Q" => break,
+            _ => (),
+        }
+    }
+    writeln!(term, "No matching layout was found")?;
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Recorder(Vec<Vec<Pixel>>);
+
+    impl Output for Recorder {
+        fn output_frame(&mut self, frame: &[Pixel]) -> io::Result<()> {
+            self.0.push(frame.to_vec());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn calibration_frame_marks_axes() {
+        let frame = calibration_frame(Dimensions { w: 4, h: 3 });
+        assert_eq!(Pixel { r: 255, g: 0, b: 0 }, frame[0]);
+        assert!(frame[1].g < frame[3].g && frame[1].r == 0 && frame[1].b == 0);
+        assert!(frame[4].b < frame[8].b && frame[4].r == 0 && frame[4].g == 0);
+        assert_eq!(Pixel::default(), frame[5]);
+    }
+
+    #[test]
+    fn candidates_are_unique() {
+        let c = candidates(Dimensions { w: 4, h: 3 });
+        assert_eq!(12, c.len());
+        // A single row has far fewer possible arrangements.
+        let c = candidates(Dimensions { w: 4, h: 1 });
+        assert_eq!(2, c.len());
+    }
+
+    #[test]
+    fn select_candidate() {
+        let dimensions = Dimensions { w: 4, h: 3 };
+        let mut output = Recorder(Vec::new());
+        let mut term = Vec::new();
+        let selected = detect_layout(
+            &mut output,
+            dimensions,
+            &Correction::none(),
+            &b"n\n\ny\n"[..],
+            &mut term,
+        )
+        .unwrap();
+        let candidates = candidates(dimensions);
+        assert_eq!(Some(candidates[2].0.clone()), selected);
+        assert_eq!(3, output.0.len());
+        assert_eq!(calibration_frame(dimensions), output.0[0]);
+        let term = String::from_utf8(term).unwrap();
+        assert!(term.ends_with(&format!(
+            "Found it! Use: --transpose {}\n",
+            candidates[2].0.join(" ")
+        )));
+    }
+}
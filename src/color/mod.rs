@@ -1,3 +1,5 @@
+use std::str;
+
 #[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
 pub struct Pixel {
     pub r: u8,
@@ -5,10 +7,186 @@ pub struct Pixel {
     pub b: u8,
 }
 
+impl Pixel {
+    /// Converts the pixel to hue in degrees, saturation and value, both in the range 0.0-1.0.
+    pub fn to_hsv(self) -> (f32, f32, f32) {
+        let (r, g, b) = (
+            f32::from(self.r) / 255.0,
+            f32::from(self.g) / 255.0,
+            f32::from(self.b) / 255.0,
+        );
+        let max = r.max(g).max(b);
+        let delta = max - r.min(g).min(b);
+        let h = if delta == 0.0 {
+            0.0
+        } else if max == r {
+            60.0 * ((g - b) / delta)
+        } else if max == g {
+            60.0 * ((b - r) / delta + 2.0)
+        } else {
+            60.0 * ((r - g) / delta + 4.0)
+        };
+        let s = if max == 0.0 { 0.0 } else { delta / max };
+        (h.rem_euclid(360.0), s, max)
+    }
+
+    /// The inverse of `to_hsv`. The hue wraps around, saturation and value are clamped.
+    pub fn from_hsv(h: f32, s: f32, v: f32) -> Pixel {
+        let (h, s, v) = (h.rem_euclid(360.0), s.clamp(0.0, 1.0), v.clamp(0.0, 1.0));
+        let c = v * s;
+        let x = c * (1.0 - ((h / 60.0) % 2.0 - 1.0).abs());
+        let (r, g, b) = match h {
+            h if h < 60.0 => (c, x, 0.0),
+            h if h < 120.0 => (x, c, 0.0),
+            h if h < 180.0 => (0.0, c, x),
+            h if h < 240.0 => (0.0, x, c),
+            h if h < 300.0 => (x, 0.0, c),
+            _ => (c, 0.0, x),
+        };
+        let m = v - c;
+        let channel = |v: f32| ((v + m) * 255.0).round().clamp(0.0, 255.0) as u8;
+        Pixel {
+            r: channel(r),
+            g: channel(g),
+            b: channel(b),
+        }
+    }
+}
+
+/// HsvAdjust transforms colors in the HSV color space.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct HsvAdjust {
+    /// Rotates the hue by this many degrees.
+    pub hue_shift: f32,
+    /// Multiplies the saturation.
+    pub saturation: f32,
+    /// Multiplies the value.
+    pub value: f32,
+}
+
+impl HsvAdjust {
+    pub fn apply(&self, pix: Pixel) -> Pixel {
+        let (h, s, v) = pix.to_hsv();
+        Pixel::from_hsv(h + self.hue_shift, s * self.saturation, v * self.value)
+    }
+}
+
+/// ColorOrder is the order in which the red, green and blue channels of a pixel are sent to a
+/// device. Each entry is the index of a channel in RGB order.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ColorOrder([usize; 3]);
+
+impl ColorOrder {
+    pub const RGB: ColorOrder = ColorOrder([0, 1, 2]);
+    pub const GRB: ColorOrder = ColorOrder([1, 0, 2]);
+
+    /// Computes the rearrangement that makes a device which sends channels in the `native` order
+    /// send them in this order instead. The result is meant to be applied with `remap`.
+    pub fn relative_to(self, native: ColorOrder) -> ColorOrder {
+        let mut order = [0; 3];
+        for (n, c) in native.0.into_iter().zip(self.0) {
+            order[n] = c;
+        }
+        ColorOrder(order)
+    }
+
+    /// Rearranges the channels of a pixel so its red, green and blue are taken from the channels
+    /// in this order.
+    pub fn remap(self, pix: Pixel) -> Pixel {
+        let ch = [pix.r, pix.g, pix.b];
+        Pixel {
+            r: ch[self.0[0]],
+            g: ch[self.0[1]],
+            b: ch[self.0[2]],
+        }
+    }
+}
+
+impl str::FromStr for ColorOrder {
+    type Err = String;
+    fn from_str(s: &str) -> Result<ColorOrder, Self::Err> {
+        let order: Vec<usize> = s
+            .chars()
+            .map(|c| match c.to_ascii_lowercase() {
+                'r' => Ok(0),
+                'g' => Ok(1),
+                'b' => Ok(2),
+                c => Err(format!("unknown channel {:?}, expected r, g or b", c)),
+            })
+            .collect::<Result<_, _>>()?;
+        match order[..] {
+            [a, b, c] if a != b && b != c && a != c => Ok(ColorOrder([a, b, c])),
+            _ => Err("expected each of r, g and b exactly once, e.g. grb".to_string()),
+        }
+    }
+}
+
+/// A pixel of a device with a separate white LED.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PixelRGBW {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub w: u8,
+}
+
+/// WhiteMode determines how the white channel is extracted from an RGB color.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WhiteMode {
+    /// The white LED is not used.
+    None,
+    /// The part of the color that all channels have in common is moved to the white LED.
+    Min,
+    /// Like Min, but takes the color of the white LED into account. Its value is the RGB color
+    /// that matches the white LED at full brightness.
+    Accurate([u8; 3]),
+}
+
+impl WhiteMode {
+    pub fn extract(self, pix: Pixel) -> PixelRGBW {
+        let white_point = match self {
+            WhiteMode::None => {
+                return PixelRGBW {
+                    r: pix.r,
+                    g: pix.g,
+                    b: pix.b,
+                    w: 0,
+                }
+            }
+            WhiteMode::Min => [255; 3],
+            WhiteMode::Accurate(white_point) => white_point,
+        };
+        let rgb = [pix.r, pix.g, pix.b].map(u32::from);
+        let white_point = white_point.map(u32::from);
+        // The brightest white that does not exceed any of the channels.
+        let w = rgb
+            .iter()
+            .zip(white_point)
+            .filter(|(_, wp)| *wp > 0)
+            .map(|(&c, wp)| c * 255 / wp)
+            .min()
+            .unwrap_or(0)
+            .min(255);
+        let [r, g, b] =
+            [0, 1, 2].map(|i| (rgb[i] - (w * white_point[i]).div_ceil(255).min(rgb[i])) as u8);
+        PixelRGBW {
+            r,
+            g,
+            b,
+            w: w as u8,
+        }
+    }
+}
+
 pub struct Correction {
     r: [u8; 256],
     g: [u8; 256],
     b: [u8; 256],
+    // Only used by devices with a separate white channel. If unset, white values are passed
+    // through as-is.
+    w: Option<[u8; 256]>,
+    // The RGB curves with 8 fractional bits, which is the precision used for dithering.
+    fine: [[u16; 256]; 3],
 }
 
 fn collect_u8(bytes: impl Iterator<Item = u8>) -> [u8; 256] {
@@ -19,32 +197,191 @@ fn collect_u8(bytes: impl Iterator<Item = u8>) -> [u8; 256] {
     buf
 }
 
+fn collect_u16(values: impl Iterator<Item = u16>) -> [u16; 256] {
+    let mut buf = [0; 256];
+    for (i, v) in values.enumerate() {
+        buf[i] = v;
+    }
+    buf
+}
+
+/// Approximates the RGB color of a black body at a temperature in Kelvin, with each channel in the
+/// range 0.0-1.0.
+///
+/// https://tannerhelland.com/2012/09/18/convert-temperature-rgb-algorithm-code.html
+fn blackbody(kelvin: f64) -> [f64; 3] {
+    let t = kelvin.clamp(1000.0, 40000.0) / 100.0;
+    let r = if t <= 66.0 {
+        255.0
+    } else {
+        329.698_727_446 * (t - 60.0).powf(-0.133_204_759_2)
+    };
+    let g = if t <= 66.0 {
+        99.470_802_586_1 * t.ln() - 161.119_568_166_1
+    } else {
+        288.122_169_528_3 * (t - 60.0).powf(-0.075_514_849_2)
+    };
+    let b = if t >= 66.0 {
+        255.0
+    } else if t <= 19.0 {
+        0.0
+    } else {
+        138.517_731_223_1 * (t - 10.0).ln() - 305.044_792_730_7
+    };
+    [r, g, b].map(|c| c.clamp(0.0, 255.0) / 255.0)
+}
+
+/// Quantizes a value with 8 fractional bits and carries the rounding error over to the next call.
+fn dither_channel(fine: u16, error: &mut i16) -> u8 {
+    let target = i32::from(fine) + i32::from(*error);
+    let out = ((target + 0x80) >> 8).clamp(0, 255);
+    *error = (target - (out << 8)) as i16;
+    out as u8
+}
+
 impl Correction {
     pub fn none() -> Correction {
         Correction {
             r: collect_u8(0..=255),
             g: collect_u8(0..=255),
             b: collect_u8(0..=255),
+            w: None,
+            fine: [collect_u16((0..=255).map(|i| i << 8)); 3],
         }
     }
 
     // https://en.wikipedia.org/wiki/SRGB
     pub fn srgb(max_red: u8, max_green: u8, max_blue: u8) -> Correction {
-        let srgb = |x: f64| -> f64 {
-            if x <= 0.04045 {
-                return x / 12.92;
+        CorrectionBuilder::from(Correction::none())
+            .srgb()
+            .scale([max_red, max_green, max_blue].map(|max| f64::from(max) / 255.0))
+            .build()
+    }
+
+    /// Applies a plain power curve with the specified exponent.
+    pub fn gamma(max_red: u8, max_green: u8, max_blue: u8, gamma: f64) -> Correction {
+        CorrectionBuilder::from(Correction::none())
+            .gamma(gamma)
+            .scale([max_red, max_green, max_blue].map(|max| f64::from(max) / 255.0))
+            .build()
+    }
+
+    /// Builds a curve by linearly interpolating between control points, which are pairs of input
+    /// and output values. The same curve is used for all channels.
+    ///
+    /// The points must start at input 0, end at input 255 and be increasing in both input and
+    /// output.
+    pub fn from_points(points: &[(u8, u8)]) -> Result<Correction, String> {
+        match (points.first(), points.last()) {
+            (Some((0, _)), Some((255, _))) => (),
+            _ => return Err("the first point must be at 0 and the last at 255".to_string()),
+        }
+        for w in points.windows(2) {
+            if w[0].0 >= w[1].0 {
+                return Err("the points must be sorted by input".to_string());
             }
-            f64::powf((x + 0.055) / (1.0 + 0.055), 2.4)
+            if w[0].1 > w[1].1 {
+                return Err("the output of the points must not decrease".to_string());
+            }
+        }
+        // Interpolates with the specified number of fractional bits.
+        let interpolate = |bits: u32| {
+            points.windows(2).flat_map(move |w| {
+                let ((x0, y0), (x1, y1)) = (w[0], w[1]);
+                let (x0, x1) = (u32::from(x0), u32::from(x1));
+                let (y0, y1) = (u32::from(y0) << bits, u32::from(y1) << bits);
+                // The end of each segment is the start of the next one, except for the last.
+                let end = if x1 == 255 { x1 + 1 } else { x1 };
+                (x0..end).map(move |x| y0 + ((y1 - y0) * (x - x0) + (x1 - x0) / 2) / (x1 - x0))
+            })
         };
-        let comp = |max| {
-            let iter =
-                (0..256).map(|i| f64::round(srgb(f64::from(i) / 255.0) * f64::from(max)) as u8);
-            collect_u8(iter)
+        let table = collect_u8(interpolate(0).map(|v| v as u8));
+        Ok(Correction {
+            r: table,
+            g: table,
+            b: table,
+            w: None,
+            fine: [collect_u16(interpolate(8).map(|v| v as u16)); 3],
+        })
+    }
+
+    /// Lifts the black level so input 0 maps to `level`.
+    ///
+    /// The range of each channel is compressed to fit between `level` and its maximum so the curve
+    /// stays monotonic.
+    pub fn with_black_level(self, level: u8) -> Correction {
+        let lift = |table: [u8; 256]| {
+            let max = u32::from(table[255]);
+            let level = u32::from(level).min(max);
+            if max == 0 {
+                return table;
+            }
+            collect_u8(
+                table
+                    .iter()
+                    .map(|&v| (level + (u32::from(v) * (max - level) + max / 2) / max) as u8),
+            )
+        };
+        let lift_fine = |table: [u16; 256]| {
+            let max = u32::from(table[255]);
+            let level = (u32::from(level) << 8).min(max);
+            if max == 0 {
+                return table;
+            }
+            collect_u16(
+                table
+                    .iter()
+                    .map(|&v| (level + (u32::from(v) * (max - level) + max / 2) / max) as u16),
+            )
+        };
+        Correction {
+            r: lift(self.r),
+            g: lift(self.g),
+            b: lift(self.b),
+            w: self.w.map(lift),
+            fine: self.fine.map(lift_fine),
+        }
+    }
+
+    /// Clips all output values below `level` to 0.
+    pub fn with_black_clip(self, level: u8) -> Correction {
+        let clip =
+            |table: [u8; 256]| collect_u8(table.iter().map(|&v| if v < level { 0 } else { v }));
+        // Values that are rounded below the level are clipped too, so dithering does not light up
+        // the clipped range.
+        let clip_fine = |table: [u16; 256]| {
+            collect_u16(table.iter().map(|&v| {
+                if (v + 0x80) >> 8 < u16::from(level) {
+                    0
+                } else {
+                    v
+                }
+            }))
         };
         Correction {
-            r: comp(max_red),
-            g: comp(max_green),
-            b: comp(max_blue),
+            r: clip(self.r),
+            g: clip(self.g),
+            b: clip(self.b),
+            w: self.w.map(clip),
+            fine: self.fine.map(clip_fine),
+        }
+    }
+
+    /// Sets a power curve for the white channel of RGBW devices.
+    pub fn with_white_gamma(self, max_white: u8, gamma: f64) -> Correction {
+        let iter = (0..256).map(|i| {
+            f64::round(f64::powf(f64::from(i) / 255.0, gamma) * f64::from(max_white)) as u8
+        });
+        Correction {
+            w: Some(collect_u8(iter)),
+            ..self
+        }
+    }
+
+    pub fn correct_white(&self, white: u8) -> u8 {
+        match &self.w {
+            Some(w) => w[white as usize],
+            None => white,
         }
     }
 
@@ -55,4 +392,530 @@ impl Correction {
             b: self.b[pix.b as usize],
         }
     }
+
+    /// Maps a pixel to the light emitted by each channel, with 8 fractional bits.
+    pub fn linear(&self, pix: Pixel) -> [u16; 3] {
+        let [r, g, b] = &self.fine;
+        [r[pix.r as usize], g[pix.g as usize], b[pix.b as usize]]
+    }
+
+    /// The inverse of `linear`. Finds the pixel that comes closest to the light of each channel,
+    /// assuming that the curves do not decrease.
+    pub fn invert_linear(&self, light: [u16; 3]) -> Pixel {
+        let [r, g, b] = [0, 1, 2].map(|c| {
+            let (curve, light) = (&self.fine[c], light[c]);
+            match curve.partition_point(|&v| v < light) {
+                0 => 0,
+                256 => 255,
+                i if curve[i] - light < light - curve[i - 1] => i as u8,
+                i => (i - 1) as u8,
+            }
+        });
+        Pixel { r, g, b }
+    }
+
+    /// Like `correct`, but the rounding error of each channel is carried over to the next frame.
+    ///
+    /// Averaged over time, the output approaches the curve more closely than 8 bits allow.
+    /// Values that can be represented exactly always produce the same output.
+    pub fn correct_dithered(&self, pix: Pixel, error: &mut (i16, i16, i16)) -> Pixel {
+        let [r, g, b] = &self.fine;
+        Pixel {
+            r: dither_channel(r[pix.r as usize], &mut error.0),
+            g: dither_channel(g[pix.g as usize], &mut error.1),
+            b: dither_channel(b[pix.b as usize], &mut error.2),
+        }
+    }
+}
+
+/// CorrectionBuilder composes a Correction from a sequence of steps.
+///
+/// Each step transforms the output of the steps before it, so the order matters. For example,
+/// dimming after a gamma curve scales the final brightness, while dimming before it would darken
+/// the dim colors more than the bright ones. The curves are kept at full precision until `build`.
+pub struct CorrectionBuilder {
+    // The output of each channel for every input value in the range 0.0-1.0.
+    curves: [[f64; 256]; 3],
+    w: Option<[u8; 256]>,
+}
+
+impl From<Correction> for CorrectionBuilder {
+    fn from(correction: Correction) -> CorrectionBuilder {
+        CorrectionBuilder {
+            curves: correction
+                .fine
+                .map(|table| table.map(|v| f64::from(v) / (255.0 * 256.0))),
+            w: correction.w,
+        }
+    }
+}
+
+impl CorrectionBuilder {
+    fn map(self, f: impl Fn(usize, f64) -> f64) -> CorrectionBuilder {
+        let mut curves = self.curves;
+        for (channel, curve) in curves.iter_mut().enumerate() {
+            for v in curve.iter_mut() {
+                *v = f(channel, *v).clamp(0.0, 1.0);
+            }
+        }
+        CorrectionBuilder { curves, ..self }
+    }
+
+    // https://en.wikipedia.org/wiki/SRGB
+    pub fn srgb(self) -> CorrectionBuilder {
+        self.map(|_, x| {
+            if x <= 0.04045 {
+                return x / 12.92;
+            }
+            f64::powf((x + 0.055) / (1.0 + 0.055), 2.4)
+        })
+    }
+
+    /// Applies a plain power curve with the specified exponent.
+    pub fn gamma(self, gamma: f64) -> CorrectionBuilder {
+        self.map(|_, x| f64::powf(x, gamma))
+    }
+
+    /// Multiplies each channel by a factor.
+    fn scale(self, factors: [f64; 3]) -> CorrectionBuilder {
+        self.map(|channel, x| x * factors[channel])
+    }
+
+    /// Scales the channels to shift the white point to the color of a black body at the specified
+    /// temperature in Kelvin. 6500K is neutral, lower temperatures are warmer.
+    pub fn white_balance(self, kelvin: f64) -> CorrectionBuilder {
+        let reference = blackbody(6500.0);
+        let target = blackbody(kelvin);
+        let factors = [0, 1, 2].map(|i| target[i] / reference[i]);
+        // Never scale a channel up, as that would clip the brightest colors.
+        let max = factors.iter().copied().fold(f64::MIN, f64::max);
+        self.scale(factors.map(|f| f / max))
+    }
+
+    /// Caps the output of each channel at a ceiling.
+    pub fn channel_max(self, max_red: u8, max_green: u8, max_blue: u8) -> CorrectionBuilder {
+        let max = [max_red, max_green, max_blue].map(|max| f64::from(max) / 255.0);
+        self.map(|channel, x| x.min(max[channel]))
+    }
+
+    /// Scales the brightness of all channels by a level in the range 0.0-1.0.
+    pub fn dim(self, level: f64) -> CorrectionBuilder {
+        self.scale([level; 3])
+    }
+
+    pub fn build(self) -> Correction {
+        let [r, g, b] = self
+            .curves
+            .map(|curve| collect_u8(curve.iter().map(|&v| f64::round(v * 255.0) as u8)));
+        Correction {
+            r,
+            g,
+            b,
+            w: self.w,
+            fine: self.curves.map(|curve| {
+                collect_u16(curve.iter().map(|&v| f64::round(v * 255.0 * 256.0) as u16))
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn white_balance() {
+        let neutral = CorrectionBuilder::from(Correction::none())
+            .white_balance(6500.0)
+            .build();
+        for i in [0, 1, 128, 255] {
+            let pix = Pixel { r: i, g: i, b: i };
+            assert_eq!(pix, neutral.correct(pix));
+        }
+
+        let white = Pixel {
+            r: 255,
+            g: 255,
+            b: 255,
+        };
+        let warm = CorrectionBuilder::from(Correction::none())
+            .white_balance(3000.0)
+            .build()
+            .correct(white);
+        assert_eq!(255, warm.r);
+        assert!(warm.g < 255 && warm.b < warm.g, "{:?}", warm);
+        let cool = CorrectionBuilder::from(Correction::none())
+            .white_balance(10000.0)
+            .build()
+            .correct(white);
+        assert_eq!(255, cool.b);
+        assert!(cool.r < 255, "{:?}", cool);
+
+        // The fine tables used for dithering are scaled too.
+        let warm = CorrectionBuilder::from(Correction::srgb(255, 255, 255))
+            .white_balance(3000.0)
+            .build();
+        let rounded = (warm.fine[2][255] + 0x80) >> 8;
+        assert!(rounded.abs_diff(u16::from(warm.b[255])) <= 1);
+    }
+
+    #[test]
+    fn builder_order() {
+        let pix = Pixel {
+            r: 128,
+            g: 128,
+            b: 255,
+        };
+        // Dimming after the curve scales the final output linearly.
+        let c = CorrectionBuilder::from(Correction::none())
+            .gamma(2.2)
+            .dim(0.5)
+            .build();
+        // (128/255)^2.2 * 255 * 0.5 = 28.04
+        assert_eq!(28, c.correct(pix).r);
+        // Dimming before the curve darkens more.
+        let c = CorrectionBuilder::from(Correction::none())
+            .dim(0.5)
+            .gamma(2.2)
+            .build();
+        // (128/255 * 0.5)^2.2 * 255 = 12.18
+        assert_eq!(12, c.correct(pix).r);
+
+        // A ceiling is scaled down by dimming after it, but not before it.
+        let c = CorrectionBuilder::from(Correction::none())
+            .channel_max(255, 255, 200)
+            .dim(0.5)
+            .build();
+        assert_eq!(100, c.correct(pix).b);
+        let c = CorrectionBuilder::from(Correction::none())
+            .dim(0.5)
+            .channel_max(255, 255, 100)
+            .build();
+        assert_eq!(100, c.correct(pix).b);
+
+        // The curves of an existing correction are kept.
+        let c = CorrectionBuilder::from(Correction::gamma(255, 255, 255, 2.2)).build();
+        assert_eq!(Correction::gamma(255, 255, 255, 2.2).r, c.r);
+        assert_eq!(Correction::gamma(255, 255, 255, 2.2).fine, c.fine);
+    }
+
+    #[test]
+    fn color_order() {
+        let pix = Pixel { r: 1, g: 2, b: 3 };
+        assert_eq!(Ok(ColorOrder::GRB), "GRB".parse());
+        assert!("rgbw".parse::<ColorOrder>().is_err());
+        assert!("rrb".parse::<ColorOrder>().is_err());
+
+        // A GRB strip on an RGB device.
+        let remap = ColorOrder::GRB.relative_to(ColorOrder::RGB);
+        assert_eq!(Pixel { r: 2, g: 1, b: 3 }, remap.remap(pix));
+        // An RGB strip on a GRB device, which swaps the channels back.
+        let remap = ColorOrder::RGB.relative_to(ColorOrder::GRB);
+        let p = remap.remap(pix);
+        assert_eq!([1, 2, 3], [p.g, p.r, p.b]);
+        // A BRG strip on a GRB device.
+        let remap = "brg"
+            .parse::<ColorOrder>()
+            .unwrap()
+            .relative_to(ColorOrder::GRB);
+        let p = remap.remap(pix);
+        assert_eq!([3, 1, 2], [p.g, p.r, p.b]);
+        assert_eq!(
+            ColorOrder::RGB,
+            ColorOrder::GRB.relative_to(ColorOrder::GRB)
+        );
+    }
+
+    #[test]
+    fn gamma_curve() {
+        let c = Correction::gamma(255, 255, 128, 2.2);
+        assert_eq!(
+            Pixel { r: 0, g: 0, b: 0 },
+            c.correct(Pixel { r: 0, g: 0, b: 0 })
+        );
+        assert_eq!(
+            Pixel {
+                r: 255,
+                g: 255,
+                b: 128
+            },
+            c.correct(Pixel {
+                r: 255,
+                g: 255,
+                b: 255
+            })
+        );
+        // (128/255)^2.2 * 255 = 56.07
+        assert_eq!(56, c.correct(Pixel { r: 128, g: 0, b: 0 }).r);
+    }
+
+    #[test]
+    fn black_level_lift() {
+        let c = Correction::none().with_black_level(16);
+        assert_eq!(
+            Pixel {
+                r: 16,
+                g: 16,
+                b: 16
+            },
+            c.correct(Pixel { r: 0, g: 0, b: 0 })
+        );
+        assert_eq!(255, c.correct(Pixel { r: 255, g: 0, b: 0 }).r);
+        assert!(c.r.windows(2).all(|w| w[0] <= w[1]));
+
+        // The maximum of a channel is preserved.
+        let c = Correction::gamma(255, 255, 100, 2.2).with_black_level(10);
+        assert_eq!(10, c.correct(Pixel { r: 0, g: 0, b: 0 }).b);
+        assert_eq!(100, c.correct(Pixel { r: 0, g: 0, b: 255 }).b);
+        assert!(c.b.windows(2).all(|w| w[0] <= w[1]));
+    }
+
+    #[test]
+    fn black_level_clip() {
+        let c = Correction::none().with_black_clip(8);
+        assert_eq!(0, c.correct(Pixel { r: 7, g: 0, b: 0 }).r);
+        assert_eq!(8, c.correct(Pixel { r: 8, g: 0, b: 0 }).r);
+        assert_eq!(255, c.correct(Pixel { r: 255, g: 0, b: 0 }).r);
+        assert!(c.r.windows(2).all(|w| w[0] <= w[1]));
+    }
+
+    #[test]
+    fn white_gamma() {
+        let c = Correction::none();
+        assert_eq!(77, c.correct_white(77));
+
+        let c = Correction::srgb(255, 255, 255).with_white_gamma(200, 2.0);
+        assert_eq!(0, c.correct_white(0));
+        assert_eq!(200, c.correct_white(255));
+        // (128/255)^2 * 200 = 50.39
+        assert_eq!(50, c.correct_white(128));
+        // The RGB channels are left alone.
+        assert_eq!(
+            Correction::srgb(255, 255, 255).correct(Pixel { r: 128, g: 0, b: 0 }),
+            c.correct(Pixel { r: 128, g: 0, b: 0 })
+        );
+    }
+
+    #[test]
+    fn channel_max() {
+        let c = CorrectionBuilder::from(Correction::none())
+            .channel_max(255, 255, 200)
+            .build();
+        assert_eq!(
+            Pixel {
+                r: 255,
+                g: 255,
+                b: 200
+            },
+            c.correct(Pixel {
+                r: 255,
+                g: 255,
+                b: 255
+            })
+        );
+        assert_eq!(
+            Pixel {
+                r: 100,
+                g: 150,
+                b: 199
+            },
+            c.correct(Pixel {
+                r: 100,
+                g: 150,
+                b: 199
+            })
+        );
+    }
+
+    #[test]
+    fn curve_from_points() {
+        let c = Correction::from_points(&[(0, 0), (128, 40), (255, 255)]).unwrap();
+        assert_eq!(0, c.r[0]);
+        assert_eq!(20, c.r[64]);
+        assert_eq!(40, c.r[128]);
+        // 40 + 215 * 64 / 127 = 148.35
+        assert_eq!(148, c.r[192]);
+        assert_eq!(255, c.r[255]);
+        assert_eq!(c.r, c.b);
+        assert!(c.r.windows(2).all(|w| w[0] <= w[1]));
+
+        let c = Correction::from_points(&[(0, 0), (255, 255)]).unwrap();
+        assert_eq!(Correction::none().r, c.r);
+    }
+
+    #[test]
+    fn linear_round_trip() {
+        let correction = Correction::srgb(255, 255, 255);
+        for v in (0..=255).step_by(5) {
+            let pix = Pixel { r: v, g: v, b: v };
+            assert_eq!(pix, correction.invert_linear(correction.linear(pix)));
+        }
+        // Values between two steps of the curve round to the nearest one.
+        let [lo, hi] = [10, 11].map(|v| correction.linear(Pixel { r: v, g: v, b: v })[0]);
+        let light = [lo + (hi - lo) / 4, hi - (hi - lo) / 4, u16::MAX];
+        assert_eq!(
+            Pixel {
+                r: 10,
+                g: 11,
+                b: 255
+            },
+            correction.invert_linear(light)
+        );
+    }
+
+    #[test]
+    fn dithering() {
+        let c = CorrectionBuilder::from(Correction::gamma(255, 255, 255, 2.2))
+            .channel_max(255, 255, 128)
+            .build()
+            .with_black_level(4);
+        for v in [0, 1, 10, 64, 128, 200, 255] {
+            let pix = Pixel { r: v, g: v, b: v };
+            // The dithered output averages out to the fine curve and never strays more than a
+            // single step from the regular output.
+            let mut error = (0, 0, 0);
+            let mut sum = [0u32; 3];
+            for _ in 0..256 {
+                let out = c.correct_dithered(pix, &mut error);
+                let reference = c.correct(pix);
+                assert!(out.r.abs_diff(reference.r) <= 1);
+                sum[0] += u32::from(out.r);
+                sum[2] += u32::from(out.b);
+            }
+            assert!(sum[0].abs_diff(c.fine[0][v as usize].into()) <= 256);
+            assert!(sum[2].abs_diff(c.fine[2][v as usize].into()) <= 256);
+            assert!(sum[2] <= 128 * 256);
+        }
+
+        // Values that are exact do not flicker.
+        let c = Correction::none();
+        let pix = Pixel { r: 7, g: 8, b: 9 };
+        let mut error = (0, 0, 0);
+        for _ in 0..16 {
+            assert_eq!(pix, c.correct_dithered(pix, &mut error));
+        }
+        assert_eq!((0, 0, 0), error);
+    }
+
+    #[test]
+    fn curve_from_invalid_points() {
+        assert!(Correction::from_points(&[]).is_err());
+        assert!(Correction::from_points(&[(0, 0)]).is_err());
+        assert!(Correction::from_points(&[(1, 0), (255, 255)]).is_err());
+        assert!(Correction::from_points(&[(0, 0), (200, 255)]).is_err());
+        assert!(Correction::from_points(&[(0, 0), (128, 40), (64, 20), (255, 255)]).is_err());
+        assert!(Correction::from_points(&[(0, 0), (128, 40), (128, 50), (255, 255)]).is_err());
+        assert!(Correction::from_points(&[(0, 100), (128, 40), (255, 255)]).is_err());
+    }
+
+    #[test]
+    fn hsv_round_trip() {
+        for (r, g, b) in [
+            (0, 0, 0),
+            (255, 255, 255),
+            (255, 0, 0),
+            (12, 200, 99),
+            (1, 2, 3),
+        ] {
+            let pix = Pixel { r, g, b };
+            let (h, s, v) = pix.to_hsv();
+            assert_eq!(pix, Pixel::from_hsv(h, s, v));
+        }
+        assert_eq!((120.0, 1.0, 1.0), Pixel { r: 0, g: 255, b: 0 }.to_hsv());
+    }
+
+    #[test]
+    fn hsv_adjust() {
+        let red = Pixel { r: 255, g: 0, b: 0 };
+        let shift = |hue_shift| HsvAdjust {
+            hue_shift,
+            saturation: 1.0,
+            value: 1.0,
+        };
+        assert_eq!(Pixel { r: 0, g: 255, b: 0 }, shift(120.0).apply(red));
+        // The hue wraps around in both directions.
+        assert_eq!(Pixel { r: 0, g: 0, b: 255 }, shift(-120.0).apply(red));
+        assert_eq!(Pixel { r: 0, g: 0, b: 255 }, shift(600.0).apply(red));
+        assert_eq!(red, shift(360.0).apply(red));
+
+        let gray = HsvAdjust {
+            hue_shift: 0.0,
+            saturation: 0.0,
+            value: 1.0,
+        };
+        assert_eq!(
+            Pixel {
+                r: 200,
+                g: 200,
+                b: 200
+            },
+            gray.apply(Pixel {
+                r: 200,
+                g: 100,
+                b: 50
+            })
+        );
+        // Factors above 1 saturate instead of overflowing.
+        let boost = HsvAdjust {
+            hue_shift: 0.0,
+            saturation: 4.0,
+            value: 4.0,
+        };
+        assert_eq!(
+            Pixel { r: 255, g: 0, b: 0 },
+            boost.apply(Pixel {
+                r: 100,
+                g: 50,
+                b: 50
+            })
+        );
+    }
+
+    #[test]
+    fn white_extraction() {
+        let pix = Pixel {
+            r: 200,
+            g: 100,
+            b: 50,
+        };
+        assert_eq!(
+            PixelRGBW {
+                r: 200,
+                g: 100,
+                b: 50,
+                w: 0
+            },
+            WhiteMode::None.extract(pix)
+        );
+        assert_eq!(
+            PixelRGBW {
+                r: 150,
+                g: 50,
+                b: 0,
+                w: 50
+            },
+            WhiteMode::Min.extract(pix)
+        );
+        // With a white LED that matches full RGB, accurate is the same as min.
+        assert_eq!(
+            WhiteMode::Min.extract(pix),
+            WhiteMode::Accurate([255, 255, 255]).extract(pix)
+        );
+        // A warm white LED takes over all of the red, the green and blue it lacks are left.
+        let warm = WhiteMode::Accurate([255, 200, 150]);
+        assert_eq!(
+            PixelRGBW {
+                r: 0,
+                g: 55,
+                b: 105,
+                w: 255
+            },
+            warm.extract(Pixel {
+                r: 255,
+                g: 255,
+                b: 255
+            })
+        );
+        assert_eq!(PixelRGBW::default(), warm.extract(Pixel::default()));
+    }
 }
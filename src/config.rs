@@ -0,0 +1,144 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Finds the value of --config in the raw program arguments. The config file has to be read before
+/// the arguments are parsed, because it changes the defaults of the other options.
+pub fn path_from_args(args: impl IntoIterator<Item = String>) -> Option<String> {
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        } else if arg == "--config" {
+            return args.next();
+        } else if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(path.to_string());
+        }
+    }
+    None
+}
+
+/// Loads a TOML file and uses its values as the defaults of the options of the command.
+///
+/// Top level keys are the long names of the global options. Options of a device go in a table
+/// named after its subcommand. Options set on the command line take precedence over the file,
+/// which in turn takes precedence over the built-in defaults.
+pub fn load(cli: clap::Command, path: impl AsRef<Path>) -> io::Result<clap::Command> {
+    let table: toml::Table = fs::read_to_string(path)?
+        .parse()
+        .map_err(io::Error::other)?;
+    apply(cli, &table)
+}
+
+fn apply(mut cli: clap::Command, table: &toml::Table) -> io::Result<clap::Command> {
+    for (key, value) in table {
+        if let toml::Value::Table(sub_table) = value {
+            let Some(sub) = cli.find_subcommand(key) else {
+                return Err(io::Error::other(format!("unknown device {:?}", key)));
+            };
+            let sub = apply(sub.clone(), sub_table)?;
+            cli = cli.mut_subcommand(key, |_| sub);
+            continue;
+        }
+        if !cli.get_arguments().any(|arg| arg.get_id() == key.as_str()) {
+            return Err(io::Error::other(format!("unknown option {:?}", key)));
+        }
+        let values = match value {
+            toml::Value::Array(values) => values
+                .iter()
+                .map(|v| scalar_to_string(key, v))
+                .collect::<io::Result<Vec<_>>>()?,
+            v => vec![scalar_to_string(key, v)?],
+        };
+        // A value from the file satisfies options that are otherwise required.
+        cli = cli.mut_arg(key, |arg| arg.default_values(values).required(false));
+    }
+    Ok(cli)
+}
+
+fn scalar_to_string(key: &str, value: &toml::Value) -> io::Result<String> {
+    match value {
+        toml::Value::String(s) => Ok(s.clone()),
+        toml::Value::Integer(i) => Ok(i.to_string()),
+        toml::Value::Float(f) => Ok(f.to_string()),
+        toml::Value::Boolean(b) => Ok(b.to_string()),
+        _ => Err(io::Error::other(format!(
+            "unsupported value for {:?}, expected a string, number, boolean or a list of those",
+            key
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command() -> clap::Command {
+        clap::Command::new("ledcat")
+            .arg(clap::arg!(-g --geometry <value>))
+            .arg(clap::arg!(-i --input <file> ...).default_value("-"))
+            .arg(clap::arg!(--stats))
+            .subcommand(
+                clap::Command::new("pwm")
+                    .arg(clap::arg!(--channel <n> ...).required(true))
+                    .arg(clap::arg!(--chip <n>).default_value("0")),
+            )
+    }
+
+    #[test]
+    fn precedence() {
+        let table: toml::Table = r#"
+            geometry = "16x8"
+            input = ["a", "b"]
+            stats = true
+            [pwm]
+            channel = [0, 1, 2]
+        "#
+        .parse()
+        .unwrap();
+        let cli = apply(command(), &table).unwrap();
+
+        let matches =
+            cli.clone()
+                .get_matches_from(["ledcat", "--geometry", "4", "pwm", "--chip", "1"]);
+        assert_eq!("4", matches.get_one::<String>("geometry").unwrap());
+        let inputs: Vec<_> = matches.get_many::<String>("input").unwrap().collect();
+        assert_eq!(vec!["a", "b"], inputs);
+        assert!(matches.get_flag("stats"));
+        let (_, sub) = matches.subcommand().unwrap();
+        let channels: Vec<_> = sub.get_many::<String>("channel").unwrap().collect();
+        assert_eq!(vec!["0", "1", "2"], channels);
+        assert_eq!("1", sub.get_one::<String>("chip").unwrap());
+
+        let matches = cli.get_matches_from(["ledcat", "--input", "c", "pwm"]);
+        let inputs: Vec<_> = matches.get_many::<String>("input").unwrap().collect();
+        assert_eq!(vec!["c"], inputs);
+    }
+
+    #[test]
+    fn unknown_keys() {
+        for text in [
+            "foo = 1",
+            "[foo]\nbar = 1",
+            "[pwm]\nbar = 1",
+            "geometry = {}",
+        ] {
+            let table: toml::Table = text.parse().unwrap();
+            assert!(apply(command(), &table).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn find_path() {
+        let args = |a: &[&str]| a.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            Some("a.toml".to_string()),
+            path_from_args(args(&["-g", "3", "--config", "a.toml", "show"]))
+        );
+        assert_eq!(
+            Some("b.toml".to_string()),
+            path_from_args(args(&["--config=b.toml"]))
+        );
+        assert_eq!(None, path_from_args(args(&["--", "--config", "a.toml"])));
+    }
+}
@@ -1,5 +1,6 @@
 use crate::device::*;
 use std::collections;
+use std::fs;
 use std::io::{self, Write};
 use std::net;
 use std::sync;
@@ -16,21 +17,31 @@ pub fn command() -> clap::Command {
         .about("Control artnet DMX nodes via unicast and broadcast")
         .arg(clap::arg!(-t --target <value> ... "One or more target IP addresses")
             .value_parser(clap::value_parser!(net::IpAddr))
-            .conflicts_with_all(["discover", "target-list", "broadcast"]))
+            .conflicts_with_all(["discover", "target-list", "target-map", "broadcast"]))
         .arg(clap::arg!(--"target-list" <file> "Specify a file containing 1 IP address per line to unicast to. Changes to the file are read automatically")
-            .conflicts_with_all(["target", "discover", "broadcast"]))
+            .conflicts_with_all(["target", "target-map", "discover", "broadcast"]))
+        .arg(clap::arg!(--"target-map" <file> "Specify a file with ip,universe,offset,count lines to send each node only its part of the frame, starting at its own universe")
+            .conflicts_with_all(["target", "target-list", "discover", "broadcast"]))
         .arg(clap::arg!(-b --broadcast "Broadcast to all devices in the network")
-            .conflicts_with_all(["target", "target-list", "discover"]))
+            .conflicts_with_all(["target", "target-list", "target-map", "discover"]))
         .arg(clap::arg!(-d --discover "Discover artnet nodes")
-            .conflicts_with_all(["target", "target-list", "broadcast"]))
-        .arg(clap::arg!(-u --universe <value> "Discover artnet nodes")
+            .conflicts_with_all(["target", "target-list", "target-map", "broadcast"]))
+        .arg(clap::arg!(-u --universe <value> "The universe to send to. Frames that do not fit are continued in the next universes")
             .value_parser(clap::value_parser!(u16))
             .default_value("0"))
+        .arg(clap::arg!(--"channels-per-universe" <n> "The number of channels used in each universe. Use 510 to keep RGB pixels from being split across universes")
+            .alias("universe-size")
+            .value_parser(clap::value_parser!(u16).range(1..=UNIVERSE_SIZE as i64))
+            .default_value("512"))
+        .arg(clap::arg!(--"send-buffer" <bytes> "Set the size of the send buffer of the socket")
+            .value_parser(clap::value_parser!(usize)))
+        .arg(clap::arg!(--"packet-gap-us" <us> "Wait this many microseconds between sending packets to avoid flooding the network")
+            .value_parser(clap::value_parser!(u64)))
 }
 
 pub fn from_command(args: &clap::ArgMatches, gargs: &GlobalArgs) -> io::Result<FromCommand> {
     if args.get_flag("discover") {
-        if let Err(err) = artnet_discover() {
+        if let Err(err) = artnet_discover(gargs.verbosity) {
             eprintln!("{}", err);
         }
         return Ok(FromCommand::SubcommandHandled);
@@ -38,11 +49,27 @@ pub fn from_command(args: &clap::ArgMatches, gargs: &GlobalArgs) -> io::Result<F
 
     let dev = Box::new(generic::Generic {
         format: generic::Format::RGB24,
+        padding: generic::Padding::default(),
     });
+    let num_pixels = gargs.dimensions()?.size();
     let artnet_target: Box<dyn Target> = if args.get_flag("broadcast") {
         Box::new(Broadcast {})
     } else if let Some(list_path) = args.get_one::<String>("target-list") {
         Box::new(ListFile::new(list_path))
+    } else if let Some(map_path) = args.get_one::<String>("target-map") {
+        let segments = Segment::parse_map(io::BufReader::new(fs::File::open(map_path)?))?;
+        if let Some(seg) = segments
+            .iter()
+            .find(|seg| seg.offset + seg.count > num_pixels)
+        {
+            return Err(io::Error::other(format!(
+                "the segment of {} ends at pixel {}, but the display has {} pixels",
+                seg.addr,
+                seg.offset + seg.count,
+                num_pixels
+            )));
+        }
+        Box::new(segments)
     } else if let Some(targets) = args.get_many::<net::IpAddr>("target") {
         let addresses: Vec<_> = targets
             .map(|addr| net::SocketAddr::new(*addr, PORT))
@@ -53,13 +80,25 @@ pub fn from_command(args: &clap::ArgMatches, gargs: &GlobalArgs) -> io::Result<F
         return Ok(FromCommand::SubcommandHandled);
     };
     let universe = args.get_one::<u16>("universe").unwrap();
+    let channels_per_universe = args.get_one::<u16>("channels-per-universe").unwrap();
 
-    let output = Unicast::to(artnet_target, gargs.dimensions()?.size() * 3, *universe)?;
+    let mut output = Unicast::to(
+        artnet_target,
+        num_pixels * 3,
+        *universe,
+        *channels_per_universe as usize,
+    )?;
+    if let Some(size) = args.get_one::<usize>("send-buffer") {
+        output = output.with_send_buffer(*size)?;
+    }
+    if let Some(us) = args.get_one::<u64>("packet-gap-us") {
+        output = output.with_packet_gap(time::Duration::from_micros(*us));
+    }
     Ok(FromCommand::Output(Box::new((dev, output))))
 }
 
-fn artnet_discover() -> io::Result<()> {
-    let discovery_stream = unicast::discover();
+fn artnet_discover(verbosity: u8) -> io::Result<()> {
+    let discovery_stream = unicast::discover(verbosity);
     let mut discovered: collections::HashSet<net::SocketAddr> = collections::HashSet::new();
 
     let (close_tx, close_rx) = sync::mpsc::sync_channel(0);
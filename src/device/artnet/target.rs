@@ -8,11 +8,92 @@ use std::thread;
 use std::time;
 
 pub trait Target: Send {
-    fn addresses(&self) -> Cow<[net::SocketAddr]>;
+    fn addresses(&self) -> Cow<'_, [net::SocketAddr]>;
+
+    /// The part of the frame that each address should receive. If None, every address receives
+    /// the full frame.
+    fn segments(&self) -> Option<Cow<'_, [Segment]>> {
+        None
+    }
+}
+
+/// A range of pixels that is sent to a single node, starting at its own universe.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Segment {
+    pub addr: net::SocketAddr,
+    pub universe: u16,
+    /// The index of the first pixel.
+    pub offset: usize,
+    /// The number of pixels.
+    pub count: usize,
+}
+
+impl Segment {
+    /// Returns the RGB24 channels of the pixels in the segment.
+    pub fn slice<'a>(&self, frame: &'a [u8]) -> &'a [u8] {
+        &frame[self.offset * 3..(self.offset + self.count) * 3]
+    }
+
+    /// Reads segments from lines formatted as `ip,universe,offset,count`. Empty lines and lines
+    /// starting with `#` are ignored.
+    pub fn parse_map(r: impl BufRead) -> io::Result<Vec<Segment>> {
+        let invalid = |lineno: usize, msg: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("target map line {}: {}", lineno + 1, msg),
+            )
+        };
+        let mut segments = Vec::new();
+        for (lineno, line) in r.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            let [addr, universe, offset, count] = fields[..] else {
+                return Err(invalid(lineno, "expected ip,universe,offset,count"));
+            };
+            let addr = addr
+                .parse()
+                .ok()
+                .or_else(|| {
+                    addr.parse()
+                        .ok()
+                        .map(|ip| net::SocketAddr::new(ip, super::PORT))
+                })
+                .ok_or_else(|| invalid(lineno, "invalid address"))?;
+            segments.push(Segment {
+                addr,
+                universe: universe
+                    .parse()
+                    .map_err(|_| invalid(lineno, "invalid universe"))?,
+                offset: offset
+                    .parse()
+                    .map_err(|_| invalid(lineno, "invalid offset"))?,
+                count: count
+                    .parse()
+                    .map_err(|_| invalid(lineno, "invalid count"))?,
+            });
+        }
+        Ok(segments)
+    }
+}
+
+impl Target for Vec<Segment> {
+    fn addresses(&self) -> Cow<'_, [net::SocketAddr]> {
+        let mut addrs: Vec<_> = self.iter().map(|seg| seg.addr).collect();
+        addrs.dedup();
+        Cow::Owned(addrs)
+    }
+
+    fn segments(&self) -> Option<Cow<'_, [Segment]>> {
+        Some(Cow::Borrowed(self))
+    }
 }
 
 impl Target for Vec<net::SocketAddr> {
-    fn addresses(&self) -> Cow<[net::SocketAddr]> {
+    fn addresses(&self) -> Cow<'_, [net::SocketAddr]> {
         Cow::Borrowed(self)
     }
 }
@@ -20,7 +101,7 @@ impl Target for Vec<net::SocketAddr> {
 pub struct Broadcast {}
 
 impl Target for Broadcast {
-    fn addresses(&self) -> Cow<[net::SocketAddr]> {
+    fn addresses(&self) -> Cow<'_, [net::SocketAddr]> {
         let ip = net::Ipv4Addr::new(255, 255, 255, 255);
         let addrs = vec![net::SocketAddrV4::new(ip, super::PORT).into()];
         Cow::Owned(addrs)
@@ -86,7 +167,49 @@ impl ListFile {
 }
 
 impl Target for ListFile {
-    fn addresses(&self) -> Cow<[net::SocketAddr]> {
+    fn addresses(&self) -> Cow<'_, [net::SocketAddr]> {
         Cow::Owned(self.cache.read().unwrap().clone())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_target_map() {
+        let map = "# ip,universe,offset,count\n\
+                   10.0.0.1,0,0,170\n\
+                   \n\
+                   10.0.0.2:6455, 4, 170, 100\n";
+        let segments = Segment::parse_map(map.as_bytes()).unwrap();
+        assert_eq!(
+            vec![
+                Segment {
+                    addr: "10.0.0.1:6454".parse().unwrap(),
+                    universe: 0,
+                    offset: 0,
+                    count: 170,
+                },
+                Segment {
+                    addr: "10.0.0.2:6455".parse().unwrap(),
+                    universe: 4,
+                    offset: 170,
+                    count: 100,
+                },
+            ],
+            segments
+        );
+
+        let frame: Vec<u8> = (0..10 * 3).map(|i| (i / 3) as u8).collect();
+        let seg = Segment {
+            offset: 2,
+            count: 3,
+            ..segments[0].clone()
+        };
+        assert_eq!(&[2, 2, 2, 3, 3, 3, 4, 4, 4], seg.slice(&frame));
+
+        let err = Segment::parse_map("10.0.0.1,0,0".as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("line 1"));
+    }
+}
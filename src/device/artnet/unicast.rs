@@ -1,7 +1,9 @@
 use super::target::*;
+use crate::util;
 use byteorder::{BigEndian, LittleEndian, ReadBytesExt, WriteBytesExt};
 use nix::sys::socket;
 use std::io;
+use std::mem;
 use std::net;
 use std::net::ToSocketAddrs;
 use std::os::fd::AsRawFd;
@@ -12,26 +14,62 @@ use std::time;
 
 pub const PORT: u16 = 6454;
 
+/// The maximum number of channels in a DMX universe.
+pub const UNIVERSE_SIZE: usize = 512;
+
 pub struct Unicast {
     socket: net::UdpSocket,
+    // Bound when the first IPv6 target is encountered.
+    socket_v6: Option<net::UdpSocket>,
     target: Box<dyn Target>,
     frame_size: usize,
     frame_buffer: Vec<u8>,
     universe: u16,
+    channels_per_universe: usize,
+    packet_gap: Option<time::Duration>,
 }
 
 impl Unicast {
-    pub fn to(target: Box<dyn Target>, frame_size: usize, universe: u16) -> io::Result<Unicast> {
+    /// Frames that do not fit in a single universe are spread over consecutive universes, starting
+    /// at `universe`.
+    pub fn to(
+        target: Box<dyn Target>,
+        frame_size: usize,
+        universe: u16,
+        channels_per_universe: usize,
+    ) -> io::Result<Unicast> {
+        assert!((1..=UNIVERSE_SIZE).contains(&channels_per_universe));
         let socket = reuse_bind(("0.0.0.0", PORT))?;
         socket.set_broadcast(true)?;
         Ok(Unicast {
             socket,
+            socket_v6: None,
             target,
             frame_size,
             frame_buffer: Vec::with_capacity(frame_size),
             universe,
+            channels_per_universe,
+            packet_gap: None,
         })
     }
+
+    /// Sets the size of the send buffer of the socket in bytes.
+    pub fn with_send_buffer(self, size: usize) -> io::Result<Unicast> {
+        io_err!(socket::setsockopt(
+            &self.socket,
+            socket::sockopt::SndBuf,
+            &size
+        ))?;
+        Ok(self)
+    }
+
+    /// Waits between sending packets so slow nodes and congested networks can keep up.
+    pub fn with_packet_gap(self, gap: time::Duration) -> Unicast {
+        Unicast {
+            packet_gap: Some(gap),
+            ..self
+        }
+    }
 }
 
 impl io::Write for Unicast {
@@ -46,17 +84,56 @@ impl io::Write for Unicast {
             return Ok(());
         }
         let new_buf = self.frame_buffer.split_off(self.frame_size);
-        let mut packet = Vec::new();
-        art_dmx_packet(&mut packet, &self.frame_buffer, self.universe)?;
-        self.frame_buffer = new_buf;
-        for addr in self.target.addresses().iter() {
-            self.socket.send_to(&packet, addr)?;
+        let frame = mem::replace(&mut self.frame_buffer, new_buf);
+        let sends = match self.target.segments() {
+            Some(segments) => segments
+                .iter()
+                .map(|seg| {
+                    let packets = art_dmx_packets(
+                        seg.slice(&frame),
+                        seg.universe,
+                        self.channels_per_universe,
+                    )?;
+                    Ok((packets, vec![seg.addr]))
+                })
+                .collect::<io::Result<Vec<_>>>()?,
+            None => vec![(
+                art_dmx_packets(&frame, self.universe, self.channels_per_universe)?,
+                self.target.addresses().into_owned(),
+            )],
+        };
+        let any_ipv6 = sends
+            .iter()
+            .any(|(_, addrs)| addrs.iter().any(|addr| addr.is_ipv6()));
+        if self.socket_v6.is_none() && any_ipv6 {
+            self.socket_v6 = Some(reuse_bind(("::", PORT))?);
+        }
+        let mut first = true;
+        for (packets, addresses) in &sends {
+            for packet in packets {
+                for addr in addresses {
+                    if let Some(gap) = self.packet_gap.filter(|_| !first) {
+                        thread::sleep(gap);
+                    }
+                    first = false;
+                    let socket = match self.socket_v6.as_ref() {
+                        Some(socket) if addr.is_ipv6() => socket,
+                        _ => &self.socket,
+                    };
+                    socket.send_to(packet, addr)?;
+                }
+            }
         }
         Ok(())
     }
 }
 
-pub fn discover() -> sync::mpsc::Receiver<io::Result<(net::SocketAddr, Option<String>)>> {
+/// Continuously polls the network for Art-Net nodes.
+///
+/// With a verbosity of 1 or higher, every received packet is dumped to stderr.
+pub fn discover(
+    verbosity: u8,
+) -> sync::mpsc::Receiver<io::Result<(net::SocketAddr, Option<String>)>> {
     let (tx, rx) = sync::mpsc::channel();
 
     thread::spawn(move || {
@@ -84,17 +161,31 @@ pub fn discover() -> sync::mpsc::Receiver<io::Result<(net::SocketAddr, Option<St
 
             loop {
                 let mut recv_buf = [0; 231];
-                let (_, sender_addr) = match socket.recv_from(&mut recv_buf) {
+                let (len, sender_addr) = match socket.recv_from(&mut recv_buf) {
                     Err(_) => break,
                     Ok(rs) => rs,
                 };
+                if verbosity >= 1 {
+                    eprint!(
+                        "\r{} bytes from {}:\n{}",
+                        len,
+                        sender_addr,
+                        util::hex_dump(&recv_buf[..len])
+                    );
+                }
                 if &recv_buf[0..8] != b"Art-Net\0" {
                     continue;
                 }
                 let mut rdr = io::Cursor::new(&recv_buf[8..10]);
                 let opcode = try_or_send!(rdr.read_u16::<LittleEndian>());
+                if verbosity >= 1 {
+                    eprintln!("\rOpCode: {:#06x}", opcode);
+                }
                 if opcode == 0x2100 {
                     let short_name = str::from_utf8(&recv_buf[19..38]).map(String::from).ok();
+                    if verbosity >= 1 {
+                        eprintln!("\rShortName: {:?}", short_name);
+                    }
                     tx.send(Ok((sender_addr, short_name))).unwrap();
                 }
             }
@@ -130,10 +221,7 @@ where
     W: io::Write,
 {
     if data.len() >= 0xffff {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            "data exceeds max dmx packet length",
-        ));
+        return Err(io::Error::other("data exceeds max dmx packet length"));
     }
     wr.write_all(b"Art-Net\0")?; // Artnet Header
     wr.write_u16::<LittleEndian>(0x5000)?; // OpCode
@@ -148,18 +236,35 @@ where
     Ok(())
 }
 
+/// Splits a frame into ArtDmx packets of at most `channels_per_universe` channels each, addressed
+/// to consecutive universes.
+fn art_dmx_packets(
+    frame: &[u8],
+    universe: u16,
+    channels_per_universe: usize,
+) -> io::Result<Vec<Vec<u8>>> {
+    frame
+        .chunks(channels_per_universe)
+        .enumerate()
+        .map(|(i, data)| {
+            let mut packet = Vec::new();
+            art_dmx_packet(&mut packet, data, universe.wrapping_add(i as u16))?;
+            Ok(packet)
+        })
+        .collect()
+}
+
 /// Like `UdpSocket::bind`, but sets the socket reuse flags before binding.
 fn reuse_bind(to_addr: impl net::ToSocketAddrs) -> io::Result<net::UdpSocket> {
     let addr = to_addr.to_socket_addrs()?.next().unwrap();
-    if addr.is_ipv6() {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            "Artnet does not support IPv6 :(",
-        ));
-    }
+    let family = if addr.is_ipv6() {
+        socket::AddressFamily::Inet6
+    } else {
+        socket::AddressFamily::Inet
+    };
 
     let fd = io_err!(socket::socket(
-        socket::AddressFamily::Inet,
+        family,
         socket::SockType::Datagram,
         socket::SockFlag::empty(),
         socket::SockProtocol::Udp,
@@ -173,3 +278,55 @@ fn reuse_bind(to_addr: impl net::ToSocketAddrs) -> io::Result<net::UdpSocket> {
 
     Ok(net::UdpSocket::from(fd))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pixels_stay_whole_within_universes() {
+        // 200 RGB pixels do not fit in a single universe.
+        let frame: Vec<u8> = (0..200 * 3).map(|i| (i / 3) as u8).collect();
+        let packets = art_dmx_packets(&frame, 3, 510).unwrap();
+        assert_eq!(2, packets.len());
+
+        let mut pixels = Vec::new();
+        for (i, packet) in packets.iter().enumerate() {
+            let (header, data) = packet.split_at(18);
+            assert_eq!(3 + i as u8, header[14]); // SubUni
+            assert_eq!(
+                data.len(),
+                u16::from_be_bytes([header[16], header[17]]) as usize
+            );
+            assert_eq!(0, data.len() % 3);
+            pixels.extend(data.chunks(3).map(|pix| {
+                assert!(pix.iter().all(|&c| c == pix[0]));
+                pix[0]
+            }));
+        }
+        assert_eq!(510, packets[0].len() - 18);
+        assert_eq!((0..200).map(|i| i as u8).collect::<Vec<_>>(), pixels);
+    }
+
+    #[test]
+    fn packet_gap() {
+        let receiver = net::UdpSocket::bind(("127.0.0.1", 0)).unwrap();
+        let target: Box<dyn Target> = Box::new(vec![receiver.local_addr().unwrap()]);
+        let gap = time::Duration::from_millis(20);
+        let mut unicast = Unicast::to(target, 4 * 3, 0, 3)
+            .unwrap()
+            .with_packet_gap(gap);
+
+        let start = time::Instant::now();
+        io::Write::write_all(&mut unicast, &[0; 4 * 3]).unwrap();
+        // 4 universes with a gap between each of them.
+        assert!(start.elapsed() >= gap * 3, "{:?}", start.elapsed());
+
+        let mut buf = [0; 64];
+        for i in 0..4 {
+            let len = receiver.recv(&mut buf).unwrap();
+            assert_eq!(18 + 3, len);
+            assert_eq!(i, buf[14]);
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use crate::device::*;
+use byteorder::{BigEndian, WriteBytesExt};
+use std::io;
+use std::net;
+
+/// The UDP port on which DDP displays listen.
+pub const PORT: u16 = 4048;
+/// The maximum number of data bytes in a single packet, which is exactly 480 RGB pixels.
+pub const MAX_DATA_LEN: usize = 1440;
+
+const FLAG_VERSION_1: u8 = 0x40;
+/// Set on the last packet of a frame to have the display show the data received so far.
+const FLAG_PUSH: u8 = 0x01;
+/// Data type of 8-bit RGB pixels.
+const TYPE_RGB24: u8 = 0x0b;
+
+fn ddp_packet<W>(
+    mut wr: W,
+    data: &[u8],
+    offset: u32,
+    sequence: u8,
+    id: u8,
+    push: bool,
+) -> io::Result<()>
+where
+    W: io::Write,
+{
+    assert!(data.len() <= MAX_DATA_LEN);
+    wr.write_u8(FLAG_VERSION_1 | if push { FLAG_PUSH } else { 0 })?; // Flags
+    wr.write_u8(sequence & 0x0f)?; // Sequence Number
+    wr.write_u8(TYPE_RGB24)?; // Data Type
+    wr.write_u8(id)?; // Destination ID
+    wr.write_u32::<BigEndian>(offset)?; // Data Offset
+    wr.write_u16::<BigEndian>(data.len() as u16)?; // Data Length
+    wr.write_all(data)?; // Data
+    Ok(())
+}
+
+/// Splits a frame into DDP packets with incrementing offsets. Only the last packet has the push
+/// flag set.
+fn ddp_packets(frame: &[u8], sequence: u8, id: u8) -> io::Result<Vec<Vec<u8>>> {
+    let num_packets = frame.len().div_ceil(MAX_DATA_LEN);
+    frame
+        .chunks(MAX_DATA_LEN)
+        .enumerate()
+        .map(|(i, data)| {
+            let mut packet = Vec::new();
+            let offset = (i * MAX_DATA_LEN) as u32;
+            ddp_packet(
+                &mut packet,
+                data,
+                offset,
+                sequence,
+                id,
+                i + 1 == num_packets,
+            )?;
+            Ok(packet)
+        })
+        .collect()
+}
+
+pub struct Ddp {
+    socket: net::UdpSocket,
+    target: net::SocketAddr,
+    id: u8,
+    frame_size: usize,
+    frame_buffer: Vec<u8>,
+    /// Cycles through 1-15, 0 means that sequence numbers are not used.
+    sequence: u8,
+}
+
+impl Ddp {
+    pub fn to(target: net::SocketAddr, id: u8, frame_size: usize) -> io::Result<Ddp> {
+        let socket = net::UdpSocket::bind(("0.0.0.0", 0))?;
+        Ok(Ddp {
+            socket,
+            target,
+            id,
+            frame_size,
+            frame_buffer: Vec::with_capacity(frame_size),
+            sequence: 1,
+        })
+    }
+}
+
+impl io::Write for Ddp {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.frame_buffer.write(buf)?;
+        self.flush()?;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.frame_buffer.len() < self.frame_size {
+            return Ok(());
+        }
+        let new_buf = self.frame_buffer.split_off(self.frame_size);
+        let packets = ddp_packets(&self.frame_buffer, self.sequence, self.id)?;
+        self.frame_buffer = new_buf;
+        self.sequence = self.sequence % 15 + 1;
+        for packet in &packets {
+            self.socket.send_to(packet, self.target)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn command() -> clap::Command {
+    clap::Command::new("ddp")
+        .about("Control displays using the Distributed Display Protocol, like WLED")
+        .arg(
+            clap::arg!(-t --target <ip> "The IP address of the display")
+                .value_parser(clap::value_parser!(net::IpAddr))
+                .required(true),
+        )
+        .arg(
+            clap::arg!(--id <value> "The ID of the output device of the display, 1 is the default output")
+                .value_parser(clap::value_parser!(u8))
+                .default_value("1"),
+        )
+}
+
+pub fn from_command(args: &clap::ArgMatches, gargs: &GlobalArgs) -> io::Result<FromCommand> {
+    let dev = Box::new(generic::Generic {
+        format: generic::Format::RGB24,
+        padding: generic::Padding::default(),
+    });
+    let target = net::SocketAddr::new(*args.get_one::<net::IpAddr>("target").unwrap(), PORT);
+    let id = *args.get_one::<u8>("id").unwrap();
+    let output = Ddp::to(target, id, gargs.dimensions()?.size() * 3)?;
+    Ok(FromCommand::Output(Box::new((dev, output))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn frames_span_packets() {
+        let frame: Vec<u8> = (0..1000 * 3).map(|i| i as u8).collect();
+        let packets = ddp_packets(&frame, 3, 1).unwrap();
+        assert_eq!(3, packets.len());
+
+        let mut data = Vec::new();
+        for (i, packet) in packets.iter().enumerate() {
+            let (header, payload) = packet.split_at(10);
+            let push = if i == 2 { FLAG_PUSH } else { 0 };
+            assert_eq!(FLAG_VERSION_1 | push, header[0]);
+            assert_eq!(3, header[1]);
+            assert_eq!(TYPE_RGB24, header[2]);
+            assert_eq!(1, header[3]);
+            let offset = u32::from_be_bytes(header[4..8].try_into().unwrap());
+            assert_eq!(data.len(), offset as usize);
+            let len = u16::from_be_bytes([header[8], header[9]]);
+            assert_eq!(payload.len(), len as usize);
+            data.extend_from_slice(payload);
+        }
+        assert_eq!(MAX_DATA_LEN, packets[0].len() - 10);
+        assert_eq!(frame, data);
+    }
+
+    #[test]
+    fn buffer_partial_frames() {
+        let receiver = net::UdpSocket::bind(("127.0.0.1", 0)).unwrap();
+        receiver
+            .set_read_timeout(Some(std::time::Duration::from_millis(100)))
+            .unwrap();
+        let mut ddp = Ddp::to(receiver.local_addr().unwrap(), 1, 6).unwrap();
+
+        let mut buf = [0; 64];
+        ddp.write_all(&[1, 2, 3, 4]).unwrap();
+        assert!(receiver.recv(&mut buf).is_err());
+        ddp.write_all(&[5, 6, 7]).unwrap();
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!([1, 2, 3, 4, 5, 6], buf[10..len]);
+        assert_eq!(1, buf[1]);
+        ddp.write_all(&[8, 9, 10, 11, 12]).unwrap();
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!([7, 8, 9, 10, 11, 12], buf[10..len]);
+        assert_eq!(2, buf[1]);
+    }
+}
@@ -0,0 +1,260 @@
+use crate::device::*;
+use crate::util;
+use byteorder::{BigEndian, WriteBytesExt};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net;
+
+/// The UDP port on which E1.31 receivers listen.
+pub const PORT: u16 = 5568;
+/// The maximum number of channels in a DMX universe.
+pub const UNIVERSE_SIZE: usize = 512;
+/// The highest universe number that may carry data.
+pub const MAX_UNIVERSE: u16 = 63999;
+
+/// The identifier of the ACN packet that precedes the root layer.
+const ACN_PACKET_IDENTIFIER: &[u8; 12] = b"ASC-E1.17\0\0\0";
+const VECTOR_ROOT_E131_DATA: u32 = 0x0000_0004;
+const VECTOR_E131_DATA_PACKET: u32 = 0x0000_0002;
+const VECTOR_DMP_SET_PROPERTY: u8 = 0x02;
+/// The high 4 bits of the flags and length fields that precede each layer.
+const PDU_FLAGS: u16 = 0x7000;
+
+/// Computes the multicast group receivers of a universe subscribe to.
+pub fn multicast_addr(universe: u16) -> net::Ipv4Addr {
+    let [hi, lo] = universe.to_be_bytes();
+    net::Ipv4Addr::new(239, 255, hi, lo)
+}
+
+/// The options that are the same for every packet sent by a source.
+pub struct Source {
+    /// The component identifier, which should be unique for each source.
+    pub cid: [u8; 16],
+    pub name: String,
+    pub priority: u8,
+}
+
+/// Builds an E1.31 data packet consisting of the root layer, framing layer and DMP layer.
+///
+/// All multi-byte values are big endian. Each layer starts with its length, which includes all the
+/// layers that follow it.
+fn data_packet(source: &Source, universe: u16, sequence: u8, data: &[u8]) -> io::Result<Vec<u8>> {
+    assert!(data.len() <= UNIVERSE_SIZE);
+    let mut wr = Vec::with_capacity(126 + data.len());
+    let pdu_length = |len: usize| PDU_FLAGS | (len as u16 & 0x0fff);
+
+    // Root layer
+    wr.write_u16::<BigEndian>(0x0010)?; // Preamble Size
+    wr.write_u16::<BigEndian>(0x0000)?; // Post-amble Size
+    wr.write_all(ACN_PACKET_IDENTIFIER)?;
+    wr.write_u16::<BigEndian>(pdu_length(110 + data.len()))?; // Flags and Length
+    wr.write_u32::<BigEndian>(VECTOR_ROOT_E131_DATA)?; // Vector
+    wr.write_all(&source.cid)?; // CID
+
+    // Framing layer
+    wr.write_u16::<BigEndian>(pdu_length(88 + data.len()))?; // Flags and Length
+    wr.write_u32::<BigEndian>(VECTOR_E131_DATA_PACKET)?; // Vector
+    let mut name = [0; 64];
+    let name_len = source.name.len().min(63);
+    name[..name_len].copy_from_slice(&source.name.as_bytes()[..name_len]);
+    wr.write_all(&name)?; // Source Name
+    wr.write_u8(source.priority)?; // Priority
+    wr.write_u16::<BigEndian>(0)?; // Synchronization Address
+    wr.write_u8(sequence)?; // Sequence Number
+    wr.write_u8(0)?; // Options
+    wr.write_u16::<BigEndian>(universe)?; // Universe
+
+    // DMP layer
+    wr.write_u16::<BigEndian>(pdu_length(11 + data.len()))?; // Flags and Length
+    wr.write_u8(VECTOR_DMP_SET_PROPERTY)?; // Vector
+    wr.write_u8(0xa1)?; // Address Type & Data Type
+    wr.write_u16::<BigEndian>(0)?; // First Property Address
+    wr.write_u16::<BigEndian>(1)?; // Address Increment
+    wr.write_u16::<BigEndian>(1 + data.len() as u16)?; // Property value count
+    wr.write_u8(0)?; // DMX Start Code
+    wr.write_all(data)?; // Data
+    Ok(wr)
+}
+
+pub struct E131 {
+    socket: net::UdpSocket,
+    source: Source,
+    /// The addresses to send to. If None, each universe is sent to its multicast group.
+    targets: Option<Vec<net::SocketAddr>>,
+    universe: u16,
+    /// The sequence number of each universe the frame is spread over.
+    sequences: Vec<u8>,
+}
+
+impl Output for E131 {
+    fn output_frame(&mut self, frame: &[Pixel]) -> io::Result<()> {
+        let data: Vec<u8> = frame.iter().flat_map(|p| [p.r, p.g, p.b]).collect();
+        // Frames that do not fit in a single universe are continued in the next universes.
+        let num_universes = data.len().div_ceil(UNIVERSE_SIZE);
+        let last_universe = usize::from(self.universe) + num_universes.saturating_sub(1);
+        if last_universe > usize::from(MAX_UNIVERSE) {
+            return Err(io::Error::other(format!(
+                "the frame needs universes up to {}, the maximum is {}",
+                last_universe, MAX_UNIVERSE
+            )));
+        }
+        if self.sequences.len() < num_universes {
+            self.sequences.resize(num_universes, 0);
+        }
+        for (i, chunk) in data.chunks(UNIVERSE_SIZE).enumerate() {
+            let universe = self.universe + i as u16;
+            let sequence = &mut self.sequences[i];
+            let packet = data_packet(&self.source, universe, *sequence, chunk)?;
+            *sequence = sequence.wrapping_add(1);
+            match &self.targets {
+                Some(targets) => {
+                    for addr in targets {
+                        self.socket.send_to(&packet, addr)?;
+                    }
+                }
+                None => {
+                    self.socket
+                        .send_to(&packet, (multicast_addr(universe), PORT))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn command() -> clap::Command {
+    clap::Command::new("e131")
+        .about("Control E1.31 (sACN) DMX nodes via unicast or multicast")
+        .arg(clap::arg!(-t --target <value> ... "One or more target IP addresses")
+            .value_parser(clap::value_parser!(net::IpAddr))
+            .conflicts_with("multicast"))
+        .arg(clap::arg!(-m --multicast "Send each universe to its multicast group")
+            .conflicts_with("target"))
+        .arg(clap::arg!(-u --universe <value> "The universe to send to. Frames that do not fit are continued in the next universes")
+            .value_parser(clap::value_parser!(u16).range(1..=i64::from(MAX_UNIVERSE)))
+            .default_value("1"))
+        .arg(clap::arg!(--priority <value> "The priority of the data, receivers use the source with the highest priority")
+            .value_parser(clap::value_parser!(u8).range(0..=200))
+            .default_value("100"))
+        .arg(clap::arg!(--cid <hex> "The 16 byte component identifier of this source as hex. Random by default")
+            .value_parser(|s: &str| -> Result<[u8; 16], String> {
+                let bytes = util::parse_hex(s)?;
+                bytes.try_into().map_err(|_| "the CID must be 16 bytes".to_string())
+            }))
+        .arg(clap::arg!(--"source-name" <name> "The name of this source shown by receivers")
+            .default_value("ledcat"))
+}
+
+pub fn from_command(args: &clap::ArgMatches, gargs: &GlobalArgs) -> io::Result<FromCommand> {
+    let targets = match args.get_many::<net::IpAddr>("target") {
+        Some(targets) => Some(
+            targets
+                .map(|addr| net::SocketAddr::new(*addr, PORT))
+                .collect(),
+        ),
+        None if args.get_flag("multicast") => None,
+        None => {
+            eprintln!("Missing E1.31 target. Please set --target IP or --multicast");
+            return Ok(FromCommand::SubcommandHandled);
+        }
+    };
+    let cid = match args.get_one::<[u8; 16]>("cid") {
+        Some(cid) => *cid,
+        None => {
+            let mut cid = [0; 16];
+            fs::File::open("/dev/urandom")?.read_exact(&mut cid)?;
+            cid
+        }
+    };
+    let num_universes = (gargs.dimensions()?.size() * 3).div_ceil(UNIVERSE_SIZE);
+
+    let socket = net::UdpSocket::bind(("0.0.0.0", 0))?;
+    Ok(FromCommand::Output(Box::new(E131 {
+        socket,
+        source: Source {
+            cid,
+            name: args.get_one::<String>("source-name").unwrap().clone(),
+            priority: *args.get_one::<u8>("priority").unwrap(),
+        },
+        targets,
+        universe: *args.get_one::<u16>("universe").unwrap(),
+        sequences: vec![0; num_universes],
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn multicast_group() {
+        assert_eq!(net::Ipv4Addr::new(239, 255, 0, 1), multicast_addr(1));
+        assert_eq!(net::Ipv4Addr::new(239, 255, 1, 44), multicast_addr(300));
+    }
+
+    #[test]
+    fn packet_layers() {
+        let source = Source {
+            cid: [7; 16],
+            name: "test".to_string(),
+            priority: 100,
+        };
+        let packet = data_packet(&source, 0x0102, 42, &[1, 2, 3]).unwrap();
+        assert_eq!(126 + 3, packet.len());
+        assert_eq!(&ACN_PACKET_IDENTIFIER[..], &packet[4..16]);
+        // The length of each layer is counted from its flags and length field.
+        let pdu_len = |at: usize| u16::from_be_bytes([packet[at], packet[at + 1]]);
+        assert_eq!(0x7000 | (packet.len() - 16) as u16, pdu_len(16));
+        assert_eq!(0x7000 | (packet.len() - 38) as u16, pdu_len(38));
+        assert_eq!(0x7000 | (packet.len() - 115) as u16, pdu_len(115));
+        assert_eq!([7; 16], packet[22..38]);
+        assert_eq!(b"test\0", &packet[44..49]);
+        assert_eq!(100, packet[108]);
+        assert_eq!(42, packet[111]);
+        assert_eq!([1, 2], packet[113..115]);
+        assert_eq!([0, 4], packet[123..125]); // Property value count
+        assert_eq!([0, 1, 2, 3], packet[125..]);
+    }
+
+    #[test]
+    fn frames_span_universes() {
+        let receiver = net::UdpSocket::bind(("127.0.0.1", 0)).unwrap();
+        let mut output = E131 {
+            socket: net::UdpSocket::bind(("127.0.0.1", 0)).unwrap(),
+            source: Source {
+                cid: [0; 16],
+                name: String::new(),
+                priority: 100,
+            },
+            targets: Some(vec![receiver.local_addr().unwrap()]),
+            universe: 5,
+            sequences: vec![0; 2],
+        };
+        // 200 pixels take 600 channels.
+        let frame = vec![Pixel { r: 1, g: 2, b: 3 }; 200];
+        output.output_frame(&frame).unwrap();
+        output.output_frame(&frame).unwrap();
+
+        let mut buf = [0; 1024];
+        for (universe, sequence, len) in [(5, 0, 512), (6, 0, 88), (5, 1, 512), (6, 1, 88)] {
+            let n = receiver.recv(&mut buf).unwrap();
+            assert_eq!(126 + len, n);
+            assert_eq!([0, universe], buf[113..115]);
+            assert_eq!(sequence, buf[111]);
+        }
+
+        // A longer frame than expected is continued in more universes.
+        let frame = vec![Pixel { r: 1, g: 2, b: 3 }; 400];
+        output.output_frame(&frame).unwrap();
+        for (universe, sequence, len) in [(5, 2, 512), (6, 2, 512), (7, 0, 176)] {
+            let n = receiver.recv(&mut buf).unwrap();
+            assert_eq!(126 + len, n);
+            assert_eq!([0, universe], buf[113..115]);
+            assert_eq!(sequence, buf[111]);
+        }
+
+        // Universes past the last one are not wrapped around.
+        output.universe = MAX_UNIVERSE;
+        assert!(output.output_frame(&frame).is_err());
+    }
+}
@@ -1,81 +1,271 @@
 use crate::color::*;
-use std::io::Write;
+use crate::device::Output;
+use crate::geometry::Dimensions;
+use std::io::{Read, Write};
 use std::*;
 
 const PORT: u16 = 5577;
 
+/// The minimum time between two error messages of the same bulb.
+const ERROR_LOG_INTERVAL: time::Duration = time::Duration::from_secs(10);
+
+/// The variants of the protocol spoken by Flux-LED bulbs.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Protocol {
+    Ledenet,
+    /// Used by older bulbs, which do not checksum their commands.
+    LedenetOriginal,
+}
+
+/// Appends the checksum used by the commands of the LEDENET protocol.
+fn with_checksum(data: &[u8]) -> Vec<u8> {
+    let checksum = data.iter().fold(0, |accum, b| accum + u32::from(*b)) as u8;
+    data.iter().cloned().chain(iter::once(checksum)).collect()
+}
+
+/// Queries the state of a bulb to find out which protocol it speaks. Some bulbs ignore colors
+/// until they have been queried.
+///
+/// ```text
+/// -> [0x81, 0x8a, 0x8b, 0x96]
+/// <- [129, 51, 35, 97, 1, 1, 0, 0, 0, 0, 4, 0, 0, 62]
+/// ```
+///
+/// Bulbs that do not respond are queried using the original protocol. If that fails as well, the
+/// LEDENET protocol is assumed.
+fn handshake(mut conn: impl Read + Write) -> io::Result<Protocol> {
+    let queries: [(Protocol, Vec<u8>, usize, u8); 2] = [
+        (
+            Protocol::Ledenet,
+            with_checksum(&[0x81, 0x8a, 0x8b]),
+            14,
+            0x81,
+        ),
+        (Protocol::LedenetOriginal, vec![0xef, 0x01, 0x77], 11, 0x66),
+    ];
+    for (protocol, query, response_len, response_start) in queries {
+        conn.write_all(&query)?;
+        let mut response = vec![0; response_len];
+        match conn.read_exact(&mut response) {
+            Ok(()) if response[0] == response_start => return Ok(protocol),
+            Ok(()) => (),
+            // The read timeout expired.
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(Protocol::Ledenet)
+}
+
 pub struct Bulb {
-    conn: Option<net::TcpStream>,
+    // The connection and the protocol that was detected when connecting.
+    conn: Option<(net::TcpStream, Protocol)>,
     ip: net::IpAddr,
+    last_error_log: Option<time::Instant>,
+    // The last power state that was sent, unknown until set.
+    power: Option<bool>,
 }
 
 impl Bulb {
     pub fn new(ip: net::IpAddr) -> Bulb {
-        let mut b = Bulb { conn: None, ip };
+        let mut b = Bulb {
+            conn: None,
+            ip,
+            last_error_log: None,
+            power: None,
+        };
         // Try to set up an initial connection.
         let _ = b.connection();
         b
     }
 
-    fn connection(&mut self) -> io::Result<&mut net::TcpStream> {
-        if let Some(ref mut conn) = self.conn {
-            return Ok(conn);
+    fn connection(&mut self) -> io::Result<(&mut net::TcpStream, Protocol)> {
+        if self.conn.is_none() {
+            // The handshake is done once per connection, so each frame is a single write.
+            let mut conn = net::TcpStream::connect((self.ip, PORT))?;
+            conn.set_read_timeout(Some(time::Duration::from_millis(100)))?;
+            let protocol = handshake(&mut conn)?;
+            self.conn = Some((conn, protocol));
         }
-
-        let conn = net::TcpStream::connect((self.ip, PORT))?;
-        conn.set_read_timeout(Some(time::Duration::from_millis(100)))?;
-        self.conn = Some(conn);
-        Ok(self.conn.as_mut().unwrap())
+        let (conn, protocol) = self.conn.as_mut().unwrap();
+        Ok((conn, *protocol))
     }
 
     pub fn set_constant_color(&mut self, pix: Pixel) -> io::Result<()> {
-        // For proto:
-        // -> [0x81, 0x8a, 0x8b, 0x96]
-        // <- [129, 51, 35, 97, 1, 1, 0, 0, 0, 0, 4, 0, 0, 62]
-        // -> [0x10, 0x14, 0x12, 0x05, 0x06, 0x15, 0x03, 0x0f0, 0x0a, 0x00, 0x0f, b'~']
-        // <- []
-        self.send_with_checksum(&[0x31, pix.r, pix.g, pix.b, 0x00, 0x00, 0x0f])
-    }
-
-    fn send_with_checksum(&mut self, data: &[u8]) -> io::Result<()> {
-        let rs = if let Ok(conn) = self.connection() {
-            let checksum = data.iter().fold(0, |accum, b| accum + u32::from(*b)) as u8;
-            let buf: Vec<u8> = data.iter().cloned().chain(iter::once(checksum)).collect();
-            conn.write_all(&buf)
-        } else {
-            Ok(())
-        };
+        self.send(|protocol| match protocol {
+            Protocol::Ledenet => with_checksum(&[0x31, pix.r, pix.g, pix.b, 0x00, 0x00, 0x0f]),
+            Protocol::LedenetOriginal => vec![0x56, pix.r, pix.g, pix.b, 0xaa],
+        })
+    }
+
+    /// Turns the bulb on or off. The command is only sent if the state changes.
+    pub fn set_power(&mut self, on: bool) -> io::Result<()> {
+        if self.power == Some(on) {
+            return Ok(());
+        }
+        let state = if on { 0x23 } else { 0x24 };
+        self.send(|protocol| match protocol {
+            Protocol::Ledenet => with_checksum(&[0x71, state, 0x0f]),
+            Protocol::LedenetOriginal => vec![0xcc, state, 0x33],
+        })?;
+        self.power = Some(on);
+        Ok(())
+    }
+
+    /// Sends the command built for the protocol of the bulb.
+    fn send(&mut self, command: impl FnOnce(Protocol) -> Vec<u8>) -> io::Result<()> {
+        let rs = self
+            .connection()
+            .and_then(|(conn, protocol)| conn.write_all(&command(protocol)));
         if rs.is_err() {
             self.conn = None;
+            // The bulb may have been power cycled by the time it is reachable again.
+            self.power = None;
         }
         rs
     }
+
+    /// Prints an error to stderr, unless one was printed for this bulb recently.
+    fn log_error(&mut self, err: &io::Error) {
+        let due = self
+            .last_error_log
+            .is_none_or(|t| t.elapsed() >= ERROR_LOG_INTERVAL);
+        if due {
+            eprintln!("fluxled: could not update bulb {}: {}", self.ip, err);
+            self.last_error_log = Some(time::Instant::now());
+        }
+    }
 }
 
 pub struct Display {
     pub bulbs: Vec<Bulb>,
     pub buf: Vec<u8>,
+    /// Turn bulbs off when their pixel is black, since many bulbs still glow when set to black.
+    pub off_on_black: bool,
 }
 
-impl io::Write for Display {
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        let need = self.bulbs.len() * 3;
-        let n = self.buf.write(buf)?;
-        if self.buf.len() >= need {
-            self.flush()?;
-        }
-        Ok(n)
-    }
-
+impl Display {
+    /// Sends the colors in the buffer to the bulbs, three bytes per bulb.
     fn flush(&mut self) -> io::Result<()> {
+        // A bulb that can not be reached should not stop the others from being updated. The
+        // output only fails if none of the bulbs could be updated.
+        let mut last_err = None;
+        let mut num_failed = 0;
         for (bulb, chunk) in self.bulbs.iter_mut().zip(self.buf.chunks(3)) {
-            let _ = bulb.set_constant_color(Pixel {
+            let pix = Pixel {
                 r: chunk[0],
                 g: chunk[1],
                 b: chunk[2],
-            });
+            };
+            let rs = if !self.off_on_black {
+                bulb.set_constant_color(pix)
+            } else if pix == Pixel::default() {
+                bulb.set_power(false)
+            } else {
+                bulb.set_power(true)
+                    .and_then(|_| bulb.set_constant_color(pix))
+            };
+            if let Err(err) = rs {
+                bulb.log_error(&err);
+                num_failed += 1;
+                last_err = Some(err);
+            }
         }
         self.buf.clear();
-        Ok(())
+        match last_err {
+            Some(err) if num_failed == self.bulbs.len() => Err(err),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Output for Display {
+    fn output_frame(&mut self, frame: &[Pixel]) -> io::Result<()> {
+        self.buf.clear();
+        self.buf
+            .extend(frame.iter().flat_map(|pix| [pix.r, pix.g, pix.b]));
+        self.flush()
+    }
+
+    fn default_dimensions(&self) -> Option<Dimensions> {
+        // Each bulb is a single pixel.
+        Some(Dimensions {
+            w: self.bulbs.len(),
+            h: 1,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Replies to each write with the next scripted response. Reads time out once the response
+    /// has been read.
+    struct Scripted {
+        responses: collections::VecDeque<Vec<u8>>,
+        pending: io::Cursor<Vec<u8>>,
+        written: Vec<u8>,
+    }
+
+    impl Scripted {
+        fn new(responses: &[&[u8]]) -> Scripted {
+            Scripted {
+                responses: responses.iter().map(|r| r.to_vec()).collect(),
+                pending: io::Cursor::new(Vec::new()),
+                written: Vec::new(),
+            }
+        }
+    }
+
+    impl Read for Scripted {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.pending.read(buf)? {
+                0 => Err(io::ErrorKind::WouldBlock.into()),
+                n => Ok(n),
+            }
+        }
+    }
+
+    impl Write for Scripted {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let response = self.responses.pop_front().unwrap_or_default();
+            self.pending = io::Cursor::new(response);
+            self.written.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn handshake_detects_protocol() {
+        let mut conn = Scripted::new(&[&[129, 51, 35, 97, 1, 1, 0, 0, 0, 0, 4, 0, 0, 62]]);
+        assert_eq!(Protocol::Ledenet, handshake(&mut conn).unwrap());
+        assert_eq!(vec![0x81, 0x8a, 0x8b, 0x96], conn.written);
+
+        let mut conn = Scripted::new(&[&[], &[0x66, 1, 0x23, 0x41, 0x20, 0, 0xff, 0, 0, 0, 0x99]]);
+        assert_eq!(Protocol::LedenetOriginal, handshake(&mut conn).unwrap());
+        assert_eq!(vec![0x81, 0x8a, 0x8b, 0x96, 0xef, 0x01, 0x77], conn.written);
+
+        let mut conn = Scripted::new(&[]);
+        assert_eq!(Protocol::Ledenet, handshake(&mut conn).unwrap());
+    }
+
+    #[test]
+    fn flush_fails_if_all_bulbs_fail() {
+        // The connection is refused, as long as no bulb is listening on the local host.
+        let mut display = Display {
+            bulbs: vec![Bulb::new(net::IpAddr::V4(net::Ipv4Addr::new(0, 0, 0, 0)))],
+            buf: vec![1, 2, 3],
+            off_on_black: false,
+        };
+        assert!(display.flush().is_err());
+        assert!(display.buf.is_empty());
     }
 }
@@ -2,6 +2,7 @@ mod bulb;
 
 use self::bulb::*;
 use crate::device::*;
+use crate::util;
 use net2::unix::UnixUdpBuilderExt;
 use nix::sys::socket::SockaddrStorage;
 use std::collections;
@@ -29,9 +30,11 @@ pub fn command() -> clap::Command {
         .arg(clap::arg!(-n --net <value> "The network range of where to look for devices in CIDR format")
             .value_parser(clap::value_parser!(Cidr))
             .requires_all(["discover"]))
+        .arg(clap::arg!(--"off-on-black" "Turn bulbs off instead of setting them to black, as some bulbs still glow")
+            .conflicts_with_all(["discover"]))
 }
 
-pub fn from_command(args: &clap::ArgMatches, _gargs: &GlobalArgs) -> io::Result<FromCommand> {
+pub fn from_command(args: &clap::ArgMatches, gargs: &GlobalArgs) -> io::Result<FromCommand> {
     if args.get_flag("discover") {
         let network_range_rs = match args.get_one::<Cidr>("net").cloned() {
             Some(v) => Ok(v),
@@ -49,7 +52,7 @@ pub fn from_command(args: &clap::ArgMatches, _gargs: &GlobalArgs) -> io::Result<
             }
         };
 
-        if let Err(err) = tui_discover(network_range) {
+        if let Err(err) = tui_discover(network_range, gargs.verbosity) {
             eprintln!("{}", err);
         }
         return Ok(FromCommand::SubcommandHandled);
@@ -61,18 +64,15 @@ pub fn from_command(args: &clap::ArgMatches, _gargs: &GlobalArgs) -> io::Result<
         .map(|addr| Bulb::new(*addr))
         .collect();
 
-    let dev = Box::new(generic::Generic {
-        format: generic::Format::RGB24,
-    });
-    let output = Display {
+    Ok(FromCommand::Output(Box::new(Display {
         bulbs,
         buf: Vec::new(),
-    };
-    Ok(FromCommand::Output(Box::new((dev, output))))
+        off_on_black: args.get_flag("off-on-black"),
+    })))
 }
 
-fn tui_discover(network_range: Cidr) -> io::Result<()> {
-    let discovery_stream = discover(network_range);
+fn tui_discover(network_range: Cidr, verbosity: u8) -> io::Result<()> {
+    let discovery_stream = discover(network_range, verbosity);
     let mut discovered: collections::HashSet<net::SocketAddr> = collections::HashSet::new();
 
     let (close_tx, close_rx) = sync::mpsc::sync_channel(0);
@@ -109,8 +109,12 @@ fn tui_discover(network_range: Cidr) -> io::Result<()> {
     Ok(())
 }
 
+/// Continuously scans the network range for Flux-LED bulbs.
+///
+/// With a verbosity of 1 or higher, every received packet is dumped to stderr.
 fn discover(
     network_range: Cidr,
+    verbosity: u8,
 ) -> sync::mpsc::Receiver<io::Result<(net::SocketAddr, Option<String>)>> {
     let (tx, rx) = sync::mpsc::channel();
 
@@ -128,23 +132,33 @@ fn discover(
         }
 
         let socket = {
-            let b = try_or_send!(net2::UdpBuilder::new_v4());
+            let b = if network_range.addr.is_ipv6() {
+                try_or_send!(net2::UdpBuilder::new_v6())
+            } else {
+                try_or_send!(net2::UdpBuilder::new_v4())
+            };
             try_or_send!(b.reuse_address(true));
             try_or_send!(b.reuse_port(true));
-            try_or_send!(b.bind(("0.0.0.0", DISCOVERY_PORT)))
+            let unspecified = match network_range.addr {
+                net::IpAddr::V4(_) => net::IpAddr::V4(net::Ipv4Addr::UNSPECIFIED),
+                net::IpAddr::V6(_) => net::IpAddr::V6(net::Ipv6Addr::UNSPECIFIED),
+            };
+            try_or_send!(b.bind((unspecified, DISCOVERY_PORT)))
         };
-        try_or_send!(socket.set_broadcast(true));
+        if network_range.addr.is_ipv4() {
+            try_or_send!(socket.set_broadcast(true));
+        }
         try_or_send!(socket.set_read_timeout(Some(time::Duration::from_secs(1))));
 
         loop {
             for ip in network_range.addresses() {
-                let addr = net::SocketAddr::new(net::IpAddr::V4(ip), DISCOVERY_PORT);
+                let addr = net::SocketAddr::new(ip, DISCOVERY_PORT);
                 try_or_send!(socket.send_to(DISCOVERY_MAGIC, addr));
             }
 
             loop {
                 let mut recv_buf = [0; 64];
-                let (_, sender_addr) = match socket.recv_from(&mut recv_buf) {
+                let (len, sender_addr) = match socket.recv_from(&mut recv_buf) {
                     Err(_) => break,
                     Ok(rs) => rs,
                 };
@@ -153,6 +167,15 @@ fn discover(
                     continue;
                 }
                 let name = String::from_utf8_lossy(&recv_buf).into_owned();
+                if verbosity >= 1 {
+                    eprint!(
+                        "\r{} bytes from {}:\n{}Name: {:?}\n",
+                        len,
+                        sender_addr,
+                        util::hex_dump(&recv_buf[..len]),
+                        name
+                    );
+                }
                 tx.send(Ok((sender_addr, Some(name)))).unwrap();
             }
         }
@@ -167,17 +190,27 @@ struct Cidr {
     mask: net::IpAddr,
 }
 
+/// The shortest IPv6 prefix that is accepted. Longer ranges have too many addresses to scan.
+const MIN_IPV6_PREFIX: u32 = 112;
+
 impl Cidr {
-    fn addresses(&self) -> impl iter::Iterator<Item = net::Ipv4Addr> {
+    fn addresses(&self) -> Box<dyn iter::Iterator<Item = net::IpAddr> + Send> {
         match (self.addr, self.mask) {
             (net::IpAddr::V4(network_ip), net::IpAddr::V4(mask_ip)) => {
                 let network: u32 = network_ip.into();
                 let mask: u32 = mask_ip.into();
                 let start = network & mask;
                 let end = start | !mask;
-                (start..end).map(net::Ipv4Addr::from)
+                Box::new((start..end).map(|ip| net::IpAddr::V4(ip.into())))
+            }
+            (net::IpAddr::V6(network_ip), net::IpAddr::V6(mask_ip)) => {
+                let network: u128 = network_ip.into();
+                let mask: u128 = mask_ip.into();
+                assert!(mask.leading_ones() >= MIN_IPV6_PREFIX);
+                let start = network & mask;
+                let end = start | !mask;
+                Box::new((start..end).map(|ip| net::IpAddr::V6(ip.into())))
             }
-            (net::IpAddr::V6(_network), net::IpAddr::V6(_mask)) => unimplemented!(),
             _ => unreachable!(),
         }
     }
@@ -187,7 +220,7 @@ impl Cidr {
         use nix::net::if_::InterfaceFlags;
         use nix::sys::socket::{AddressFamily, SockaddrLike};
         nix::ifaddrs::getifaddrs()
-            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?
+            .map_err(io::Error::other)?
             // Filter out loopback interfaces, those are not very useful for discovering remote
             // devices.
             .filter(|iface| !iface.flags.contains(InterfaceFlags::IFF_LOOPBACK))
@@ -209,9 +242,7 @@ impl Cidr {
                 }
             })
             .next()
-            .ok_or_else(|| {
-                io::Error::new(io::ErrorKind::Other, "Unable to determine default network")
-            })
+            .ok_or_else(|| io::Error::other("Unable to determine default network"))
     }
 
     #[cfg(any(
@@ -223,10 +254,7 @@ impl Cidr {
         target_os = "openbsd"
     ))]
     fn default_interface() -> io::Result<Cidr> {
-        Err(io::Error::new(
-            io::ErrorKind::Other,
-            "Platform is not supported",
-        ))
+        Err(io::Error::other("Platform is not supported"))
     }
 }
 
@@ -236,20 +264,78 @@ impl FromStr for Cidr {
         let mut split = s.split('/');
         let addr: net::IpAddr = split
             .next()
-            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "missing the address of the CIDR"))?
+            .ok_or_else(|| io::Error::other("missing the address of the CIDR"))?
             .parse()?;
         let mask_str = split
             .next()
-            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "missing the mask of the CIDR"))?;
+            .ok_or_else(|| io::Error::other("missing the mask of the CIDR"))?;
         let mask: net::IpAddr =
             mask_str
                 .parse()
                 .or_else(|_| -> Result<_, Box<dyn error::Error + Send + Sync>> {
                     let bits: u32 = mask_str.parse()?;
-                    Ok(net::IpAddr::V4(net::Ipv4Addr::from(
-                        !((0x8000_0000 >> (bits - 1)) - 1),
-                    )))
+                    let max_bits = if addr.is_ipv4() { 32 } else { 128 };
+                    if bits > max_bits {
+                        return Err(io::Error::other(format!(
+                            "the prefix /{} is longer than the {} bits of the address",
+                            bits, max_bits
+                        ))
+                        .into());
+                    }
+                    Ok(match addr {
+                        net::IpAddr::V4(_) => net::IpAddr::V4(net::Ipv4Addr::from(
+                            u32::MAX.checked_shl(32 - bits).unwrap_or(0),
+                        )),
+                        net::IpAddr::V6(_) => net::IpAddr::V6(net::Ipv6Addr::from(
+                            u128::MAX.checked_shl(128 - bits).unwrap_or(0),
+                        )),
+                    })
                 })?;
+        if addr.is_ipv4() != mask.is_ipv4() {
+            return Err(io::Error::other(
+                "the address and mask of the CIDR are of different IP versions",
+            )
+            .into());
+        }
+        if let net::IpAddr::V6(mask_ip) = mask {
+            let prefix = u128::from(mask_ip).leading_ones();
+            if prefix < MIN_IPV6_PREFIX {
+                return Err(io::Error::other(format!(
+                    "the IPv6 network /{} has 2^{} addresses, which is too many to scan. Please use a prefix of /{} or longer",
+                    prefix,
+                    128 - prefix,
+                    MIN_IPV6_PREFIX
+                ))
+                .into());
+            }
+        }
         Ok(Cidr { addr, mask })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cidr_addresses() {
+        let cidr: Cidr = "192.168.1.0/30".parse().unwrap();
+        let addrs: Vec<_> = cidr.addresses().map(|ip| ip.to_string()).collect();
+        assert_eq!(vec!["192.168.1.0", "192.168.1.1", "192.168.1.2"], addrs);
+
+        let cidr: Cidr = "fd00::1:0/126".parse().unwrap();
+        let addrs: Vec<_> = cidr.addresses().map(|ip| ip.to_string()).collect();
+        assert_eq!(vec!["fd00::1:0", "fd00::1:1", "fd00::1:2"], addrs);
+
+        assert_eq!(
+            (1 << 16) - 1,
+            "fd00::/112".parse::<Cidr>().unwrap().addresses().count()
+        );
+        let err = "fd00::/64".parse::<Cidr>().err().unwrap();
+        assert!(err.to_string().contains("2^64"));
+
+        for cidr in ["192.168.1.0/33", "fd00::/129", "fd00::/200"] {
+            assert!(cidr.parse::<Cidr>().is_err(), "{}", cidr);
+        }
+    }
+}
@@ -1,68 +1,94 @@
 use crate::device::*;
 use std::io;
+use std::iter;
 
 pub enum Format {
     RGB24,
-    RGB16,
+    /// RGB plus a white channel. The white is extracted from the RGB values and corrected with
+    /// the white curve of the correction.
+    RGBW32(Box<Correction>, WhiteMode),
+    /// RGB565, the byte order of each pixel is set by the endianness.
+    RGB16(Endian),
     RGB12,
     RGB8,
     GS1,
 }
 
+impl Format {
+    /// The number of bytes used by a single pixel. Formats that pack multiple pixels into a byte
+    /// return None.
+    fn bytes_per_pixel(&self) -> Option<usize> {
+        match self {
+            Format::RGB24 => Some(3),
+            Format::RGBW32(..) => Some(4),
+            Format::RGB16(_) => Some(2),
+            Format::RGB8 => Some(1),
+            Format::RGB12 | Format::GS1 => None,
+        }
+    }
+}
+
+/// Padding inserted for controllers with alignment requirements.
+#[derive(Default)]
+pub struct Padding {
+    /// The number of bytes appended to each pixel.
+    pub pixel: usize,
+    /// The minimum length of a frame in bytes.
+    pub frame: usize,
+    /// The value of the bytes that are inserted.
+    pub fill: u8,
+}
+
 pub struct Generic {
     pub format: Format,
+    pub padding: Padding,
 }
 
 impl Device for Generic {
     fn write_frame(&self, writer: &mut dyn io::Write, pixels: &[Pixel]) -> io::Result<()> {
-        match self.format {
-            Format::RGB24 => {
-                let buf: Vec<u8> = pixels
-                    .iter()
-                    .flat_map(|pix| vec![pix.r, pix.g, pix.b])
-                    .collect();
-                writer.write_all(&buf)?;
-            }
-            Format::RGB16 => {
-                let buf: Vec<u8> = pixels
-                    .iter()
-                    .flat_map(|pix| {
-                        vec![
-                            (pix.r & 0xf8) | (pix.g >> 5),
-                            (pix.g & 0x08) << 5 | (pix.b >> 3),
-                        ]
-                    })
-                    .collect();
-                writer.write_all(&buf)?;
-            }
-            Format::RGB12 => {
-                let buf: Vec<u8> = pixels
-                    .chunks(2)
-                    .flat_map(|ch| {
-                        let (a, b) = (&ch[0], ch.get(1).cloned().unwrap_or_default());
-                        vec![
-                            (a.r & 0xf0) | (a.g >> 4),
-                            (a.b & 0xf0) | (b.r >> 4),
-                            (b.g & 0xf0) | (b.b >> 4),
-                        ]
-                    })
-                    .collect();
-                writer.write_all(&buf)?;
-            }
-            Format::RGB8 => {
-                let buf: Vec<u8> = pixels
-                    .iter()
-                    .map(|p| (p.b & 0xc0) | ((p.g >> 2) & 0x3c) | (p.r & 0x3))
-                    .collect();
-                writer.write_all(&buf)?;
-            }
+        let mut buf: Vec<u8> = match &self.format {
+            Format::RGB24 => pixels
+                .iter()
+                .flat_map(|pix| vec![pix.r, pix.g, pix.b])
+                .collect(),
+            Format::RGBW32(correction, white_mode) => pixels
+                .iter()
+                .flat_map(|pix| {
+                    let pix = white_mode.extract(*pix);
+                    vec![pix.r, pix.g, pix.b, correction.correct_white(pix.w)]
+                })
+                .collect(),
+            Format::RGB16(endian) => pixels
+                .iter()
+                .flat_map(|pix| {
+                    let v = u16::from(pix.r & 0xf8) << 8
+                        | u16::from(pix.g & 0xfc) << 3
+                        | u16::from(pix.b >> 3);
+                    endian.u16_bytes(v)
+                })
+                .collect(),
+            Format::RGB12 => pixels
+                .chunks(2)
+                .flat_map(|ch| {
+                    let (a, b) = (&ch[0], ch.get(1).cloned().unwrap_or_default());
+                    vec![
+                        (a.r & 0xf0) | (a.g >> 4),
+                        (a.b & 0xf0) | (b.r >> 4),
+                        (b.g & 0xf0) | (b.b >> 4),
+                    ]
+                })
+                .collect(),
+            Format::RGB8 => pixels
+                .iter()
+                .map(|p| (p.b & 0xc0) | ((p.g >> 2) & 0x3c) | (p.r & 0x3))
+                .collect(),
             Format::GS1 => {
-                assert!(pixels.len() % 8 == 0);
+                assert!(pixels.len().is_multiple_of(8));
                 let prebuf: Vec<u8> = pixels
                     .iter()
                     .map(|p| if grayscale(*p) > 127 { 1 } else { 0 })
                     .collect();
-                let packed: Vec<u8> = prebuf
+                prebuf
                     .chunks(8)
                     .map(|chunk| {
                         chunk
@@ -70,11 +96,25 @@ impl Device for Generic {
                             .enumerate()
                             .fold(0, |pack, (i, b)| pack | b << i)
                     })
-                    .collect();
-                writer.write_all(&packed)?;
+                    .collect()
             }
+        };
+
+        if self.padding.pixel > 0 {
+            let bytes_per_pixel = self.format.bytes_per_pixel().unwrap();
+            buf = buf
+                .chunks(bytes_per_pixel)
+                .flat_map(|pix| {
+                    pix.iter()
+                        .copied()
+                        .chain(iter::repeat_n(self.padding.fill, self.padding.pixel))
+                })
+                .collect();
         }
-        Ok(())
+        if buf.len() < self.padding.frame {
+            buf.resize(self.padding.frame, self.padding.fill);
+        }
+        writer.write_all(&buf)
     }
 }
 
@@ -84,22 +124,124 @@ pub fn command() -> clap::Command {
         .arg(
             clap::arg!(-f --format <value>)
                 .default_value("rgb24")
-                .value_parser(["rgb24", "rgb16", "rgb12", "rgb8", "gs1"]),
+                .value_parser(["rgb24", "rgbw32", "rgb16", "rgb12", "rgb8", "gs1"]),
         )
+        .arg(
+            clap::arg!(--"white-gamma" <value> "The gamma of the white channel when using rgbw32")
+                .default_value("1.0")
+                .value_parser(clap::value_parser!(f64)),
+        )
+        .arg(Endian::arg("big").help(
+            "The byte order of rgb16 pixels. SPI displays like the ILI9341 and ST7735 expect big, \
+             Linux framebuffers and most microcontrollers expect little",
+        ))
+        .arg(clap::arg!(--"pad-pixel" <bytes> "Append a number of bytes to each pixel. Not supported by rgb12 and gs1")
+            .default_value("0")
+            .value_parser(clap::value_parser!(usize)))
+        .arg(clap::arg!(--"pad-frame" <bytes> "Pad each frame to at least this number of bytes")
+            .default_value("0")
+            .value_parser(clap::value_parser!(usize)))
+        .arg(clap::arg!(--"pad-fill" <value> "The value of the bytes used for padding")
+            .default_value("0")
+            .value_parser(clap::value_parser!(u8)))
 }
 
-pub fn from_command(args: &clap::ArgMatches, _: &GlobalArgs) -> io::Result<FromCommand> {
+pub fn from_command(args: &clap::ArgMatches, gargs: &GlobalArgs) -> io::Result<FromCommand> {
     let format = match args.get_one::<String>("format").unwrap().as_str() {
-        "rgb16" => Format::RGB16,
+        "rgbw32" => {
+            let gamma = *args.get_one::<f64>("white-gamma").unwrap();
+            Format::RGBW32(
+                Box::new(Correction::none().with_white_gamma(255, gamma)),
+                gargs.white_mode,
+            )
+        }
+        "rgb16" => Format::RGB16(Endian::from_args(args)),
         "rgb12" => Format::RGB12,
         "rgb8" => Format::RGB8,
         "gs1" => Format::GS1,
         _ => Format::RGB24,
     };
-    Ok(FromCommand::Device(Box::new(Generic { format })))
+    let padding = Padding {
+        pixel: *args.get_one::<usize>("pad-pixel").unwrap(),
+        frame: *args.get_one::<usize>("pad-frame").unwrap(),
+        fill: *args.get_one::<u8>("pad-fill").unwrap(),
+    };
+    if padding.pixel > 0 && format.bytes_per_pixel().is_none() {
+        return Err(io::Error::other(
+            "--pad-pixel can not be used with formats that pack pixels",
+        ));
+    }
+    Ok(FromCommand::Device(Box::new(Generic { format, padding })))
 }
 
 fn grayscale(p: Pixel) -> u8 {
     let g = (0.2125 * p.r as f32) + (0.7154 * p.g as f32) + (0.0721 * p.b as f32);
     g.round() as u8
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rgb565() {
+        let dev = Generic {
+            format: Format::RGB16(Endian::Big),
+            padding: Padding::default(),
+        };
+        let write = |r, g, b| {
+            let mut buf = Vec::new();
+            dev.write_frame(&mut buf, &[Pixel { r, g, b }]).unwrap();
+            buf
+        };
+        assert_eq!(vec![0xf8, 0x00], write(255, 0, 0));
+        assert_eq!(vec![0x07, 0xe0], write(0, 255, 0));
+        assert_eq!(vec![0x00, 0x1f], write(0, 0, 255));
+        assert_eq!(vec![0xff, 0xff], write(255, 255, 255));
+        assert_eq!(vec![0x84, 0x10], write(128, 128, 128));
+        // Only the lower bits of each channel are dropped.
+        assert_eq!(vec![0x00, 0x20], write(0, 4, 0));
+        assert_eq!(vec![0x00, 0x00], write(7, 3, 7));
+
+        let dev = Generic {
+            format: Format::RGB16(Endian::Little),
+            padding: Padding::default(),
+        };
+        let mut buf = Vec::new();
+        dev.write_frame(&mut buf, &[Pixel { r: 0, g: 255, b: 0 }])
+            .unwrap();
+        assert_eq!(vec![0xe0, 0x07], buf);
+    }
+
+    #[test]
+    fn padding() {
+        let pixels = [Pixel { r: 1, g: 2, b: 3 }, Pixel { r: 4, g: 5, b: 6 }];
+        let mut buf = Vec::new();
+        let dev = Generic {
+            format: Format::RGB24,
+            padding: Padding {
+                pixel: 1,
+                frame: 12,
+                fill: 0xaa,
+            },
+        };
+        dev.write_frame(&mut buf, &pixels).unwrap();
+        assert_eq!(
+            vec![1, 2, 3, 0xaa, 4, 5, 6, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa],
+            buf
+        );
+
+        // Frames that are already long enough are left alone.
+        let mut buf = Vec::new();
+        let dev = Generic {
+            format: Format::RGB24,
+            padding: Padding {
+                pixel: 0,
+                frame: 4,
+                fill: 0,
+            },
+        };
+        dev.write_frame(&mut buf, &pixels).unwrap();
+        assert_eq!(vec![1, 2, 3, 4, 5, 6], buf);
+    }
+}
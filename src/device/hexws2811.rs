@@ -8,23 +8,28 @@ impl Device for HexWS2811 {
         Correction::srgb(255, 255, 255)
     }
 
+    fn color_order(&self) -> ColorOrder {
+        ColorOrder::GRB
+    }
+
     fn write_frame(&self, writer: &mut dyn io::Write, pixels: &[Pixel]) -> io::Result<()> {
         let mut buf = Vec::with_capacity(pixels.len() * 6 + 4);
         for pix in pixels.iter().rev() {
-            buf.write_all(&[
-                ((u16::from(pix.g) * 256) & 0xff) as u8,
-                ((u16::from(pix.g) * 256) >> 8) as u8,
-                ((u16::from(pix.r) * 256) & 0xff) as u8,
-                ((u16::from(pix.r) * 256) >> 8) as u8,
-                ((u16::from(pix.b) * 256) & 0xff) as u8,
-                ((u16::from(pix.b) * 256) >> 8) as u8,
-            ])?;
+            for ch in [pix.g, pix.r, pix.b] {
+                buf.write_all(&expand_u16(ch).to_le_bytes())?;
+            }
         }
         buf.write_all(&[0xff, 0xff, 0xff, 0xf0])?;
         writer.write_all(&buf)
     }
 }
 
+/// Scales an 8-bit channel value to the full 16-bit range by replicating it into both bytes, so
+/// 0xff maps to 0xffff instead of 0xff00.
+fn expand_u16(v: u8) -> u16 {
+    u16::from(v) << 8 | u16::from(v)
+}
+
 pub fn command() -> clap::Command {
     clap::Command::new("hexws2811")
 }
@@ -32,3 +37,47 @@ pub fn command() -> clap::Command {
 pub fn from_command(_: &clap::ArgMatches, _: &GlobalArgs) -> io::Result<FromCommand> {
     Ok(FromCommand::Device(Box::new(HexWS2811 {})))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(dev: &HexWS2811, pixels: &[Pixel]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        dev.write_frame(&mut buf, pixels).unwrap();
+        buf
+    }
+
+    #[test]
+    fn scale_to_16_bit() {
+        assert_eq!(0x0000, expand_u16(0));
+        assert_eq!(0x8080, expand_u16(128));
+        assert_eq!(0xffff, expand_u16(255));
+        // The low byte must carry information too, not just the high byte.
+        for v in 1..=255 {
+            assert_ne!(0, expand_u16(v) & 0xff);
+        }
+    }
+
+    #[test]
+    fn byte_layout() {
+        let pixels = [
+            Pixel {
+                r: 0x00,
+                g: 0x01,
+                b: 0x02,
+            },
+            Pixel {
+                r: 0xff,
+                g: 0x80,
+                b: 0x10,
+            },
+        ];
+        let expected = vec![
+            0x80, 0x80, 0xff, 0xff, 0x10, 0x10, // Second pixel, GRB order
+            0x01, 0x01, 0x00, 0x00, 0x02, 0x02, // First pixel
+            0xff, 0xff, 0xff, 0xf0, // Trailer
+        ];
+        assert_eq!(expected, write(&HexWS2811 {}, &pixels));
+    }
+}
@@ -1,30 +1,43 @@
 use crate::color::*;
 use crate::device::*;
-use gpio::sysfs::SysFsGpioOutput;
+use crate::driver::gpio::mem::{GpioMem, MemGpioOutput};
+use crate::driver::gpio::sysfs::{self, SysFsGpioOutput};
 use gpio::{GpioOut, GpioValue};
 use std::io;
-use std::sync::mpsc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc};
 use std::thread;
+use std::{hint, time};
 
-type WorkerGpioOut = GpioOutBuffer<SysFsGpioOutput>;
-
-struct Worker {
+struct Worker<P: GpioOut> {
     width: usize,
     height: usize,
 
-    pwm_cycles: u8,
+    // The refreshes of all rows that make up a single frame.
+    passes: Vec<Pass>,
+    // The number of rows that are driven by each set of data lines.
+    scan_height: usize,
+    // The percentage of the time that a row is lit.
+    brightness: u8,
+    // When the last row that was latched was switched on.
+    lit_since: Option<time::Instant>,
+    // The line addresses in the order they are scanned, along with the row of the scan section
+    // that is shown at each address.
+    scan: Vec<(usize, usize)>,
     frame_rx: mpsc::Receiver<Vec<Pixel>>,
+    // Counts the number of times all passes of a frame have been shown.
+    refreshes: Arc<AtomicU64>,
     err_tx: mpsc::Sender<io::Error>,
     cur_frame: Vec<Pixel>,
 
-    level_select: Vec<WorkerGpioOut>,
-    rgb: Vec<[WorkerGpioOut; 3]>,
-    clock: WorkerGpioOut,
-    output_enable: WorkerGpioOut,
-    latch: WorkerGpioOut,
+    level_select: Vec<GpioOutBuffer<P>>,
+    rgb: Vec<[GpioOutBuffer<P>; 3]>,
+    clock: GpioOutBuffer<P>,
+    output_enable: GpioOutBuffer<P>,
+    latch: GpioOutBuffer<P>,
 }
 
-impl Worker {
+impl<P: GpioOut<Error = io::Error>> Worker<P> {
     fn run(&mut self) {
         loop {
             match self.frame_rx.try_recv() {
@@ -35,29 +48,28 @@ impl Worker {
                 Err(mpsc::TryRecvError::Empty) => (),
                 Err(_) => break,
             };
-            for i in 0..self.pwm_cycles {
-                let a = 255 / (self.pwm_cycles + 1);
-                let min_val = 255 - i * a - a;
-                if let Err(err) = self.refresh_display(min_val) {
-                    self.err_tx.send(err).unwrap();
+            for i in 0..self.passes.len() {
+                if let Err(err) = self.refresh_display(self.passes[i]) {
+                    // Give up, the pins are released when the worker is dropped so a new worker
+                    // can be started in its place.
+                    let _ = self.err_tx.send(err);
+                    return;
                 }
             }
+            self.refreshes.fetch_add(1, Ordering::Relaxed);
         }
     }
 
-    fn refresh_display(&mut self, min_val: u8) -> io::Result<()> {
-        let num_level_select = self.level_select.len();
-        let scan_height = 1 << self.level_select.len();
-        let scan_interleaved = (0..scan_height)
-            .map(|i| ((i << 1) | (i >> (num_level_select - 1))) & (scan_height - 1));
-        for y in scan_interleaved {
+    fn refresh_display(&mut self, pass: Pass) -> io::Result<()> {
+        let scan_height = self.scan_height;
+        for &(address, y) in &self.scan {
             // Clock in data for one row (Rn, Gn, Bn for data)
             for x in 0..self.width {
                 for (line, rgb) in self.rgb.iter_mut().enumerate() {
                     let pix = &self.cur_frame[(y + line * scan_height) * self.width + x];
-                    rgb[0].set_value(pix.r >= min_val)?;
-                    rgb[1].set_value(pix.g >= min_val)?;
-                    rgb[2].set_value(pix.b >= min_val)?;
+                    rgb[0].set_value(pass.lit(pix.r))?;
+                    rgb[1].set_value(pass.lit(pix.g))?;
+                    rgb[2].set_value(pass.lit(pix.b))?;
                 }
                 // CLK pulse
                 self.clock.set_value(1)?;
@@ -65,23 +77,292 @@ impl Worker {
             }
             // OE high
             self.output_enable.set_value(1)?;
+            if let Some(lit_since) = self.lit_since.take() {
+                // Dim the display by keeping the previous row dark for a while. This is
+                // proportional to the time it was lit, so all passes are dimmed equally.
+                spin(blank_time(lit_since.elapsed(), self.brightness));
+            }
             // Select line address (A, B, C, D)
             for (i, ls) in self.level_select.iter_mut().enumerate() {
-                ls.set_value((y >> i) as u8 & 1)?;
+                ls.set_value((address >> i) as u8 & 1)?;
             }
             // LAT pulse
             self.latch.set_value(1)?;
             self.latch.set_value(0)?;
-            // OE low
-            self.output_enable.set_value(0)?;
+            // OE low, unless the display is dimmed completely
+            if self.brightness > 0 {
+                self.output_enable.set_value(0)?;
+            }
+            if self.brightness < 100 {
+                self.lit_since = Some(time::Instant::now());
+            }
+            if let Pass::BitPlane(_, hold) = pass {
+                spin(hold);
+            }
         }
         Ok(())
     }
 }
 
-pub struct Hub75 {
+/// Waits for the duration. Sleeping is too coarse for the short periods that rows are lit.
+fn spin(duration: time::Duration) {
+    let start = time::Instant::now();
+    while start.elapsed() < duration {
+        hint::spin_loop();
+    }
+}
+
+/// Computes how long a row should be kept dark after it has been lit for `lit` to reach the
+/// brightness percentage.
+fn blank_time(lit: time::Duration, brightness: u8) -> time::Duration {
+    match brightness {
+        0 | 100.. => time::Duration::ZERO,
+        b => lit * u32::from(100 - b) / u32::from(b),
+    }
+}
+
+/// How the grayscale levels are produced by switching the LEDs on and off.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Modulation {
+    /// Refreshes the display a number of times per frame, with each refresh lighting the channels
+    /// above a threshold.
+    Pwm(u8),
+    /// Binary coded modulation, which shows each bit of the channels for a period proportional to
+    /// its significance.
+    Bcm { bits: u8, unit: time::Duration },
+}
+
+impl Modulation {
+    fn passes(&self) -> Vec<Pass> {
+        match *self {
+            Modulation::Pwm(cycles) => {
+                let a = (255 / (u16::from(cycles) + 1)) as u8;
+                (0..cycles)
+                    .map(|i| Pass::Threshold(255 - i * a - a))
+                    .collect()
+            }
+            Modulation::Bcm { bits, unit } => (0..bits)
+                .map(|plane| Pass::BitPlane(8 - bits + plane, unit * (1 << plane)))
+                .collect(),
+        }
+    }
+}
+
+/// A single refresh of all rows.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Pass {
+    /// Lights the channels with a value of at least the threshold.
+    Threshold(u8),
+    /// Lights the channels that have the bit set and keeps each row lit for the duration.
+    BitPlane(u8, time::Duration),
+}
+
+impl Pass {
+    fn lit(&self, value: u8) -> bool {
+        match *self {
+            Pass::Threshold(min_val) => value >= min_val,
+            Pass::BitPlane(bit, _) => (value >> bit) & 1 == 1,
+        }
+    }
+}
+
+/// The order in which the line addresses are scanned.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum ScanPattern {
+    /// Even addresses first, followed by the odd ones.
+    Interleaved,
+    /// All addresses in ascending order.
+    Sequential,
+}
+
+/// Computes the line addresses in the order they are scanned, along with the row within each scan
+/// section that is shown at each address. With `reverse`, the rows are assigned to the addresses
+/// in the opposite order, for panels that are wired upside down.
+fn scan_order(scan_height: usize, pattern: ScanPattern, reverse: bool) -> Vec<(usize, usize)> {
+    let addresses: Vec<usize> = match pattern {
+        ScanPattern::Interleaved => (0..scan_height)
+            .step_by(2)
+            .chain((1..scan_height).step_by(2))
+            .collect(),
+        ScanPattern::Sequential => (0..scan_height).collect(),
+    };
+    addresses
+        .into_iter()
+        .map(|address| {
+            let y = if reverse {
+                scan_height - 1 - address
+            } else {
+                address
+            };
+            (address, y)
+        })
+        .collect()
+}
+
+/// The way the GPIO pins are accessed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Backend {
+    SysFs,
+    /// Direct register access on a Raspberry Pi.
+    Mem,
+}
+
+/// The pin assignment of a panel, retained so the GPIO pins can be reacquired when the worker is
+/// restarted.
+struct Config {
+    width: usize,
+    /// The number of rows scanned by the worker. This is larger than the height of the display if
+    /// frames are padded with black rows.
+    height: usize,
+    modulation: Modulation,
+    brightness: u8,
+    /// The number of rows driven by each set of data lines, e.g. 8 for a 1/8 scan panel.
+    scan_height: usize,
+    scan_pattern: ScanPattern,
+    scan_reverse: bool,
+    backend: Backend,
+    gpio_base: String,
+    level_select: Vec<u16>,
+    red: Vec<u16>,
+    green: Vec<u16>,
+    blue: Vec<u16>,
+    clock: u16,
+    latch: u16,
+    output_enable: u16,
+    /// Shared by all workers, so the refresh rate can be measured across restarts.
+    refreshes: Arc<AtomicU64>,
+}
+
+impl Config {
+    fn spawn_worker(&self) -> io::Result<WorkerHandle> {
+        match self.backend {
+            Backend::SysFs => {
+                self.spawn_worker_with(|num| SysFsGpioOutput::exported_new(&self.gpio_base, num))
+            }
+            Backend::Mem => {
+                let mem = GpioMem::open()?;
+                self.spawn_worker_with(|num| MemGpioOutput::new(&mem, num))
+            }
+        }
+    }
+
+    fn spawn_worker_with<P>(&self, open: impl Fn(u16) -> io::Result<P>) -> io::Result<WorkerHandle>
+    where
+        P: GpioOut<Error = io::Error> + Send + 'static,
+    {
+        let pins = |nums: &[u16]| -> io::Result<Vec<_>> {
+            nums.iter()
+                .map(|&num| open(num).map(GpioOutBuffer::new))
+                .collect()
+        };
+        let pin = |num: u16| -> io::Result<_> { Ok(pins(&[num])?.pop().unwrap()) };
+
+        let (frame_tx, frame_rx) = mpsc::sync_channel(0);
+        let (err_tx, err_rx) = mpsc::channel();
+        let mut worker = Worker {
+            width: self.width,
+            height: self.height,
+            passes: self.modulation.passes(),
+            scan_height: self.scan_height,
+            brightness: self.brightness,
+            lit_since: None,
+            scan: scan_order(self.scan_height, self.scan_pattern, self.scan_reverse),
+            frame_rx,
+            refreshes: self.refreshes.clone(),
+            cur_frame: vec![Pixel::default(); self.width * self.height],
+            err_tx,
+            level_select: pins(&self.level_select)?,
+            rgb: pins(&self.red)?
+                .into_iter()
+                .zip(pins(&self.green)?)
+                .zip(pins(&self.blue)?)
+                .map(|a| [(a.0).0, (a.0).1, a.1])
+                .collect(),
+            clock: pin(self.clock)?,
+            latch: pin(self.latch)?,
+            output_enable: pin(self.output_enable)?,
+        };
+        let thread = thread::spawn(move || {
+            worker.run();
+        });
+        Ok(WorkerHandle {
+            frame_tx,
+            err_rx,
+            thread,
+        })
+    }
+}
+
+struct WorkerHandle {
     frame_tx: mpsc::SyncSender<Vec<Pixel>>,
     err_rx: mpsc::Receiver<io::Error>,
+    thread: thread::JoinHandle<()>,
+}
+
+/// The interval at which the refresh rate is measured.
+const REFRESH_REPORT_PERIOD: time::Duration = time::Duration::from_secs(1);
+
+/// Spawns a thread that measures the refresh rate. It is printed to stderr if `print` is set, and
+/// a warning is printed when it drops below `min_rate`. The thread stops once the counter is
+/// dropped.
+fn spawn_refresh_reporter(refreshes: &Arc<AtomicU64>, min_rate: f64, print: bool) {
+    let refreshes = Arc::downgrade(refreshes);
+    thread::spawn(move || {
+        let mut prev = 0;
+        let mut prev_time = time::Instant::now();
+        let mut below = false;
+        loop {
+            thread::sleep(REFRESH_REPORT_PERIOD);
+            let Some(refreshes) = refreshes.upgrade() else {
+                return;
+            };
+            let (now, now_time) = (refreshes.load(Ordering::Relaxed), time::Instant::now());
+            let rate = refresh_rate(now - prev, now_time - prev_time);
+            if print {
+                eprintln!(
+                    "hub75: refresh rate: {:.1}Hz, {:.3}ms per refresh",
+                    rate,
+                    1e3 / rate.max(f64::MIN_POSITIVE)
+                );
+            }
+            if rate < min_rate && !below {
+                eprintln!(
+                    "hub75: refresh rate dropped to {:.1}Hz, which is below {}Hz and may be visible as flicker",
+                    rate, min_rate
+                );
+            }
+            below = rate < min_rate;
+            (prev, prev_time) = (now, now_time);
+        }
+    });
+}
+
+fn refresh_rate(refreshes: u64, elapsed: time::Duration) -> f64 {
+    refreshes as f64 / elapsed.as_secs_f64()
+}
+
+pub struct Hub75 {
+    config: Config,
+    worker: Option<WorkerHandle>,
+}
+
+impl Hub75 {
+    /// Waits for the dead worker to release its pins and starts a new one.
+    fn restart_worker(&mut self, reason: &dyn std::fmt::Display) -> io::Result<()> {
+        eprintln!("hub75: worker stopped ({}), restarting", reason);
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.thread.join();
+        }
+        self.worker = Some(self.config.spawn_worker()?);
+        Ok(())
+    }
+
+    /// The worker is gone if restarting it failed.
+    fn worker(&self) -> io::Result<&WorkerHandle> {
+        self.worker
+            .as_ref()
+            .ok_or_else(|| io::Error::other("hub75: the worker could not be restarted"))
+    }
 }
 
 impl Output for Hub75 {
@@ -90,102 +371,194 @@ impl Output for Hub75 {
     }
 
     fn output_frame(&mut self, frame: &[Pixel]) -> io::Result<()> {
-        match self.err_rx.try_recv() {
-            Ok(io_err) => return Err(io_err),
+        let worker = self.worker()?;
+        match worker.err_rx.try_recv() {
+            Ok(err) => self.restart_worker(&err)?,
             Err(mpsc::TryRecvError::Empty) => (),
-            Err(err) => return io_err!(Err(err)),
+            Err(mpsc::TryRecvError::Disconnected) => self.restart_worker(&"panicked")?,
         };
-        io_err!(self.frame_tx.send(frame.to_vec()))?;
+        let frame = pad_rows(frame, self.config.width, self.config.height);
+        let worker = self.worker()?;
+        if worker.frame_tx.send(frame.clone()).is_err() {
+            // The worker died while we were waiting for it to accept the frame.
+            let err = worker
+                .err_rx
+                .try_recv()
+                .unwrap_or_else(|_| io::Error::other("panicked"));
+            self.restart_worker(&err)?;
+            io_err!(self.worker()?.frame_tx.send(frame))?;
+        }
         Ok(())
     }
 }
 
 pub fn command() -> clap::Command {
-    let comma_separated = |s: &str| -> Result<(), std::num::ParseIntError> {
-        for v in s.split(',') {
-            v.parse::<u32>()?;
-        }
-        Ok(())
+    let comma_separated = |s: &str| -> Result<Vec<u16>, std::num::ParseIntError> {
+        s.split(',').map(|v| v.parse()).collect()
     };
     clap::Command::new("hub75")
         .about("Drive HUB75 LED-panels using GPIO")
         .arg(clap::arg!(--"level-select" <value> "The GPIO-pins connected to the level select. These are typically labeled as A, B, C and D")
             .value_parser(comma_separated))
         .arg(clap::arg!(--clock <value> "The GPIO-pin connected to the clock. Typically labeled as CLK")
-            .value_parser(clap::value_parser!(u32)))
+            .value_parser(clap::value_parser!(u16)))
         .arg(clap::arg!(--latch <value> "The GPIO-pin connected to the latch. Typically labeled as LAT")
-            .value_parser(clap::value_parser!(u32)))
+            .value_parser(clap::value_parser!(u16)))
         .arg(clap::arg!(--"output-enable" <value> "The GPIO-pin connected to the output-enable. Typically labeled as OE")
-            .value_parser(clap::value_parser!(u32)))
+            .value_parser(clap::value_parser!(u16)))
         .arg(clap::arg!(--red <value> "The GPIO-pins connected to the red data lines. Typically labeled as R1 and R2")
             .value_parser(comma_separated))
         .arg(clap::arg!(--green <value> "The GPIO-pins connected to the green data lines. Typically labeled as G1 and G2")
             .value_parser(comma_separated))
         .arg(clap::arg!(--blue <value> "The GPIO-pins connected to the blue data lines. Typically labeled as B1 and B2")
             .value_parser(comma_separated))
+        .arg(clap::arg!(--"gpio-backend" <value> "How the GPIO pins are accessed. mem writes to the registers of a Raspberry Pi directly, which is much faster but requires root")
+            .value_parser(["sysfs", "mem"])
+            .default_value("sysfs"))
+        .arg(clap::arg!(--"gpio-sysfs" <path> "The directory where the GPIO sysfs interface is mounted")
+            .default_value(sysfs::DEFAULT_BASE))
         .arg(clap::arg!(--pwm <value> "The number of grayscale refreshes per frame that should be performed")
             .default_value("3")
             .value_parser(clap::value_parser!(u8)))
+        .arg(clap::arg!(--brightness <percent> "Dim the display by keeping each row dark for part of the time, which retains all grayscale levels")
+            .value_parser(clap::value_parser!(u8).range(0..=100))
+            .default_value("100"))
+        .arg(clap::arg!(--modulation <value> "How grayscale is produced. bcm (binary coded modulation) shows each bit for a period proportional to its significance, which allows for more levels at the same refresh rate")
+            .value_parser(["pwm", "bcm"])
+            .default_value("pwm"))
+        .arg(clap::arg!(--"bcm-bits" <value> "The number of bits per channel that are shown when using binary coded modulation")
+            .value_parser(clap::value_parser!(u8).range(1..=8))
+            .default_value("6"))
+        .arg(clap::arg!(--"bcm-unit" <us> "The time in microseconds that each row shows the least significant bit when using binary coded modulation")
+            .value_parser(clap::value_parser!(u64))
+            .default_value("10"))
+        .arg(clap::arg!(--"scan-pattern" <value> "The order in which the rows are scanned. Most panels interleave the even and odd rows")
+            .value_parser(["interleaved", "sequential"])
+            .default_value("interleaved"))
+        .arg(clap::arg!(--"scan-reverse" "Reverse the order of the row addresses, for panels that show the image mirrored within each scan section"))
+        .arg(clap::arg!(--"scan-rate" <rows> "The number of rows driven by each set of data lines, e.g. 8 for a 1/8 scan panel. Defaults to 2^len(level-select-pins)")
+            .value_parser(clap::value_parser!(u16).range(1..)))
+        .arg(clap::arg!(--stats "Print the refresh rate of the display every second"))
+        .arg(clap::arg!(--"min-refresh-rate" <hz> "Print a warning when the refresh rate drops below this rate")
+            .value_parser(clap::value_parser!(f64))
+            .default_value("100"))
+        .arg(clap::arg!(--"pad-rows" "Pad the frames with black rows if the height does not fill all rows that are scanned"))
+}
+
+/// Rounds the height up to a multiple of the number of rows that are covered by a single scan of
+/// all line addresses.
+fn padded_height(height: usize, scan_height: usize, num_data_lines: usize) -> usize {
+    let rows_per_scan = scan_height * num_data_lines;
+    height.div_ceil(rows_per_scan) * rows_per_scan
+}
+
+/// Checks that a single scan covers exactly the rows of the display and returns the number of rows
+/// that are scanned.
+fn scanned_height(
+    height: usize,
+    scan_height: usize,
+    num_data_lines: usize,
+    pad: bool,
+) -> io::Result<usize> {
+    let rows_per_scan = scan_height * num_data_lines;
+    let scanned = if pad {
+        padded_height(height, scan_height, num_data_lines)
+    } else {
+        height
+    };
+    if scanned != rows_per_scan {
+        let hint = if height < rows_per_scan {
+            ", use --pad-rows to pad it"
+        } else {
+            ""
+        };
+        return Err(io::Error::other(format!(
+            "A scan rate of 1/{} with {} data line(s) covers {} rows, but the height is {}{}",
+            scan_height, num_data_lines, rows_per_scan, height, hint
+        )));
+    }
+    Ok(scanned)
+}
+
+/// Appends black rows to the frame until it is `height` rows high.
+fn pad_rows(frame: &[Pixel], width: usize, height: usize) -> Vec<Pixel> {
+    let mut padded = frame.to_vec();
+    padded.resize(width * height, Pixel::default());
+    padded
 }
 
 pub fn from_command(args: &clap::ArgMatches, gargs: &GlobalArgs) -> io::Result<FromCommand> {
     let dimensions = gargs.dimensions()?;
 
-    let pwm_cycles = *args.get_one::<u8>("pwm").unwrap();
-    let pins = |name: &str| -> io::Result<Vec<_>> {
-        args.get_one::<String>(name)
-            .unwrap()
-            .split(',')
-            .map(|s| s.parse().unwrap())
-            .map(|num| SysFsGpioOutput::open(num).map(GpioOutBuffer::new))
-            .collect()
-    };
-    let pin = |name: &str| -> io::Result<_> { Ok(pins(name)?.pop().unwrap()) };
+    let pins = |name: &str| -> Vec<u16> { args.get_one::<Vec<u16>>(name).unwrap().clone() };
+    let pin = |name: &str| -> u16 { *args.get_one::<u16>(name).unwrap() };
 
-    let (frame_tx, frame_rx) = mpsc::sync_channel(0);
-    let (err_tx, err_rx) = mpsc::channel();
+    let num_addresses = 1usize << pins("level-select").len();
+    let scan_height = args
+        .get_one::<u16>("scan-rate")
+        .map(|&rate| usize::from(rate))
+        .unwrap_or(num_addresses);
+    if scan_height > num_addresses {
+        return Err(io::Error::other(format!(
+            "A scan rate of 1/{} requires at least {} level select pins",
+            scan_height,
+            scan_height.next_power_of_two().trailing_zeros()
+        )));
+    }
 
-    let mut worker = Worker {
+    let mut config = Config {
         width: dimensions.w,
         height: dimensions.h,
-        pwm_cycles,
-        frame_rx,
-        cur_frame: vec![Pixel::default(); dimensions.size()],
-        err_tx,
-        level_select: {
-            let p = pins("level-select")?;
-            if dimensions.h % (1 << p.len()) != 0 {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    "The height must be a multiple of 2^len(level-select-pins)",
-                ));
-            }
-            p
+        modulation: match args.get_one::<String>("modulation").unwrap().as_str() {
+            "bcm" => Modulation::Bcm {
+                bits: *args.get_one::<u8>("bcm-bits").unwrap(),
+                unit: time::Duration::from_micros(*args.get_one::<u64>("bcm-unit").unwrap()),
+            },
+            _ => Modulation::Pwm(*args.get_one::<u8>("pwm").unwrap()),
         },
-        rgb: {
-            let r = pins("red")?;
-            let g = pins("green")?;
-            let b = pins("blue")?;
-            if r.len() != g.len() || g.len() != b.len() {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    "The number of red, green and blue pins must be all equal",
-                ));
-            }
-            r.into_iter()
-                .zip(g)
-                .zip(b)
-                .map(|a| [(a.0).0, (a.0).1, a.1])
-                .collect()
+        brightness: *args.get_one::<u8>("brightness").unwrap(),
+        scan_height,
+        scan_pattern: match args.get_one::<String>("scan-pattern").unwrap().as_str() {
+            "sequential" => ScanPattern::Sequential,
+            _ => ScanPattern::Interleaved,
         },
-        clock: pin("clock")?,
-        latch: pin("latch")?,
-        output_enable: pin("output-enable")?,
+        scan_reverse: args.get_flag("scan-reverse"),
+        backend: match args.get_one::<String>("gpio-backend").unwrap().as_str() {
+            "mem" => Backend::Mem,
+            _ => Backend::SysFs,
+        },
+        gpio_base: args.get_one::<String>("gpio-sysfs").unwrap().clone(),
+        level_select: pins("level-select"),
+        red: pins("red"),
+        green: pins("green"),
+        blue: pins("blue"),
+        clock: pin("clock"),
+        latch: pin("latch"),
+        output_enable: pin("output-enable"),
+        refreshes: Arc::new(AtomicU64::new(0)),
     };
-    thread::spawn(move || {
-        worker.run();
-    });
-    Ok(FromCommand::Output(Box::new(Hub75 { frame_tx, err_rx })))
+    if config.red.len() != config.green.len() || config.green.len() != config.blue.len() {
+        return Err(io::Error::other(
+            "The number of red, green and blue pins must be all equal",
+        ));
+    }
+    config.height = scanned_height(
+        dimensions.h,
+        scan_height,
+        config.red.len(),
+        args.get_flag("pad-rows"),
+    )?;
+
+    let worker = config.spawn_worker()?;
+    spawn_refresh_reporter(
+        &config.refreshes,
+        *args.get_one::<f64>("min-refresh-rate").unwrap(),
+        args.get_flag("stats"),
+    );
+    Ok(FromCommand::Output(Box::new(Hub75 {
+        config,
+        worker: Some(worker),
+    })))
 }
 
 struct GpioOutBuffer<T: GpioOut> {
@@ -221,3 +594,90 @@ impl<T: GpioOut> GpioOut for GpioOutBuffer<T> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pad_partial_scan() {
+        // A 1/8 scan with 2 data lines covers 16 rows, so a 24 row frame is padded to 32 rows.
+        let (width, height) = (2, 24);
+        let scan_height = padded_height(height, 8, 2);
+        assert_eq!(32, scan_height);
+        assert_eq!(32, padded_height(32, 8, 2));
+
+        let frame = vec![Pixel { r: 1, g: 2, b: 3 }; width * height];
+        let padded = pad_rows(&frame, width, scan_height);
+        assert_eq!(width * 32, padded.len());
+        assert_eq!(&frame[..], &padded[..width * height]);
+        assert!(padded[width * height..]
+            .iter()
+            .all(|&pix| pix == Pixel::default()));
+    }
+
+    #[test]
+    fn scan_orders() {
+        assert_eq!(
+            vec![(0, 0), (2, 2), (1, 1), (3, 3)],
+            scan_order(4, ScanPattern::Interleaved, false)
+        );
+        assert_eq!(
+            vec![(0, 3), (2, 1), (1, 2), (3, 0)],
+            scan_order(4, ScanPattern::Interleaved, true)
+        );
+        assert_eq!(
+            vec![(0, 0), (1, 1), (2, 2), (3, 3)],
+            scan_order(4, ScanPattern::Sequential, false)
+        );
+        assert_eq!(vec![(0, 0)], scan_order(1, ScanPattern::Interleaved, false));
+    }
+
+    #[test]
+    fn scan_rate_must_cover_height() {
+        // A 1/8 scan panel of 16 rows with 2 data lines.
+        assert_eq!(16, scanned_height(16, 8, 2, false).unwrap());
+        assert!(scanned_height(32, 8, 2, false).is_err());
+        assert!(scanned_height(12, 8, 2, false).is_err());
+        assert_eq!(16, scanned_height(12, 8, 2, true).unwrap());
+    }
+
+    #[test]
+    fn modulation_passes() {
+        assert_eq!(
+            vec![
+                Pass::Threshold(192),
+                Pass::Threshold(129),
+                Pass::Threshold(66)
+            ],
+            Modulation::Pwm(3).passes()
+        );
+
+        let unit = time::Duration::from_micros(10);
+        let passes = Modulation::Bcm { bits: 3, unit }.passes();
+        assert_eq!(
+            vec![
+                Pass::BitPlane(5, unit),
+                Pass::BitPlane(6, unit * 2),
+                Pass::BitPlane(7, unit * 4),
+            ],
+            passes
+        );
+        assert!(passes[2].lit(0b1000_0000));
+        assert!(!passes[0].lit(0b1101_1111));
+    }
+
+    #[test]
+    fn brightness_blank_time() {
+        let lit = time::Duration::from_micros(30);
+        assert_eq!(time::Duration::ZERO, blank_time(lit, 100));
+        assert_eq!(lit, blank_time(lit, 50));
+        assert_eq!(lit * 3, blank_time(lit, 25));
+    }
+
+    #[test]
+    fn measure_refresh_rate() {
+        assert_eq!(240.0, refresh_rate(120, time::Duration::from_millis(500)));
+        assert_eq!(0.0, refresh_rate(0, time::Duration::from_secs(1)));
+    }
+}
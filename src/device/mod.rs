@@ -1,17 +1,31 @@
 use crate::color::*;
+use crate::driver::gpio::sysfs::SysFsGpioInput;
 use crate::geometry::*;
+use gpio::{GpioIn, GpioValue};
 use std::io;
 use std::ops::{Deref, DerefMut};
 use std::path::PathBuf;
+use std::time::Duration;
 
 pub mod artnet;
+pub mod capture;
+pub mod ddp;
+pub mod e131;
 pub mod fluxled;
 pub mod generic;
 pub mod hexws2811;
 pub mod hub75;
+pub mod null;
+pub mod ola;
+pub mod opc;
+pub mod pixelpusher;
+pub mod pwm;
 #[cfg(feature = "rpi-led-matrix")]
 pub mod rpi_led_matrix;
 pub mod simulator;
+pub mod snapshot;
+pub mod tpm2;
+pub mod wled;
 
 /// An output represents the device that is used as output.
 ///
@@ -23,6 +37,22 @@ pub trait Output: Send {
     fn color_correction(&self) -> Correction {
         Correction::none()
     }
+
+    /// The order in which the output sends the color channels of each pixel.
+    fn color_order(&self) -> ColorOrder {
+        ColorOrder::RGB
+    }
+
+    /// The highest number of frames per second the output is able to show, if it is limited by
+    /// the hardware.
+    fn max_frame_rate(&self) -> Option<u32> {
+        None
+    }
+
+    /// The dimensions implied by the configuration of the output, used when no geometry is set.
+    fn default_dimensions(&self) -> Option<Dimensions> {
+        None
+    }
 }
 
 impl<D, W> Output for (D, W)
@@ -31,12 +61,18 @@ where
     W: io::Write + Send,
 {
     fn output_frame(&mut self, frame: &[Pixel]) -> io::Result<()> {
-        self.0.write_frame(&mut self.1, frame)
+        self.0.write_frame(&mut self.1, frame)?;
+        // Let writers act on frame boundaries.
+        self.1.flush()
     }
 
     fn color_correction(&self) -> Correction {
         self.0.color_correction()
     }
+
+    fn color_order(&self) -> ColorOrder {
+        self.0.color_order()
+    }
 }
 
 impl Output for Box<dyn Output> {
@@ -47,6 +83,58 @@ impl Output for Box<dyn Output> {
     fn color_correction(&self) -> Correction {
         self.deref().color_correction()
     }
+
+    fn color_order(&self) -> ColorOrder {
+        self.deref().color_order()
+    }
+
+    fn max_frame_rate(&self) -> Option<u32> {
+        self.deref().max_frame_rate()
+    }
+
+    fn default_dimensions(&self) -> Option<Dimensions> {
+        self.deref().default_dimensions()
+    }
+}
+
+/// Paused holds back frames while a GPIO pin is high, e.g. while a button is pressed.
+pub struct Paused {
+    output: Box<dyn Output>,
+    pin: SysFsGpioInput,
+}
+
+impl Paused {
+    pub fn new(output: Box<dyn Output>, pin: SysFsGpioInput) -> io::Result<Paused> {
+        let pin = pin.with_edge_detection()?;
+        Ok(Paused { output, pin })
+    }
+}
+
+impl Output for Paused {
+    fn output_frame(&mut self, frame: &[Pixel]) -> io::Result<()> {
+        while self.pin.read_value()? == GpioValue::High {
+            // Edges should wake us up right away, the timeout is a fallback for when they are
+            // missed.
+            self.pin.wait_for_edge(Duration::from_millis(100))?;
+        }
+        self.output.output_frame(frame)
+    }
+
+    fn color_correction(&self) -> Correction {
+        self.output.color_correction()
+    }
+
+    fn color_order(&self) -> ColorOrder {
+        self.output.color_order()
+    }
+
+    fn max_frame_rate(&self) -> Option<u32> {
+        self.output.max_frame_rate()
+    }
+
+    fn default_dimensions(&self) -> Option<Dimensions> {
+        self.output.default_dimensions()
+    }
 }
 
 /// The Device is half of an output system and represents the wire format of some physical device.
@@ -59,6 +147,11 @@ pub trait Device: Send {
     fn color_correction(&self) -> Correction {
         Correction::none()
     }
+
+    /// The order in which the device writes the color channels of each pixel.
+    fn color_order(&self) -> ColorOrder {
+        ColorOrder::RGB
+    }
 }
 
 impl<T> Device for Box<T>
@@ -69,24 +162,59 @@ where
         self.deref().color_correction()
     }
 
+    fn color_order(&self) -> ColorOrder {
+        self.deref().color_order()
+    }
+
     fn write_frame(&self, out: &mut dyn io::Write, frame: &[Pixel]) -> io::Result<()> {
         self.deref().write_frame(out, frame)
     }
 }
 
+/// The byte order used by devices that emit multi-byte channel values.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Endian {
+    Big,
+    Little,
+}
+
+impl Endian {
+    pub fn u16_bytes(self, value: u16) -> [u8; 2] {
+        match self {
+            Endian::Big => value.to_be_bytes(),
+            Endian::Little => value.to_le_bytes(),
+        }
+    }
+
+    /// Constructs a command line argument for selecting the byte order with the specified default.
+    pub fn arg(default: &'static str) -> clap::Arg {
+        clap::arg!(--endian <value> "The byte order of 16-bit values")
+            .value_parser(["big", "little"])
+            .default_value(default)
+    }
+
+    /// Reads the value of the argument constructed by `Endian::arg`.
+    pub fn from_args(args: &clap::ArgMatches) -> Endian {
+        match args.get_one::<String>("endian").unwrap().as_str() {
+            "big" => Endian::Big,
+            _ => Endian::Little,
+        }
+    }
+}
+
 pub struct GlobalArgs {
     pub output_file: PathBuf,
     pub dimensions: Option<Dimensions>,
+    /// How RGBW devices compute the value of the white channel.
+    pub white_mode: WhiteMode,
+    /// The number of times -v was specified.
+    pub verbosity: u8,
 }
 
 impl GlobalArgs {
     pub fn dimensions(&self) -> io::Result<Dimensions> {
-        self.dimensions.ok_or_else(|| {
-            io::Error::new(
-                io::ErrorKind::Other,
-                "Please set the frame size with --geometry",
-            )
-        })
+        self.dimensions
+            .ok_or_else(|| io::Error::other("Please set the frame size with --geometry"))
     }
 }
 
@@ -106,12 +234,22 @@ pub type FromCommandFn = fn(&clap::ArgMatches, &GlobalArgs) -> io::Result<FromCo
 pub fn devices() -> Vec<(clap::Command, FromCommandFn)> {
     vec![
         (artnet::command(), artnet::from_command),
+        (capture::command(), capture::from_command),
+        (ddp::command(), ddp::from_command),
+        (e131::command(), e131::from_command),
         (fluxled::command(), fluxled::from_command),
         (generic::command(), generic::from_command),
         (hexws2811::command(), hexws2811::from_command),
         (hub75::command(), hub75::from_command),
+        (null::command(), null::from_command),
+        (ola::command(), ola::from_command),
+        (opc::command(), opc::from_command),
+        (pixelpusher::command(), pixelpusher::from_command),
+        (pwm::command(), pwm::from_command),
         #[cfg(feature = "rpi-led-matrix")]
         (rpi_led_matrix::command(), rpi_led_matrix::from_command),
         (simulator::command(), simulator::from_command),
+        (tpm2::command(), tpm2::from_command),
+        (wled::command(), wled::from_command),
     ]
 }
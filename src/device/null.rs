@@ -0,0 +1,74 @@
+use crate::device::*;
+use std::io;
+use std::time::{Duration, Instant};
+
+/// Null discards all frames. It is used to measure how fast the rest of the pipeline can go.
+pub struct Null {
+    report: bool,
+    frames: u64,
+    start: Option<Instant>,
+}
+
+impl Null {
+    fn summary(frames: u64, elapsed: Duration) -> String {
+        // The time is measured from the first frame, so it spans one frame less than counted.
+        let fps = match frames {
+            0 | 1 => 0.0,
+            n => (n - 1) as f64 / elapsed.as_secs_f64(),
+        };
+        format!(
+            "{} frames in {:.3}s, {:.1} fps",
+            frames,
+            elapsed.as_secs_f64(),
+            fps
+        )
+    }
+}
+
+impl Output for Null {
+    fn output_frame(&mut self, _frame: &[Pixel]) -> io::Result<()> {
+        self.start.get_or_insert_with(Instant::now);
+        self.frames += 1;
+        Ok(())
+    }
+}
+
+impl Drop for Null {
+    fn drop(&mut self) {
+        if self.report {
+            let elapsed = self.start.map(|t| t.elapsed()).unwrap_or_default();
+            eprintln!("{}", Null::summary(self.frames, elapsed));
+        }
+    }
+}
+
+pub fn command() -> clap::Command {
+    clap::Command::new("null")
+        .about("Discard all frames, e.g. to measure the frame rate that can be achieved")
+        .arg(clap::arg!(--report "Print the number of frames and the time it took on exit"))
+}
+
+pub fn from_command(args: &clap::ArgMatches, _: &GlobalArgs) -> io::Result<FromCommand> {
+    Ok(FromCommand::Output(Box::new(Null {
+        report: args.get_flag("report"),
+        frames: 0,
+        start: None,
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summary() {
+        assert_eq!(
+            "101 frames in 2.000s, 50.0 fps",
+            Null::summary(101, Duration::from_secs(2))
+        );
+        assert_eq!(
+            "0 frames in 0.000s, 0.0 fps",
+            Null::summary(0, Duration::ZERO)
+        );
+    }
+}
@@ -0,0 +1,162 @@
+use crate::device::*;
+use byteorder::{LittleEndian, WriteBytesExt};
+use std::io::{self, Write};
+use std::net;
+
+/// The address on which olad accepts RPC connections by default.
+const DEFAULT_ADDRESS: &str = "127.0.0.1:9010";
+/// The number of channels in a DMX universe.
+const UNIVERSE_SIZE: usize = 512;
+/// The version of the RPC framing, stored in the upper 4 bits of the header.
+const RPC_VERSION: u32 = 1;
+/// Messages of this type are not answered by olad, so frames can be streamed without waiting for
+/// a response.
+const RPC_TYPE_STREAM_REQUEST: u64 = 10;
+
+/// Appends a protobuf varint.
+fn write_varint(buf: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        buf.push((v as u8 & 0x7f) | 0x80);
+        v >>= 7;
+    }
+    buf.push(v as u8);
+}
+
+/// Appends a protobuf field with wire type 0 (varint).
+fn write_varint_field(buf: &mut Vec<u8>, field: u64, v: u64) {
+    write_varint(buf, field << 3);
+    write_varint(buf, v);
+}
+
+/// Appends a protobuf field with wire type 2 (length delimited).
+fn write_bytes_field(buf: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    write_varint(buf, (field << 3) | 2);
+    write_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+/// Encodes a call to StreamDmxData, which sets the DMX data of a universe.
+///
+/// The call is wrapped in an RpcMessage and prefixed with the 4 byte little endian header that
+/// olad expects, holding the version and the size of the message.
+fn stream_dmx_data(id: u32, universe: u32, data: &[u8]) -> Vec<u8> {
+    // message DmxData { required int32 universe = 1; required bytes data = 2; }
+    let mut dmx_data = Vec::new();
+    write_varint_field(&mut dmx_data, 1, universe.into());
+    write_bytes_field(&mut dmx_data, 2, data);
+
+    // message RpcMessage { required Type type = 1; optional uint32 id = 2;
+    //                      optional string name = 3; optional bytes buffer = 4; }
+    let mut message = Vec::new();
+    write_varint_field(&mut message, 1, RPC_TYPE_STREAM_REQUEST);
+    write_varint_field(&mut message, 2, id.into());
+    write_bytes_field(&mut message, 3, b"StreamDmxData");
+    write_bytes_field(&mut message, 4, &dmx_data);
+
+    let mut packet = Vec::with_capacity(message.len() + 4);
+    packet
+        .write_u32::<LittleEndian>((RPC_VERSION << 28) | (message.len() as u32 & 0x0fff_ffff))
+        .unwrap();
+    packet.extend(message);
+    packet
+}
+
+pub struct Ola {
+    addr: net::SocketAddr,
+    universe: u32,
+    conn: Option<net::TcpStream>,
+    id: u32,
+}
+
+impl Ola {
+    fn connect(addr: net::SocketAddr) -> io::Result<net::TcpStream> {
+        let conn = net::TcpStream::connect(addr).map_err(|err| {
+            io::Error::new(
+                err.kind(),
+                format!(
+                    "could not connect to olad at {}, is it running? ({})",
+                    addr, err
+                ),
+            )
+        })?;
+        conn.set_nodelay(true)?;
+        Ok(conn)
+    }
+}
+
+impl Output for Ola {
+    fn output_frame(&mut self, frame: &[Pixel]) -> io::Result<()> {
+        let data: Vec<u8> = frame.iter().flat_map(|p| [p.r, p.g, p.b]).collect();
+        let packet = stream_dmx_data(self.id, self.universe, &data);
+        self.id = self.id.wrapping_add(1);
+
+        // If olad is restarted, the connection is lost. Frames are dropped until it is back so
+        // ledcat can keep running in the meantime.
+        if self.conn.is_none() {
+            self.conn = Ola::connect(self.addr).ok();
+        }
+        let Some(conn) = &mut self.conn else {
+            return Ok(());
+        };
+        if let Err(err) = conn.write_all(&packet) {
+            eprintln!("ola: lost connection to olad ({}), reconnecting", err);
+            self.conn = None;
+        }
+        Ok(())
+    }
+}
+
+pub fn command() -> clap::Command {
+    clap::Command::new("ola")
+        .about("Send frames to a universe of the Open Lighting Architecture daemon")
+        .arg(
+            clap::arg!(-u --"ola-universe" <value> "The OLA universe to send the frames to")
+                .value_parser(clap::value_parser!(u32))
+                .default_value("0"),
+        )
+        .arg(
+            clap::arg!(--address <value> "The address of olad")
+                .value_parser(clap::value_parser!(net::SocketAddr))
+                .default_value(DEFAULT_ADDRESS),
+        )
+}
+
+pub fn from_command(args: &clap::ArgMatches, gargs: &GlobalArgs) -> io::Result<FromCommand> {
+    let dimensions = gargs.dimensions()?;
+    if dimensions.size() * 3 > UNIVERSE_SIZE {
+        return Err(io::Error::other(format!(
+            "a DMX universe can hold up to {} pixels, got {}",
+            UNIVERSE_SIZE / 3,
+            dimensions.size()
+        )));
+    }
+    let addr = *args.get_one::<net::SocketAddr>("address").unwrap();
+    let conn = Ola::connect(addr)?;
+    Ok(FromCommand::Output(Box::new(Ola {
+        addr,
+        universe: *args.get_one::<u32>("ola-universe").unwrap(),
+        conn: Some(conn),
+        id: 0,
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_stream_dmx_data() {
+        let packet = stream_dmx_data(300, 2, &[1, 2, 3]);
+        #[rustfmt::skip]
+        let message = [
+            0x08, 10,               // type
+            0x10, 0xac, 0x02,       // id
+            0x1a, 13, b'S', b't', b'r', b'e', b'a', b'm', b'D', b'm', b'x', b'D', b'a', b't', b'a',
+            0x22, 7,                // buffer
+            0x08, 2,                // universe
+            0x12, 3, 1, 2, 3,       // data
+        ];
+        assert_eq!(&[message.len() as u8, 0, 0, 0x10], &packet[..4]);
+        assert_eq!(&message[..], &packet[4..]);
+    }
+}
@@ -0,0 +1,146 @@
+use crate::device::*;
+use byteorder::{BigEndian, WriteBytesExt};
+use std::io::{self, Write};
+use std::net;
+
+/// The address on which fcserver listens by default.
+const DEFAULT_TARGET: &str = "127.0.0.1:7890";
+/// Set pixel colors, the data is a sequence of RGB triplets.
+const COMMAND_SET_PIXEL_COLORS: u8 = 0;
+/// The length of a message is stored in 16 bits.
+const MAX_DATA_LEN: usize = 0xffff;
+
+/// Builds an OPC message consisting of the 4 byte header (channel, command, length) and the RGB
+/// data of the frame.
+fn set_pixel_colors(channel: u8, frame: &[Pixel]) -> Vec<u8> {
+    let mut msg = Vec::with_capacity(4 + frame.len() * 3);
+    msg.push(channel);
+    msg.push(COMMAND_SET_PIXEL_COLORS);
+    msg.write_u16::<BigEndian>((frame.len() * 3) as u16)
+        .unwrap();
+    msg.extend(frame.iter().flat_map(|p| [p.r, p.g, p.b]));
+    msg
+}
+
+pub struct Opc {
+    target: String,
+    channel: u8,
+    conn: Option<net::TcpStream>,
+}
+
+impl Opc {
+    pub fn new(target: String, channel: u8) -> Opc {
+        let mut opc = Opc {
+            target,
+            channel,
+            conn: None,
+        };
+        // Try to set up an initial connection.
+        let _ = opc.connection();
+        opc
+    }
+
+    fn connection(&mut self) -> io::Result<&mut net::TcpStream> {
+        if let Some(ref mut conn) = self.conn {
+            return Ok(conn);
+        }
+
+        let conn = net::TcpStream::connect(self.target.as_str())?;
+        conn.set_nodelay(true)?;
+        self.conn = Some(conn);
+        Ok(self.conn.as_mut().unwrap())
+    }
+}
+
+impl Output for Opc {
+    fn output_frame(&mut self, frame: &[Pixel]) -> io::Result<()> {
+        let msg = set_pixel_colors(self.channel, frame);
+        // Frames are dropped while the server is unreachable so ledcat keeps running until it is
+        // back.
+        let rs = match self.connection() {
+            Ok(conn) => conn.write_all(&msg),
+            Err(_) => return Ok(()),
+        };
+        if let Err(err) = rs {
+            eprintln!(
+                "opc: lost connection to {} ({}), reconnecting",
+                self.target, err
+            );
+            self.conn = None;
+        }
+        Ok(())
+    }
+}
+
+pub fn command() -> clap::Command {
+    clap::Command::new("opc")
+        .about("Send frames to an Open Pixel Control server like fcserver")
+        .arg(
+            clap::arg!(-t --target <value> "The host:port of the server")
+                .default_value(DEFAULT_TARGET),
+        )
+        .arg(
+            clap::arg!(-c --channel <value> "The channel to send to, 0 sends to all channels")
+                .value_parser(clap::value_parser!(u8))
+                .default_value("0"),
+        )
+}
+
+pub fn from_command(args: &clap::ArgMatches, gargs: &GlobalArgs) -> io::Result<FromCommand> {
+    let dimensions = gargs.dimensions()?;
+    if dimensions.size() * 3 > MAX_DATA_LEN {
+        return Err(io::Error::other(format!(
+            "an OPC message can hold up to {} pixels, got {}",
+            MAX_DATA_LEN / 3,
+            dimensions.size()
+        )));
+    }
+    Ok(FromCommand::Output(Box::new(Opc::new(
+        args.get_one::<String>("target").unwrap().clone(),
+        *args.get_one::<u8>("channel").unwrap(),
+    ))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn encode_set_pixel_colors() {
+        let frame = [Pixel { r: 1, g: 2, b: 3 }, Pixel { r: 4, g: 5, b: 6 }];
+        assert_eq!(
+            vec![7, 0, 0, 6, 1, 2, 3, 4, 5, 6],
+            set_pixel_colors(7, &frame)
+        );
+    }
+
+    #[test]
+    fn reconnect() {
+        let listener = net::TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let mut opc = Opc::new(listener.local_addr().unwrap().to_string(), 0);
+        let frame = [Pixel { r: 1, g: 2, b: 3 }];
+
+        let (mut server, _) = listener.accept().unwrap();
+        opc.output_frame(&frame).unwrap();
+        let mut buf = [0; 7];
+        server.read_exact(&mut buf).unwrap();
+        assert_eq!([0, 0, 0, 3, 1, 2, 3], buf);
+
+        // Writes to a closed stream only fail after the peer has reset the connection, so keep
+        // sending until the output notices and connects again.
+        drop(server);
+        listener.set_nonblocking(true).unwrap();
+        let mut server = loop {
+            opc.output_frame(&frame).unwrap();
+            if let Ok((server, _)) = listener.accept() {
+                break server;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        };
+        server.set_nonblocking(false).unwrap();
+        opc.output_frame(&frame).unwrap();
+        server.read_exact(&mut buf).unwrap();
+        assert_eq!([0, 0, 0, 3, 1, 2, 3], buf);
+    }
+}
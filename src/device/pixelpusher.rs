@@ -0,0 +1,382 @@
+use crate::device::*;
+use crate::util;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use net2::unix::UnixUdpBuilderExt;
+use std::collections;
+use std::io::{self, Write};
+use std::net;
+use std::sync;
+use std::thread;
+use std::time;
+
+/// The port on which controllers broadcast their beacons.
+const DISCOVERY_PORT: u16 = 7331;
+/// The port pixel data is sent to if the controller does not specify one.
+const DEFAULT_DATA_PORT: u16 = 9897;
+/// The device type of PixelPushers in the beacon header. Other devices use the same discovery
+/// protocol.
+const DEVICE_TYPE_PIXELPUSHER: u8 = 2;
+
+/// The parts of a PixelPusher beacon that are needed to send pixel data.
+///
+/// Beacons start with a header that is shared by all devices of the Universal Discovery Protocol,
+/// all multi-byte values are little endian:
+///
+/// | Offset | Size | Field              |
+/// |--------|------|--------------------|
+/// | 0      | 6    | MAC address        |
+/// | 6      | 4    | IPv4 address       |
+/// | 10     | 1    | Device type        |
+/// | 11     | 1    | Protocol version   |
+/// | 12     | 2    | Vendor ID          |
+/// | 14     | 2    | Product ID         |
+/// | 16     | 2    | Hardware revision  |
+/// | 18     | 2    | Software revision  |
+/// | 20     | 4    | Link speed         |
+///
+/// Followed by the PixelPusher specific part:
+///
+/// | Offset | Size | Field                 |
+/// |--------|------|-----------------------|
+/// | 24     | 1    | Strips attached       |
+/// | 25     | 1    | Max strips per packet |
+/// | 26     | 2    | Pixels per strip      |
+/// | 28     | 4    | Update period in µs   |
+/// | 32     | 4    | Power total           |
+/// | 36     | 4    | Delta sequence        |
+/// | 40     | 4    | Controller ordinal    |
+/// | 44     | 4    | Group ordinal         |
+/// | 48     | 2    | Art-Net universe      |
+/// | 50     | 2    | Art-Net channel       |
+/// | 52     | 2    | Data port             |
+///
+/// Older firmware sends shorter beacons without the data port.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Beacon {
+    mac: [u8; 6],
+    ip: net::Ipv4Addr,
+    strips_attached: u8,
+    max_strips_per_packet: u8,
+    pixels_per_strip: u16,
+    update_period: u32,
+    controller_ordinal: i32,
+    group_ordinal: i32,
+    port: u16,
+}
+
+impl Beacon {
+    fn parse(buf: &[u8]) -> io::Result<Beacon> {
+        if buf.len() < 48 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "beacon is too short",
+            ));
+        }
+        if buf[10] != DEVICE_TYPE_PIXELPUSHER {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("device type {} is not a PixelPusher", buf[10]),
+            ));
+        }
+        let mut rdr = io::Cursor::new(&buf[24..]);
+        let beacon = Beacon {
+            mac: buf[0..6].try_into().unwrap(),
+            ip: net::Ipv4Addr::new(buf[6], buf[7], buf[8], buf[9]),
+            strips_attached: rdr.read_u8()?,
+            max_strips_per_packet: rdr.read_u8()?.max(1),
+            pixels_per_strip: rdr.read_u16::<LittleEndian>()?,
+            update_period: rdr.read_u32::<LittleEndian>()?,
+            controller_ordinal: {
+                rdr.set_position(40 - 24);
+                rdr.read_i32::<LittleEndian>()?
+            },
+            group_ordinal: rdr.read_i32::<LittleEndian>()?,
+            port: {
+                rdr.set_position(52 - 24);
+                match rdr.read_u16::<LittleEndian>() {
+                    Ok(0) | Err(_) => DEFAULT_DATA_PORT,
+                    Ok(port) => port,
+                }
+            },
+        };
+        Ok(beacon)
+    }
+
+    fn num_pixels(&self) -> usize {
+        self.strips_attached as usize * self.pixels_per_strip as usize
+    }
+}
+
+pub struct PixelPusher {
+    socket: net::UdpSocket,
+    beacon: Beacon,
+    sequence: u32,
+}
+
+impl Output for PixelPusher {
+    fn output_frame(&mut self, frame: &[Pixel]) -> io::Result<()> {
+        let packets = data_packets(frame, &self.beacon, &mut self.sequence)?;
+        let addr = (self.beacon.ip, self.beacon.port);
+        for (i, packet) in packets.iter().enumerate() {
+            if i > 0 {
+                // The controller needs some time to process each packet.
+                thread::sleep(time::Duration::from_micros(
+                    self.beacon.update_period.into(),
+                ));
+            }
+            self.socket.send_to(packet, addr)?;
+        }
+        Ok(())
+    }
+}
+
+/// Divides a frame over the strips of the controller and packs them into as few packets as
+/// possible.
+///
+/// Each packet starts with a sequence number, followed by the strip number and RGB data of each
+/// strip in it.
+fn data_packets(frame: &[Pixel], beacon: &Beacon, sequence: &mut u32) -> io::Result<Vec<Vec<u8>>> {
+    let strip_len = beacon.pixels_per_strip as usize;
+    let strips: Vec<_> = frame.chunks(strip_len.max(1)).enumerate().collect();
+    strips
+        .chunks(beacon.max_strips_per_packet as usize)
+        .map(|strips| {
+            let mut packet = Vec::new();
+            packet.write_u32::<LittleEndian>(*sequence)?;
+            *sequence = sequence.wrapping_add(1);
+            for (strip, pixels) in strips {
+                packet.write_u8(*strip as u8)?;
+                for i in 0..strip_len {
+                    let pix = pixels.get(i).copied().unwrap_or_default();
+                    packet.write_all(&[pix.r, pix.g, pix.b])?;
+                }
+            }
+            Ok(packet)
+        })
+        .collect()
+}
+
+pub fn command() -> clap::Command {
+    clap::Command::new("pixelpusher")
+        .about("Send frames to a PixelPusher controller")
+        .arg(clap::arg!(-t --target <value> "The IP address of the controller to use. If not set, the first controller that is found is used")
+            .value_parser(clap::value_parser!(net::Ipv4Addr))
+            .conflicts_with("discover"))
+        .arg(clap::arg!(-d --discover "Discover PixelPusher controllers")
+            .conflicts_with("target"))
+        .arg(clap::arg!(--"discover-timeout" <seconds> "How long to wait for the beacon of a controller")
+            .value_parser(clap::value_parser!(u64))
+            .default_value("5"))
+}
+
+pub fn from_command(args: &clap::ArgMatches, gargs: &GlobalArgs) -> io::Result<FromCommand> {
+    if args.get_flag("discover") {
+        if let Err(err) = tui_discover(gargs.verbosity) {
+            eprintln!("{}", err);
+        }
+        return Ok(FromCommand::SubcommandHandled);
+    }
+
+    let dimensions = gargs.dimensions()?;
+    let target = args.get_one::<net::Ipv4Addr>("target").copied();
+    let timeout = time::Duration::from_secs(*args.get_one::<u64>("discover-timeout").unwrap());
+    let discovery_stream = discover(gargs.verbosity);
+    let deadline = time::Instant::now() + timeout;
+    let beacon = loop {
+        let remaining = deadline.saturating_duration_since(time::Instant::now());
+        match discovery_stream.recv_timeout(remaining) {
+            Ok(Ok(beacon)) if target.is_none_or(|ip| ip == beacon.ip) => break beacon,
+            Ok(Ok(_)) => continue,
+            Ok(Err(err)) => return Err(err),
+            Err(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "no PixelPusher was found, check the network or try --discover",
+                ))
+            }
+        }
+    };
+    if dimensions.size() > beacon.num_pixels() {
+        return Err(io::Error::other(format!(
+            "the geometry has {} pixels, but the PixelPusher at {} only has {}",
+            dimensions.size(),
+            beacon.ip,
+            beacon.num_pixels()
+        )));
+    }
+
+    let socket = net::UdpSocket::bind(("0.0.0.0", 0))?;
+    Ok(FromCommand::Output(Box::new(PixelPusher {
+        socket,
+        beacon,
+        sequence: 0,
+    })))
+}
+
+fn tui_discover(verbosity: u8) -> io::Result<()> {
+    let discovery_stream = discover(verbosity);
+    let mut discovered: collections::HashSet<[u8; 6]> = collections::HashSet::new();
+
+    let (close_tx, close_rx) = sync::mpsc::sync_channel(0);
+    thread::spawn(move || {
+        let mut out = io::stderr();
+        for ch in ['|', '/', '-', '\\'].iter().cycle() {
+            if close_rx.try_recv().is_ok() {
+                break;
+            }
+            write!(&mut out, "\r{}", ch).unwrap();
+            out.flush().unwrap();
+            thread::sleep(time::Duration::from_millis(100));
+        }
+    });
+
+    for result in discovery_stream {
+        let beacon = match result {
+            Ok(beacon) => beacon,
+            Err(err) => {
+                close_tx.send(()).unwrap();
+                eprint!("\r");
+                return Err(err);
+            }
+        };
+        if !discovered.contains(&beacon.mac) {
+            let ip_str = format!("{}", beacon.ip); // Padding only works with strings. :(
+            eprintln!(
+                "\r{: <15} -> {} strips of {} pixels (group {}, controller {})",
+                ip_str,
+                beacon.strips_attached,
+                beacon.pixels_per_strip,
+                beacon.group_ordinal,
+                beacon.controller_ordinal,
+            );
+        }
+        discovered.insert(beacon.mac);
+    }
+    Ok(())
+}
+
+/// Continuously listens for beacons of PixelPusher controllers.
+///
+/// With a verbosity of 1 or higher, every received packet is dumped to stderr.
+fn discover(verbosity: u8) -> sync::mpsc::Receiver<io::Result<Beacon>> {
+    let (tx, rx) = sync::mpsc::channel();
+
+    thread::spawn(move || {
+        macro_rules! try_or_send {
+            ($expression:expr) => {
+                match $expression {
+                    Ok(val) => val,
+                    Err(err) => {
+                        let _ = tx.send(Err(err));
+                        return;
+                    }
+                }
+            };
+        }
+
+        let socket = {
+            let b = try_or_send!(net2::UdpBuilder::new_v4());
+            try_or_send!(b.reuse_address(true));
+            try_or_send!(b.reuse_port(true));
+            try_or_send!(b.bind(("0.0.0.0", DISCOVERY_PORT)))
+        };
+
+        loop {
+            let mut recv_buf = [0; 1024];
+            let (len, sender_addr) = try_or_send!(socket.recv_from(&mut recv_buf));
+            if verbosity >= 1 {
+                eprint!(
+                    "\r{} bytes from {}:\n{}",
+                    len,
+                    sender_addr,
+                    util::hex_dump(&recv_buf[..len])
+                );
+            }
+            match Beacon::parse(&recv_buf[..len]) {
+                Ok(beacon) => {
+                    if tx.send(Ok(beacon)).is_err() {
+                        return;
+                    }
+                }
+                Err(err) if verbosity >= 1 => eprintln!("\rIgnored: {}", err),
+                Err(_) => (),
+            }
+        }
+    });
+
+    rx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn beacon_packet(port: Option<u16>) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&[0xd8, 0x80, 0x39, 0x01, 0x02, 0x03]); // MAC
+        buf.extend_from_slice(&[192, 168, 1, 42]); // IP
+        buf.push(DEVICE_TYPE_PIXELPUSHER);
+        buf.push(1); // Protocol version
+        buf.extend_from_slice(&[0; 12]); // Vendor, product, revisions and link speed
+        buf.push(8); // Strips attached
+        buf.push(3); // Max strips per packet
+        buf.write_u16::<LittleEndian>(240).unwrap();
+        buf.write_u32::<LittleEndian>(1000).unwrap(); // Update period
+        buf.extend_from_slice(&[0; 8]); // Power total and delta sequence
+        buf.write_i32::<LittleEndian>(2).unwrap(); // Controller ordinal
+        buf.write_i32::<LittleEndian>(1).unwrap(); // Group ordinal
+        if let Some(port) = port {
+            buf.extend_from_slice(&[0; 4]); // Art-Net universe and channel
+            buf.write_u16::<LittleEndian>(port).unwrap();
+        }
+        buf
+    }
+
+    #[test]
+    fn parse_beacon() {
+        let beacon = Beacon::parse(&beacon_packet(Some(5078))).unwrap();
+        assert_eq!(
+            Beacon {
+                mac: [0xd8, 0x80, 0x39, 0x01, 0x02, 0x03],
+                ip: net::Ipv4Addr::new(192, 168, 1, 42),
+                strips_attached: 8,
+                max_strips_per_packet: 3,
+                pixels_per_strip: 240,
+                update_period: 1000,
+                controller_ordinal: 2,
+                group_ordinal: 1,
+                port: 5078,
+            },
+            beacon
+        );
+        assert_eq!(8 * 240, beacon.num_pixels());
+
+        // Older firmware does not announce a port.
+        let beacon = Beacon::parse(&beacon_packet(None)).unwrap();
+        assert_eq!(DEFAULT_DATA_PORT, beacon.port);
+
+        let mut packet = beacon_packet(None);
+        packet[10] = 1;
+        assert!(Beacon::parse(&packet).is_err());
+        assert!(Beacon::parse(&packet[..30]).is_err());
+    }
+
+    #[test]
+    fn strips_per_packet() {
+        let mut beacon = Beacon::parse(&beacon_packet(None)).unwrap();
+        beacon.pixels_per_strip = 2;
+        beacon.max_strips_per_packet = 2;
+        let frame: Vec<_> = (0..5).map(|i| Pixel { r: i, g: i, b: i }).collect();
+        let mut sequence = 7;
+        let packets = data_packets(&frame, &beacon, &mut sequence).unwrap();
+        assert_eq!(9, sequence);
+        assert_eq!(
+            vec![
+                vec![7, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 3, 3, 3],
+                // The last strip is only partially covered by the frame.
+                vec![8, 0, 0, 0, 2, 4, 4, 4, 0, 0, 0],
+            ],
+            packets
+        );
+    }
+}
@@ -0,0 +1,206 @@
+use crate::device::*;
+use std::fs;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time;
+
+/// The location where the kernel mounts the PWM sysfs interface.
+pub const DEFAULT_BASE: &str = "/sys/class/pwm";
+
+/// A PWM channel configured through the Linux sysfs interface.
+///
+/// Like the sysfs GPIO output, the duty cycle file is kept open so updating it costs a single
+/// write.
+struct Channel {
+    chip_dir: PathBuf,
+    channel: u32,
+    // Whether the channel was exported by us and should be unexported when dropped.
+    exported: bool,
+    duty_cycle: fs::File,
+}
+
+impl Channel {
+    /// Exports the channel if that has not been done already and enables it with the specified
+    /// period.
+    fn open(chip_dir: &Path, channel: u32, period_ns: u64) -> io::Result<Channel> {
+        let channel_dir = chip_dir.join(format!("pwm{}", channel));
+        let exported = !channel_dir.exists();
+        if exported {
+            fs::write(chip_dir.join("export"), format!("{}", channel))?;
+            // The kernel and udev may take a moment to set up the directory and its permissions.
+            let start = time::Instant::now();
+            while !channel_dir.exists() && start.elapsed() < time::Duration::from_secs(1) {
+                thread::sleep(time::Duration::from_millis(10));
+            }
+        }
+        // The duty cycle may not exceed the period, so it is reset before the period is changed.
+        fs::write(channel_dir.join("duty_cycle"), "0\n")?;
+        fs::write(channel_dir.join("period"), format!("{}\n", period_ns))?;
+        fs::write(channel_dir.join("enable"), "1\n")?;
+        let duty_cycle = fs::OpenOptions::new()
+            .write(true)
+            .open(channel_dir.join("duty_cycle"))?;
+        Ok(Channel {
+            chip_dir: chip_dir.to_path_buf(),
+            channel,
+            exported,
+            duty_cycle,
+        })
+    }
+
+    fn set_duty_cycle(&self, duty_ns: u64) -> io::Result<()> {
+        self.duty_cycle
+            .write_all_at(format!("{}\n", duty_ns).as_bytes(), 0)
+    }
+}
+
+impl Drop for Channel {
+    fn drop(&mut self) {
+        // Best effort, failures are ignored.
+        let channel_dir = self.chip_dir.join(format!("pwm{}", self.channel));
+        let _ = fs::write(channel_dir.join("enable"), "0\n");
+        if self.exported {
+            let _ = fs::write(self.chip_dir.join("unexport"), format!("{}", self.channel));
+        }
+    }
+}
+
+/// Pwm drives a single LED or an analog RGB strip with PWM channels. The brightness of each
+/// channel is the average of all pixels.
+pub struct Pwm {
+    channels: Vec<Channel>,
+    period_ns: u64,
+}
+
+impl Pwm {
+    /// Opens either a single channel that is driven by the brightness of the pixels, or three
+    /// channels that are driven by their red, green and blue components.
+    pub fn open(
+        base: impl AsRef<Path>,
+        chip: u32,
+        channels: &[u32],
+        period_ns: u64,
+    ) -> io::Result<Pwm> {
+        if channels.len() != 1 && channels.len() != 3 {
+            return Err(io::Error::other("either 1 or 3 PWM channels must be set"));
+        }
+        let chip_dir = base.as_ref().join(format!("pwmchip{}", chip));
+        let channels = channels
+            .iter()
+            .map(|&channel| Channel::open(&chip_dir, channel, period_ns))
+            .collect::<io::Result<_>>()?;
+        Ok(Pwm {
+            channels,
+            period_ns,
+        })
+    }
+
+    /// Computes the values of the channels, each in the range 0-255.
+    fn levels(&self, frame: &[Pixel]) -> Vec<u8> {
+        let mut sum = [0u64; 3];
+        for pix in frame {
+            for (sum, v) in sum.iter_mut().zip([pix.r, pix.g, pix.b]) {
+                *sum += u64::from(v);
+            }
+        }
+        let n = frame.len().max(1) as u64;
+        match self.channels.len() {
+            1 => vec![(sum.iter().sum::<u64>() / (n * 3)) as u8],
+            _ => sum.iter().map(|s| (s / n) as u8).collect(),
+        }
+    }
+}
+
+impl Output for Pwm {
+    fn output_frame(&mut self, frame: &[Pixel]) -> io::Result<()> {
+        for (channel, level) in self.channels.iter().zip(self.levels(frame)) {
+            channel.set_duty_cycle(self.period_ns * u64::from(level) / 255)?;
+        }
+        Ok(())
+    }
+
+    fn default_dimensions(&self) -> Option<Dimensions> {
+        Some(Dimensions { w: 1, h: 1 })
+    }
+}
+
+pub fn command() -> clap::Command {
+    clap::Command::new("pwm")
+        .about("Drive a single LED or an analog RGB strip using the PWM sysfs interface of Linux")
+        .arg(
+            clap::arg!(--chip <n> "The number of the PWM chip")
+                .value_parser(clap::value_parser!(u32))
+                .default_value("0"),
+        )
+        .arg(
+            clap::arg!(--channel <n> ... "The PWM channel to use, or three channels for red, green and blue")
+                .value_parser(clap::value_parser!(u32))
+                .required(true),
+        )
+        .arg(
+            clap::arg!(--"period-ns" <ns> "The period of the PWM signal in nanoseconds")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .default_value("1000000"),
+        )
+}
+
+pub fn from_command(args: &clap::ArgMatches, _: &GlobalArgs) -> io::Result<FromCommand> {
+    let channels: Vec<u32> = args.get_many::<u32>("channel").unwrap().copied().collect();
+    Ok(FromCommand::Output(Box::new(Pwm::open(
+        DEFAULT_BASE,
+        *args.get_one::<u32>("chip").unwrap(),
+        &channels,
+        *args.get_one::<u64>("period-ns").unwrap(),
+    )?)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn read_value(path: impl AsRef<Path>) -> String {
+        let value = fs::read_to_string(path).unwrap();
+        value.lines().next().unwrap_or_default().to_string()
+    }
+
+    #[test]
+    fn rgb_channels() {
+        let tmp = tempdir().unwrap();
+        let chip_dir = tmp.path().join("pwmchip1");
+        for channel in ["pwm0", "pwm1", "pwm2"] {
+            fs::create_dir_all(chip_dir.join(channel)).unwrap();
+        }
+
+        let mut pwm = Pwm::open(tmp.path(), 1, &[0, 1, 2], 1000).unwrap();
+        assert_eq!("1000", read_value(chip_dir.join("pwm0/period")));
+        assert_eq!("1", read_value(chip_dir.join("pwm0/enable")));
+        let frame = [
+            Pixel { r: 255, g: 0, b: 0 },
+            Pixel {
+                r: 255,
+                g: 102,
+                b: 0,
+            },
+        ];
+        pwm.output_frame(&frame).unwrap();
+        assert_eq!("1000", read_value(chip_dir.join("pwm0/duty_cycle")));
+        assert_eq!("200", read_value(chip_dir.join("pwm1/duty_cycle")));
+        assert_eq!("0", read_value(chip_dir.join("pwm2/duty_cycle")));
+
+        // The channels were already exported, so they are only disabled.
+        drop(pwm);
+        assert_eq!("0", read_value(chip_dir.join("pwm0/enable")));
+        assert!(!chip_dir.join("unexport").exists());
+    }
+
+    #[test]
+    fn single_channel() {
+        let tmp = tempdir().unwrap();
+        fs::create_dir_all(tmp.path().join("pwmchip0/pwm3")).unwrap();
+        let pwm = Pwm::open(tmp.path(), 0, &[3], 255).unwrap();
+        assert_eq!(vec![85], pwm.levels(&[Pixel { r: 255, g: 0, b: 0 }]));
+        assert!(Pwm::open(tmp.path(), 0, &[3, 4], 255).is_err());
+    }
+}
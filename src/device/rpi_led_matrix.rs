@@ -9,6 +9,7 @@ pub struct LedMatrix {
     backbuffer: *mut LedCanvas,
     width: usize,
     height: usize,
+    refresh_limit: Option<u32>,
 }
 
 unsafe impl Send for LedMatrix {}
@@ -29,6 +30,18 @@ impl Output for LedMatrix {
         }
         Ok(())
     }
+
+    fn max_frame_rate(&self) -> Option<u32> {
+        // Swapping happens on vsync, so frames can not be shown faster than the display refreshes.
+        self.refresh_limit
+    }
+
+    fn default_dimensions(&self) -> Option<Dimensions> {
+        Some(Dimensions {
+            w: self.width,
+            h: self.height,
+        })
+    }
 }
 
 impl Drop for LedMatrix {
@@ -72,13 +85,17 @@ pub fn command() -> clap::Command {
             .value_parser(["direct", "stripe", "checker", "spiral", "strip", "0", "1", "2", "3", "4"]))
         .arg(clap::arg!(--"led-rgb-sequence" <value> "Allows swapping of subpixels")
             .default_value("RGB"))
+        .arg(clap::arg!(--"led-brightness" <percent> "The brightness of the display in percent")
+            .value_parser(clap::value_parser!(i32).range(1..=100)))
+        .arg(clap::arg!(--"led-show-refresh" "Show the refresh rate of the display on the terminal"))
+        .arg(clap::arg!(--"led-limit-refresh" <hz> "Limit the refresh rate of the display. Frames are not sent faster than this when --framerate is higher")
+            .value_parser(clap::value_parser!(u32).range(1..)))
 }
 
 pub fn from_command(args: &clap::ArgMatches, gargs: &GlobalArgs) -> io::Result<FromCommand> {
     unsafe {
         let mut options: RGBLedMatrixOptions = mem::zeroed();
 
-        let dimensions = gargs.dimensions()?;
         let chain_length = args.get_one::<i32>("led-chain").copied();
         let cols = args.get_one::<i32>("led-cols").copied();
         let parallel = args.get_one::<i32>("led-parallel").copied();
@@ -86,13 +103,10 @@ pub fn from_command(args: &clap::ArgMatches, gargs: &GlobalArgs) -> io::Result<F
         // X = cols * chain_length
         let (calc_cols, calc_chain_length) = match (cols, chain_length) {
             (Some(c), Some(l)) => (c, l),
-            (Some(c), None) => (c, dimensions.w as i32 / c),
-            (None, Some(l)) => (dimensions.w as i32 / l, l),
+            (Some(c), None) => (c, gargs.dimensions()?.w as i32 / c),
+            (None, Some(l)) => (gargs.dimensions()?.w as i32 / l, l),
             (None, None) => {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    "Either --chain or --cols must be set",
-                ));
+                return Err(io::Error::other("Either --chain or --cols must be set"));
             }
         };
         options.cols = calc_cols;
@@ -100,13 +114,10 @@ pub fn from_command(args: &clap::ArgMatches, gargs: &GlobalArgs) -> io::Result<F
         // Y = rows * parallel
         let (calc_rows, calc_parallel) = match (rows, parallel) {
             (Some(r), Some(p)) => (r, p),
-            (Some(r), None) => (r, dimensions.h as i32 / r),
-            (None, Some(p)) => (dimensions.w as i32 / p, p),
+            (Some(r), None) => (r, gargs.dimensions()?.h as i32 / r),
+            (None, Some(p)) => (gargs.dimensions()?.w as i32 / p, p),
             (None, None) => {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    "Either --rows or --parallel must be set",
-                ));
+                return Err(io::Error::other("Either --rows or --parallel must be set"));
             }
         };
         options.rows = calc_rows;
@@ -152,20 +163,33 @@ pub fn from_command(args: &clap::ArgMatches, gargs: &GlobalArgs) -> io::Result<F
         if let Some(s) = args.get_one::<CString>("led-rgb-sequence") {
             options.led_rgb_sequence = s.as_ptr() as _;
         }
+        if let Some(b) = args.get_one::<i32>("led-brightness") {
+            options.brightness = *b;
+        }
+        if args.get_flag("led-show-refresh") {
+            options.set_show_refresh_rate(1);
+        }
+        let refresh_limit = args.get_one::<u32>("led-limit-refresh").copied();
+        if let Some(hz) = refresh_limit {
+            options.limit_refresh_rate_hz = hz as i32;
+        }
 
         let led_matrix = led_matrix_create_from_options(&mut options, &mut 0, ptr::null_mut());
         if led_matrix.is_null() {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                "could not initialize LED Matrix driver",
-            ));
+            return Err(io::Error::other("could not initialize LED Matrix driver"));
         }
         let backbuffer = led_matrix_create_offscreen_canvas(led_matrix);
+        // Without a geometry, the display is assumed to be covered by the panels exactly.
+        let dimensions = gargs.dimensions.unwrap_or(Dimensions {
+            w: (calc_cols * calc_chain_length) as usize,
+            h: (calc_rows * calc_parallel) as usize,
+        });
         Ok(FromCommand::Output(Box::new(LedMatrix {
             led_matrix,
             backbuffer,
             width: dimensions.w,
             height: dimensions.h,
+            refresh_limit,
         })))
     }
 }
@@ -1,61 +1,615 @@
 use crate::device::*;
+use nix::sys::signal;
 use std::io::Write;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd};
+use std::sync::atomic::{AtomicBool, Ordering};
 
-pub struct AnsiDisplay {
+nix::ioctl_read_bad!(tiocgwinsz, nix::libc::TIOCGWINSZ, nix::libc::winsize);
+
+/// Set by the SIGWINCH handler to signal that the size of the terminal should be queried again.
+static RESIZED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn handle_sigwinch(_: nix::libc::c_int) {
+    RESIZED.store(true, Ordering::Relaxed);
+}
+
+/// Returns the number of columns and rows of the terminal attached to the file descriptor.
+fn terminal_size(tty: BorrowedFd<'_>) -> io::Result<(usize, usize)> {
+    let mut ws = nix::libc::winsize {
+        ws_row: 0,
+        ws_col: 0,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    io_err!(unsafe { tiocgwinsz(tty.as_raw_fd(), &mut ws) })?;
+    if ws.ws_col == 0 || ws.ws_row < 2 {
+        return Err(io::Error::other("terminal has no usable size"));
+    }
+    Ok((ws.ws_col as usize, ws.ws_row as usize))
+}
+
+/// Computes the largest display geometry that fits in the terminal attached to stdout.
+///
+/// The last row is left empty so the cursor does not make the terminal scroll.
+pub fn terminal_dimensions(density: Density) -> io::Result<Dimensions> {
+    terminal_dimensions_of(io::stdout().as_fd(), density)
+}
+
+fn terminal_dimensions_of(tty: BorrowedFd<'_>, density: Density) -> io::Result<Dimensions> {
+    let (cols, rows) = terminal_size(tty)?;
+    let (cell_w, cell_h) = density.cell_size();
+    Ok(Dimensions {
+        w: cols * cell_w,
+        h: (rows - 1) * cell_h,
+    })
+}
+
+/// Installs the SIGWINCH handler that lets displays know when to recompute their view.
+fn watch_resize() -> io::Result<()> {
+    let handler = signal::SigAction::new(
+        signal::SigHandler::Handler(handle_sigwinch),
+        signal::SaFlags::SA_RESTART,
+        signal::SigSet::empty(),
+    );
+    io_err!(unsafe { signal::sigaction(signal::Signal::SIGWINCH, &handler) })?;
+    Ok(())
+}
+
+/// The kind of color escape sequences written to the terminal.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ColorMode {
+    /// 24-bit colors.
+    TrueColor,
+    /// The xterm 256 color palette, for terminals that do not support truecolor.
+    Ansi256,
+}
+
+impl ColorMode {
+    fn write_color(self, buf: &mut Vec<u8>, layer: u8, pix: Pixel) -> io::Result<()> {
+        match self {
+            ColorMode::TrueColor => write!(buf, "\x1b[{};2;{};{};{}m", layer, pix.r, pix.g, pix.b),
+            ColorMode::Ansi256 => write!(buf, "\x1b[{};5;{}m", layer, ansi256(pix)),
+        }
+    }
+}
+
+/// The number of pixels that are rendered in a single character cell.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Density {
+    /// 1x2 pixels using the upper half block.
+    Half,
+    /// 2x2 pixels using the quadrant blocks. Only two colors can be shown per cell.
+    Quarter,
+    /// 2x4 pixels using braille dots. Only two colors can be shown per cell.
+    Braille,
+}
+
+impl Density {
+    /// The width and height in pixels of a single character cell.
+    fn cell_size(self) -> (usize, usize) {
+        match self {
+            Density::Half => (1, 2),
+            Density::Quarter => (2, 2),
+            Density::Braille => (2, 4),
+        }
+    }
+
+    pub fn arg() -> clap::Arg {
+        clap::arg!(--density <mode> "The number of pixels shown in each character: half (1x2), quarter (2x2) or braille (2x4). Quarter and braille can only show 2 colors in each character")
+            .default_value("half")
+            .value_parser(["half", "quarter", "braille"])
+    }
+
+    pub fn from_args(args: &clap::ArgMatches) -> Density {
+        match args.get_one::<String>("density").map(String::as_str) {
+            Some("quarter") => Density::Quarter,
+            Some("braille") => Density::Braille,
+            _ => Density::Half,
+        }
+    }
+
+    /// Picks the character that approximates the pixels of a cell, which are in row major order.
+    /// Returns the character and its foreground and background color.
+    fn render_cell(self, pixels: &[Pixel]) -> (char, Pixel, Pixel) {
+        if self == Density::Half {
+            // The top half is colored with the foreground color while the lower half uses the
+            // background.
+            return ('\u{2580}', pixels[0], pixels[1]);
+        }
+        let (mask, fg, bg) = split_colors(pixels);
+        let ch = match self {
+            Density::Quarter => {
+                const QUADRANTS: [char; 16] = [
+                    ' ', '\u{2598}', '\u{259d}', '\u{2580}', '\u{2596}', '\u{258c}', '\u{259e}',
+                    '\u{259b}', '\u{2597}', '\u{259a}', '\u{2590}', '\u{259c}', '\u{2584}',
+                    '\u{2599}', '\u{259f}', '\u{2588}',
+                ];
+                QUADRANTS[mask as usize]
+            }
+            _ => {
+                // The dots of a braille character are numbered column by column, except for the
+                // bottom row which was added later.
+                const DOTS: [u8; 8] = [0x01, 0x08, 0x02, 0x10, 0x04, 0x20, 0x40, 0x80];
+                let bits = (0..8)
+                    .filter(|i| mask & (1 << i) != 0)
+                    .fold(0, |bits, i| bits | DOTS[i]);
+                char::from_u32(0x2800 + u32::from(bits)).unwrap()
+            }
+        };
+        (ch, fg, bg)
+    }
+}
+
+/// Divides the pixels into two groups so that the average color of each group approximates its
+/// pixels best. Returns a mask of the pixels in the first group and the colors of both groups.
+fn split_colors(pixels: &[Pixel]) -> (u8, Pixel, Pixel) {
+    let average = |mask: u8, set: bool| {
+        let (mut sum, mut n) = ([0u32; 3], 0);
+        for (i, pix) in pixels.iter().enumerate() {
+            if (mask & (1 << i) != 0) == set {
+                sum[0] += u32::from(pix.r);
+                sum[1] += u32::from(pix.g);
+                sum[2] += u32::from(pix.b);
+                n += 1;
+            }
+        }
+        match n {
+            0 => Pixel::default(),
+            n => Pixel {
+                r: ((sum[0] + n / 2) / n) as u8,
+                g: ((sum[1] + n / 2) / n) as u8,
+                b: ((sum[2] + n / 2) / n) as u8,
+            },
+        }
+    };
+    let distance = |a: Pixel, b: Pixel| {
+        let d = |a: u8, b: u8| (i32::from(a) - i32::from(b)).pow(2);
+        d(a.r, b.r) + d(a.g, b.g) + d(a.b, b.b)
+    };
+    // Swapping the groups results in the same error, so the last pixel is always put in the
+    // background.
+    (0..1u16 << (pixels.len() - 1))
+        .map(|mask| {
+            let mask = mask as u8;
+            let (fg, bg) = (average(mask, true), average(mask, false));
+            let error: i32 = pixels
+                .iter()
+                .enumerate()
+                .map(|(i, &pix)| distance(pix, if mask & (1 << i) != 0 { fg } else { bg }))
+                .sum();
+            (error, mask, fg, bg)
+        })
+        .min_by_key(|&(error, ..)| error)
+        .map(|(_, mask, fg, bg)| (mask, fg, bg))
+        .unwrap()
+}
+
+/// Finds the index of the color in the xterm 256 color palette that is closest to the pixel.
+///
+/// Only the 6x6x6 color cube and the grayscale ramp are considered, since the 16 system colors
+/// differ between terminals.
+fn ansi256(pix: Pixel) -> u8 {
+    const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+    let cube_index = |v: u8| match v {
+        0..48 => 0,
+        48..115 => 1,
+        _ => (v - 35) / 40,
+    };
+    let (ri, gi, bi) = (cube_index(pix.r), cube_index(pix.g), cube_index(pix.b));
+    let cube = Pixel {
+        r: CUBE_LEVELS[ri as usize],
+        g: CUBE_LEVELS[gi as usize],
+        b: CUBE_LEVELS[bi as usize],
+    };
+
+    // The grayscale ramp runs from 8 to 238 in steps of 10.
+    let avg = ((u16::from(pix.r) + u16::from(pix.g) + u16::from(pix.b)) / 3) as u8;
+    let gray_index = if avg > 238 {
+        23
+    } else {
+        avg.saturating_sub(3) / 10
+    };
+    let level = 8 + 10 * gray_index;
+    let gray = Pixel {
+        r: level,
+        g: level,
+        b: level,
+    };
+
+    let distance = |c: Pixel| {
+        let d = |a: u8, b: u8| (i32::from(a) - i32::from(b)).pow(2);
+        d(pix.r, c.r) + d(pix.g, c.g) + d(pix.b, c.b)
+    };
+    if distance(gray) < distance(cube) {
+        232 + gray_index
+    } else {
+        16 + 36 * ri + 6 * gi + bi
+    }
+}
+
+/// Renders the top-left `view_width` by `view_height` pixels of a frame that is `width` pixels
+/// wide as ANSI escape sequences, starting at the position of the cursor.
+pub fn render_ansi(
+    buf: &mut Vec<u8>,
+    frame: &[Pixel],
+    width: usize,
+    view_width: usize,
+    view_height: usize,
+    mode: ColorMode,
+    density: Density,
+) -> io::Result<()> {
+    // Multiple pixels are rendered at once using block characters that are partially colored
+    // with the foreground color and partially with the background. This neat trick allows us to
+    // render square pixels with a higher density than combining two rectangular characters.
+    let (cell_w, cell_h) = density.cell_size();
+    let mut cell = Vec::with_capacity(cell_w * cell_h);
+    for y in (0..view_height).step_by(cell_h) {
+        for x in (0..view_width).step_by(cell_w) {
+            // Pixels outside of the view are black.
+            cell.clear();
+            cell.extend((y..y + cell_h).flat_map(|y| {
+                (x..x + cell_w).map(move |x| match x < view_width && y < view_height {
+                    true => frame[y * width + x],
+                    false => Pixel::default(),
+                })
+            }));
+            let (ch, fg, bg) = density.render_cell(&cell);
+            // Set the background color.
+            mode.write_color(buf, 48, bg)?;
+            // Set the foreground color.
+            mode.write_color(buf, 38, fg)?;
+            write!(buf, "{}", ch)?;
+        }
+        // Reset to the default background color and jump to the next line.
+        writeln!(buf, "\x1b[0m")?;
+    }
+    Ok(())
+}
+
+pub struct AnsiDisplay<W> {
+    out: W,
     width: usize,
     height: usize,
+    // The part of the frame that fits in the terminal.
+    view_width: usize,
+    view_height: usize,
+    mode: ColorMode,
+    density: Density,
+    /// Shrink frames that do not fit in the terminal instead of cropping them.
+    fit: bool,
+    // Set to the size frames are shrunk to if they do not fit.
+    scaled: Option<Dimensions>,
 
     initial_frame: bool,
 }
 
-impl Output for AnsiDisplay {
+impl<W> AnsiDisplay<W>
+where
+    W: io::Write + AsFd,
+{
+    fn new(out: W, dimensions: Dimensions, mode: ColorMode, density: Density, fit: bool) -> Self {
+        AnsiDisplay {
+            out,
+            width: dimensions.w,
+            height: dimensions.h,
+            view_width: dimensions.w,
+            view_height: dimensions.h,
+            mode,
+            density,
+            fit,
+            scaled: None,
+            initial_frame: true,
+        }
+    }
+
+    fn update_view(&mut self) {
+        let (w, h) = match terminal_dimensions_of(self.out.as_fd(), self.density) {
+            Ok(term) => (term.w, term.h),
+            Err(_) => (self.width, self.height),
+        };
+        self.scaled = None;
+        if self.fit && (self.width > w || self.height > h) {
+            // Keep the aspect ratio so pixels stay square.
+            let scale = f64::min(w as f64 / self.width as f64, h as f64 / self.height as f64);
+            self.scaled = Some(Dimensions {
+                w: ((self.width as f64 * scale) as usize).clamp(1, w),
+                h: ((self.height as f64 * scale) as usize).clamp(1, h),
+            });
+        }
+        let (width, height) = match self.scaled {
+            Some(d) => (d.w, d.h),
+            None => (self.width, self.height),
+        };
+        self.view_width = width.min(w);
+        self.view_height = height.min(h);
+    }
+}
+
+impl<W> Output for AnsiDisplay<W>
+where
+    W: io::Write + AsFd + Send,
+{
     fn output_frame(&mut self, frame: &[Pixel]) -> io::Result<()> {
         // A buffer is used so frames can be written in one go, significantly improving
         // performance.
         let mut buf = Vec::new();
 
-        // Clear the screen so there is no other stuff on it.
-        if self.initial_frame {
+        // Clear the screen so there is no other stuff on it. After the terminal has been resized,
+        // the old contents may have been reflowed so the screen is cleared again.
+        if self.initial_frame || RESIZED.swap(false, Ordering::Relaxed) {
+            self.update_view();
             write!(buf, "\x1b[3J\x1b[H\x1b[2J")?;
             self.initial_frame = false;
         }
         write!(buf, "\x1b[1;1H")?;
+        let (frame, width) = match self.scaled {
+            Some(to) => {
+                let from = Dimensions {
+                    w: self.width,
+                    h: self.height,
+                };
+                (nearest_neighbor(frame, from, to), to.w)
+            }
+            None => (frame.to_vec(), self.width),
+        };
+        render_ansi(
+            &mut buf,
+            &frame,
+            width,
+            self.view_width,
+            self.view_height,
+            self.mode,
+            self.density,
+        )?;
+
+        self.out.write_all(&buf)
+    }
+}
+
+/// Preview wraps an output so every frame is also rendered to the terminal attached to stderr.
+///
+/// Stdout is left alone since it may be the output of the wrapped device.
+pub struct Preview {
+    output: Box<dyn Output>,
+    display: AnsiDisplay<io::Stderr>,
+    dimensions: Dimensions,
+    // Used to undo the transpositions so the preview shows the frame as it was read.
+    transposition: Vec<usize>,
+    scale: usize,
+    buffer: Vec<Pixel>,
+}
+
+impl Preview {
+    pub fn new(
+        output: Box<dyn Output>,
+        dimensions: Dimensions,
+        transposition: Vec<usize>,
+        scale: usize,
+    ) -> io::Result<Preview> {
+        assert_ne!(scale, 0);
+        watch_resize()?;
+        let scaled = Dimensions {
+            w: dimensions.w.div_ceil(scale),
+            h: dimensions.h.div_ceil(scale),
+        };
+        Ok(Preview {
+            output,
+            display: AnsiDisplay::new(
+                io::stderr(),
+                scaled,
+                ColorMode::TrueColor,
+                Density::Half,
+                false,
+            ),
+            dimensions,
+            transposition,
+            scale,
+            buffer: Vec::with_capacity(dimensions.size()),
+        })
+    }
+}
+
+impl Output for Preview {
+    fn output_frame(&mut self, frame: &[Pixel]) -> io::Result<()> {
+        self.output.output_frame(frame)?;
+
+        self.buffer.clear();
+        self.buffer
+            .extend(self.transposition.iter().map(|&i| frame[i]));
+        let small = downsample(&self.buffer, self.dimensions, self.scale);
+        self.display.output_frame(&small)
+    }
+
+    fn color_correction(&self) -> Correction {
+        self.output.color_correction()
+    }
 
-        // Two pixels are rendered at once using the Upper Half Block character. The top half is
-        // colored with the foreground color while the lower half uses the background. This neat
-        // trick allows us to render square pixels with a higher density than combining two
-        // rectangular characters.
-        for y in 0..self.height / 2 + (self.height & 1) {
-            for x in 0..self.width {
-                let pix_hi = &frame[y * 2 * self.width + x];
-                let pix_lo = frame.get((y * 2 + 1) * self.width + x);
-                // Set the background color.
-                if let Some(pix_lo) = pix_lo {
-                    write!(buf, "\x1b[48;2;{};{};{}m", pix_lo.r, pix_lo.g, pix_lo.b)?;
-                } else {
-                    write!(buf, "\x1b[48;2;0m")?;
+    fn color_order(&self) -> ColorOrder {
+        self.output.color_order()
+    }
+
+    fn max_frame_rate(&self) -> Option<u32> {
+        self.output.max_frame_rate()
+    }
+}
+
+/// Resizes a frame by picking the pixel closest to each position in the new frame.
+fn nearest_neighbor(frame: &[Pixel], from: Dimensions, to: Dimensions) -> Vec<Pixel> {
+    (0..to.h)
+        .flat_map(|y| {
+            let sy = (2 * y + 1) * from.h / (2 * to.h);
+            (0..to.w).map(move |x| {
+                let sx = (2 * x + 1) * from.w / (2 * to.w);
+                frame[sy * from.w + sx]
+            })
+        })
+        .collect()
+}
+
+/// Shrinks a frame by averaging each block of `scale` by `scale` pixels into a single pixel.
+fn downsample(frame: &[Pixel], dimensions: Dimensions, scale: usize) -> Vec<Pixel> {
+    if scale == 1 {
+        return frame.to_vec();
+    }
+    let (w, h) = (dimensions.w, dimensions.h);
+    let mut out = Vec::with_capacity(w.div_ceil(scale) * h.div_ceil(scale));
+    for by in (0..h).step_by(scale) {
+        for bx in (0..w).step_by(scale) {
+            let (mut r, mut g, mut b, mut n) = (0u32, 0u32, 0u32, 0u32);
+            for y in by..(by + scale).min(h) {
+                for x in bx..(bx + scale).min(w) {
+                    let pix = frame[y * w + x];
+                    r += u32::from(pix.r);
+                    g += u32::from(pix.g);
+                    b += u32::from(pix.b);
+                    n += 1;
                 }
-                // Set the foreground color.
-                write!(buf, "\x1b[38;2;{};{};{}m", pix_hi.r, pix_hi.g, pix_hi.b)?;
-                write!(buf, "\u{2580}")?;
             }
-            // Reset to the default background color and jump to the next line.
-            writeln!(buf, "\x1b[0m")?;
+            out.push(Pixel {
+                r: (r / n) as u8,
+                g: (g / n) as u8,
+                b: (b / n) as u8,
+            });
         }
-
-        io::stdout().write_all(&buf)
     }
+    out
 }
 
 pub fn command() -> clap::Command {
-    clap::Command::new("show").about("Visualize 2D frames using a text based display")
+    clap::Command::new("show")
+        .about("Visualize 2D frames using a text based display")
+        .arg(clap::arg!(--"color-mode" <mode> "The kind of colors to use. Use 256 for terminals that do not support truecolor")
+            .default_value("truecolor")
+            .value_parser(["truecolor", "256"]))
+        .arg(Density::arg())
+        .arg(clap::arg!(--fit "Shrink frames that do not fit in the terminal instead of cropping them"))
+        .after_help("The geometry defaults to the size of the terminal, this can also be explicitly set with \"--geometry terminal\"")
 }
 
-pub fn from_command(_: &clap::ArgMatches, gargs: &GlobalArgs) -> io::Result<FromCommand> {
+pub fn from_command(args: &clap::ArgMatches, gargs: &GlobalArgs) -> io::Result<FromCommand> {
     let dimensions = gargs.dimensions()?;
-    Ok(FromCommand::Output(Box::new(AnsiDisplay {
-        width: dimensions.w,
-        height: dimensions.h,
-        initial_frame: true,
-    })))
+    let mode = match args.get_one::<String>("color-mode").unwrap().as_str() {
+        "256" => ColorMode::Ansi256,
+        _ => ColorMode::TrueColor,
+    };
+    watch_resize()?;
+    Ok(FromCommand::Output(Box::new(AnsiDisplay::new(
+        io::stdout(),
+        dimensions,
+        mode,
+        Density::from_args(args),
+        args.get_flag("fit"),
+    ))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn downsample_averages_blocks() {
+        let px = |v| Pixel { r: v, g: v, b: v };
+        #[rustfmt::skip]
+        let frame = vec![
+            px(0), px(4), px(10),
+            px(8), px(4), px(20),
+            px(1), px(1), px(30),
+        ];
+        let dimensions = Dimensions { w: 3, h: 3 };
+        assert_eq!(downsample(&frame, dimensions, 1), frame);
+        // The blocks at the right and bottom edges are partial.
+        assert_eq!(
+            downsample(&frame, dimensions, 2),
+            vec![px(4), px(15), px(1), px(30)],
+        );
+    }
+
+    #[test]
+    fn nearest_neighbor_shrinks() {
+        let frame: Vec<Pixel> = (0..16).map(|v| Pixel { r: v, g: v, b: v }).collect();
+        let from = Dimensions { w: 4, h: 4 };
+        let small = nearest_neighbor(&frame, from, Dimensions { w: 2, h: 2 });
+        assert_eq!(
+            vec![5, 7, 13, 15],
+            small.iter().map(|p| p.r).collect::<Vec<_>>()
+        );
+        let small = nearest_neighbor(&frame, from, Dimensions { w: 3, h: 1 });
+        assert_eq!(
+            vec![8, 10, 11],
+            small.iter().map(|p| p.r).collect::<Vec<_>>()
+        );
+        assert_eq!(frame, nearest_neighbor(&frame, from, from));
+    }
+
+    #[test]
+    fn half_block_cells() {
+        let px = |v| Pixel { r: v, g: v, b: v };
+        let frame = vec![px(1), px(2), px(3), px(4), px(5), px(6)];
+        let mut buf = Vec::new();
+        render_ansi(
+            &mut buf,
+            &frame,
+            2,
+            2,
+            3,
+            ColorMode::TrueColor,
+            Density::Half,
+        )
+        .unwrap();
+        let expected = "\x1b[48;2;3;3;3m\x1b[38;2;1;1;1m\u{2580}\x1b[48;2;4;4;4m\x1b[38;2;2;2;2m\u{2580}\x1b[0m\n\
+                        \x1b[48;2;0;0;0m\x1b[38;2;5;5;5m\u{2580}\x1b[48;2;0;0;0m\x1b[38;2;6;6;6m\u{2580}\x1b[0m\n";
+        assert_eq!(expected, String::from_utf8(buf).unwrap());
+    }
+
+    #[test]
+    fn quarter_cells() {
+        let red = Pixel { r: 255, g: 0, b: 0 };
+        let blue = Pixel { r: 0, g: 0, b: 255 };
+        // The last pixel always uses the background color.
+        assert_eq!(
+            ('\u{259e}', blue, red),
+            Density::Quarter.render_cell(&[red, blue, blue, red])
+        );
+        assert_eq!(
+            ('\u{2580}', red, blue),
+            Density::Quarter.render_cell(&[red, red, blue, blue])
+        );
+        // Three colors can not be shown, the two that are closest are merged.
+        let dark_blue = Pixel { r: 0, g: 0, b: 245 };
+        assert_eq!(
+            ('\u{2598}', red, Pixel { r: 0, g: 0, b: 252 }),
+            Density::Quarter.render_cell(&[red, blue, dark_blue, blue])
+        );
+    }
+
+    #[test]
+    fn braille_cells() {
+        let on = Pixel { r: 9, g: 9, b: 9 };
+        let off = Pixel::default();
+        #[rustfmt::skip]
+        let cell = [
+            on,  off,
+            off, on,
+            off, off,
+            on,  off,
+        ];
+        // Dots 1, 5 and 7.
+        assert_eq!(('\u{2851}', on, off), Density::Braille.render_cell(&cell));
+    }
+
+    #[test]
+    fn ansi256_palette() {
+        let idx = |r, g, b| ansi256(Pixel { r, g, b });
+        assert_eq!(16, idx(0, 0, 0));
+        assert_eq!(231, idx(255, 255, 255));
+        assert_eq!(196, idx(255, 0, 0));
+        assert_eq!(46, idx(0, 255, 0));
+        assert_eq!(21, idx(0, 0, 255));
+        assert_eq!(24, idx(0, 95, 135));
+        // Close to a cube color.
+        assert_eq!(214, idx(250, 170, 10));
+        // Grays between the cube levels use the grayscale ramp.
+        assert_eq!(244, idx(128, 128, 128));
+        assert_eq!(232, idx(8, 8, 8));
+    }
 }
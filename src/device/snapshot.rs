@@ -0,0 +1,93 @@
+use crate::device::*;
+use crate::input::png;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+/// Snapshot writes each frame to an image file as it is sent to the wrapped output, after color
+/// correction and transposition.
+///
+/// Files ending in `.png` are written as PNG, anything else as binary PPM. The file is replaced by
+/// every frame, so this is meant to be used together with `--one`.
+pub struct Snapshot {
+    output: Box<dyn Output>,
+    path: PathBuf,
+    dimensions: Dimensions,
+}
+
+impl Snapshot {
+    /// The dimensions should be those of the display after rotating it.
+    pub fn new(output: Box<dyn Output>, path: PathBuf, dimensions: Dimensions) -> Snapshot {
+        Snapshot {
+            output,
+            path,
+            dimensions,
+        }
+    }
+
+    fn write(&self, frame: &[Pixel]) -> io::Result<()> {
+        // Frames that do not match the display, e.g. because pixels are skipped, are shown as a
+        // single row.
+        let dimensions = if frame.len() == self.dimensions.size() {
+            self.dimensions
+        } else {
+            Dimensions {
+                w: frame.len(),
+                h: 1,
+            }
+        };
+        let rgb: Vec<u8> = frame.iter().flat_map(|p| [p.r, p.g, p.b]).collect();
+        if png::is_png(&self.path.to_string_lossy()) {
+            image::save_buffer_with_format(
+                &self.path,
+                &rgb,
+                dimensions.w as u32,
+                dimensions.h as u32,
+                image::ExtendedColorType::Rgb8,
+                image::ImageFormat::Png,
+            )
+            .map_err(io::Error::other)
+        } else {
+            let mut out = io::BufWriter::new(fs::File::create(&self.path)?);
+            write_ppm(&mut out, dimensions, &rgb)?;
+            out.flush()
+        }
+    }
+}
+
+/// Writes an image in the binary PPM (P6) format.
+fn write_ppm(mut w: impl io::Write, dimensions: Dimensions, rgb: &[u8]) -> io::Result<()> {
+    write!(w, "P6\n{} {}\n255\n", dimensions.w, dimensions.h)?;
+    w.write_all(rgb)
+}
+
+impl Output for Snapshot {
+    fn output_frame(&mut self, frame: &[Pixel]) -> io::Result<()> {
+        self.write(frame)?;
+        self.output.output_frame(frame)
+    }
+
+    fn color_correction(&self) -> Correction {
+        self.output.color_correction()
+    }
+
+    fn color_order(&self) -> ColorOrder {
+        self.output.color_order()
+    }
+
+    fn max_frame_rate(&self) -> Option<u32> {
+        self.output.max_frame_rate()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ppm() {
+        let mut buf = Vec::new();
+        write_ppm(&mut buf, Dimensions { w: 2, h: 1 }, &[1, 2, 3, 4, 5, 6]).unwrap();
+        assert_eq!(b"P6\n2 1\n255\n\x01\x02\x03\x04\x05\x06", &buf[..]);
+    }
+}
@@ -0,0 +1,139 @@
+use crate::device::*;
+use byteorder::{BigEndian, WriteBytesExt};
+use std::io::{self, Write};
+use std::net;
+
+/// The UDP port on which TPM2.net receivers listen.
+pub const PORT: u16 = 65506;
+/// The maximum number of data bytes in a single TPM2.net packet.
+pub const MAX_PACKET_SIZE: usize = 1490;
+
+/// The first byte of a serial frame.
+const START_SERIAL: u8 = 0xc9;
+/// The first byte of a TPM2.net packet.
+const START_NET: u8 = 0x9c;
+/// The block type of frames that hold pixel data.
+const BLOCK_TYPE_DATA: u8 = 0xda;
+/// The last byte of both serial frames and TPM2.net packets.
+const END: u8 = 0x36;
+
+fn rgb_data(frame: &[Pixel]) -> io::Result<Vec<u8>> {
+    if frame.len() * 3 > 0xffff {
+        return Err(io::Error::other(format!(
+            "a TPM2 frame can hold up to {} pixels, got {}",
+            0xffff / 3,
+            frame.len()
+        )));
+    }
+    Ok(frame.iter().flat_map(|p| [p.r, p.g, p.b]).collect())
+}
+
+/// Tpm2 writes frames using the serial framing of TPM2.
+pub struct Tpm2 {}
+
+impl Device for Tpm2 {
+    fn write_frame(&self, writer: &mut dyn io::Write, frame: &[Pixel]) -> io::Result<()> {
+        let data = rgb_data(frame)?;
+        let mut buf = Vec::with_capacity(data.len() + 5);
+        buf.write_u8(START_SERIAL)?; // Start Byte
+        buf.write_u8(BLOCK_TYPE_DATA)?; // Block Type
+        buf.write_u16::<BigEndian>(data.len() as u16)?; // Frame Size
+        buf.write_all(&data)?; // Data
+        buf.write_u8(END)?; // End Byte
+        writer.write_all(&buf)
+    }
+}
+
+/// Splits a frame into TPM2.net packets of at most `packet_size` data bytes each. Packets are
+/// numbered starting at 1.
+fn tpm2_net_packets(data: &[u8], packet_size: usize) -> io::Result<Vec<Vec<u8>>> {
+    let num_packets = data.len().div_ceil(packet_size).max(1);
+    if num_packets > 0xff {
+        return Err(io::Error::other(format!(
+            "a frame of {} bytes needs {} packets, but TPM2.net supports up to 255",
+            data.len(),
+            num_packets
+        )));
+    }
+    data.chunks(packet_size)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let mut packet = Vec::with_capacity(chunk.len() + 7);
+            packet.write_u8(START_NET)?; // Start Byte
+            packet.write_u8(BLOCK_TYPE_DATA)?; // Block Type
+            packet.write_u16::<BigEndian>(chunk.len() as u16)?; // Frame Size
+            packet.write_u8(i as u8 + 1)?; // Packet Number
+            packet.write_u8(num_packets as u8)?; // Total Packets
+            packet.write_all(chunk)?; // Data
+            packet.write_u8(END)?; // End Byte
+            Ok(packet)
+        })
+        .collect()
+}
+
+/// Tpm2Net sends frames to a receiver using the UDP form of TPM2.
+pub struct Tpm2Net {
+    socket: net::UdpSocket,
+    target: net::SocketAddr,
+    packet_size: usize,
+}
+
+impl Output for Tpm2Net {
+    fn output_frame(&mut self, frame: &[Pixel]) -> io::Result<()> {
+        let data = rgb_data(frame)?;
+        for packet in tpm2_net_packets(&data, self.packet_size)? {
+            self.socket.send_to(&packet, self.target)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn command() -> clap::Command {
+    clap::Command::new("tpm2")
+        .about("Write frames using the TPM2 serial protocol, or send them to a TPM2.net receiver")
+        .arg(clap::arg!(-t --target <ip> "Send frames to this TPM2.net receiver instead of the output file")
+            .value_parser(clap::value_parser!(net::IpAddr)))
+        .arg(clap::arg!(--"packet-size" <bytes> "The maximum number of data bytes in each TPM2.net packet")
+            .value_parser(clap::value_parser!(u16).range(1..=MAX_PACKET_SIZE as i64))
+            .default_value("1490")
+            .requires("target"))
+}
+
+pub fn from_command(args: &clap::ArgMatches, _: &GlobalArgs) -> io::Result<FromCommand> {
+    let Some(ip) = args.get_one::<net::IpAddr>("target") else {
+        return Ok(FromCommand::Device(Box::new(Tpm2 {})));
+    };
+    Ok(FromCommand::Output(Box::new(Tpm2Net {
+        socket: net::UdpSocket::bind(("0.0.0.0", 0))?,
+        target: net::SocketAddr::new(*ip, PORT),
+        packet_size: *args.get_one::<u16>("packet-size").unwrap() as usize,
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serial_framing() {
+        let frame = [Pixel { r: 1, g: 2, b: 3 }, Pixel { r: 4, g: 5, b: 6 }];
+        let mut buf = Vec::new();
+        Tpm2 {}.write_frame(&mut buf, &frame).unwrap();
+        assert_eq!(vec![0xc9, 0xda, 0, 6, 1, 2, 3, 4, 5, 6, 0x36], buf);
+    }
+
+    #[test]
+    fn net_fragmentation() {
+        let data: Vec<u8> = (0..10).collect();
+        let packets = tpm2_net_packets(&data, 4).unwrap();
+        assert_eq!(
+            vec![
+                vec![0x9c, 0xda, 0, 4, 1, 3, 0, 1, 2, 3, 0x36],
+                vec![0x9c, 0xda, 0, 4, 2, 3, 4, 5, 6, 7, 0x36],
+                vec![0x9c, 0xda, 0, 2, 3, 3, 8, 9, 0x36],
+            ],
+            packets
+        );
+        assert!(tpm2_net_packets(&[0; 256], 1).is_err());
+    }
+}
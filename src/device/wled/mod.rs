@@ -0,0 +1,171 @@
+use crate::device::*;
+use std::io;
+use std::net;
+
+/// The port on which WLED listens for realtime UDP packets by default.
+const DEFAULT_PORT: &str = "21324";
+
+/// The realtime protocols supported by WLED, identified by the first byte of each packet.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Protocol {
+    /// The index and color of each LED, up to 255 LEDs.
+    Warls,
+    /// The color of each LED starting at the first, up to 490 LEDs.
+    Drgb,
+    /// Like DRGB, but with the index of the first LED so frames can be split over packets.
+    Dnrgb,
+}
+
+impl Protocol {
+    fn id(self) -> u8 {
+        match self {
+            Protocol::Warls => 1,
+            Protocol::Drgb => 2,
+            Protocol::Dnrgb => 4,
+        }
+    }
+
+    /// The number of LEDs that fit in a single packet.
+    fn max_leds(self) -> usize {
+        match self {
+            Protocol::Warls => 255,
+            Protocol::Drgb => 490,
+            Protocol::Dnrgb => 489,
+        }
+    }
+}
+
+/// Builds the packets for a frame. Only DNRGB can spread a frame over multiple packets.
+///
+/// `timeout` is the number of seconds WLED stays in realtime mode after the last packet.
+fn packets(protocol: Protocol, timeout: u8, frame: &[Pixel]) -> io::Result<Vec<Vec<u8>>> {
+    if protocol != Protocol::Dnrgb && frame.len() > protocol.max_leds() {
+        return Err(io::Error::other(format!(
+            "{:?} supports up to {} LEDs, use DNRGB for more",
+            protocol,
+            protocol.max_leds()
+        )));
+    }
+    if frame.len() > 0x10000 {
+        return Err(io::Error::other("WLED supports up to 65536 LEDs"));
+    }
+    Ok(frame
+        .chunks(protocol.max_leds())
+        .enumerate()
+        .map(|(i, chunk)| {
+            let mut packet = vec![protocol.id(), timeout];
+            match protocol {
+                Protocol::Warls => {
+                    for (index, pix) in chunk.iter().enumerate() {
+                        packet.extend([index as u8, pix.r, pix.g, pix.b]);
+                    }
+                }
+                Protocol::Drgb => {
+                    packet.extend(chunk.iter().flat_map(|p| [p.r, p.g, p.b]));
+                }
+                Protocol::Dnrgb => {
+                    let start = (i * protocol.max_leds()) as u16;
+                    packet.extend(start.to_be_bytes());
+                    packet.extend(chunk.iter().flat_map(|p| [p.r, p.g, p.b]));
+                }
+            }
+            packet
+        })
+        .collect())
+}
+
+pub struct Wled {
+    socket: net::UdpSocket,
+    target: net::SocketAddr,
+    protocol: Protocol,
+    timeout: u8,
+}
+
+impl Output for Wled {
+    fn output_frame(&mut self, frame: &[Pixel]) -> io::Result<()> {
+        for packet in packets(self.protocol, self.timeout, frame)? {
+            self.socket.send_to(&packet, self.target)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn command() -> clap::Command {
+    clap::Command::new("wled")
+        .about("Control WLED controllers using the UDP realtime protocol")
+        .arg(clap::arg!(-t --target <ip> "The IP address of the controller")
+            .value_parser(clap::value_parser!(net::IpAddr))
+            .required(true))
+        .arg(clap::arg!(--port <port> "The UDP port of the controller")
+            .value_parser(clap::value_parser!(u16))
+            .default_value(DEFAULT_PORT))
+        .arg(clap::arg!(--protocol <value> "The realtime protocol to use. WARLS supports up to 255 LEDs and DRGB up to 490, DNRGB supports any number")
+            .value_parser(["warls", "drgb", "dnrgb"])
+            .default_value("dnrgb"))
+        .arg(clap::arg!(--timeout <seconds> "The number of seconds after the last frame before WLED returns to its normal mode. 255 keeps it in realtime mode")
+            .value_parser(clap::value_parser!(u8).range(1..))
+            .default_value("2"))
+}
+
+pub fn from_command(args: &clap::ArgMatches, gargs: &GlobalArgs) -> io::Result<FromCommand> {
+    let protocol = match args.get_one::<String>("protocol").unwrap().as_str() {
+        "warls" => Protocol::Warls,
+        "drgb" => Protocol::Drgb,
+        _ => Protocol::Dnrgb,
+    };
+    let dimensions = gargs.dimensions()?;
+    // Check the frame size upfront instead of failing on the first frame.
+    packets(protocol, 0, &vec![Pixel::default(); dimensions.size()])?;
+
+    let ip = *args.get_one::<net::IpAddr>("target").unwrap();
+    Ok(FromCommand::Output(Box::new(Wled {
+        socket: net::UdpSocket::bind(("0.0.0.0", 0))?,
+        target: net::SocketAddr::new(ip, *args.get_one::<u16>("port").unwrap()),
+        protocol,
+        timeout: *args.get_one::<u8>("timeout").unwrap(),
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_packets() {
+        let frame = [Pixel { r: 1, g: 2, b: 3 }, Pixel { r: 4, g: 5, b: 6 }];
+        assert_eq!(
+            vec![vec![1, 2, 0, 1, 2, 3, 1, 4, 5, 6]],
+            packets(Protocol::Warls, 2, &frame).unwrap()
+        );
+        assert_eq!(
+            vec![vec![2, 255, 1, 2, 3, 4, 5, 6]],
+            packets(Protocol::Drgb, 255, &frame).unwrap()
+        );
+        assert_eq!(
+            vec![vec![4, 2, 0, 0, 1, 2, 3, 4, 5, 6]],
+            packets(Protocol::Dnrgb, 2, &frame).unwrap()
+        );
+        assert!(packets(Protocol::Warls, 2, &[Pixel::default(); 256]).is_err());
+        assert!(packets(Protocol::Drgb, 2, &[Pixel::default(); 491]).is_err());
+    }
+
+    #[test]
+    fn dnrgb_splits_frames() {
+        let frame: Vec<Pixel> = (0..1000)
+            .map(|i| Pixel {
+                r: (i >> 8) as u8,
+                g: i as u8,
+                b: 0,
+            })
+            .collect();
+        let packets = packets(Protocol::Dnrgb, 2, &frame).unwrap();
+        assert_eq!(3, packets.len());
+        for (packet, start) in packets.iter().zip([0u16, 489, 978]) {
+            assert_eq!(start.to_be_bytes(), packet[2..4]);
+            // The first LED in the packet matches the start index.
+            assert_eq!(start.to_be_bytes(), packet[4..6]);
+        }
+        assert_eq!(4 + 489 * 3, packets[0].len());
+        assert_eq!(4 + 22 * 3, packets[2].len());
+    }
+}
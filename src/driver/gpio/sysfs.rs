@@ -0,0 +1,246 @@
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+use std::fs;
+use std::io;
+use std::os::fd::AsFd;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time;
+
+/// The location where the kernel mounts the GPIO sysfs interface.
+pub const DEFAULT_BASE: &str = "/sys/class/gpio";
+
+/// Exports a pin if its directory does not exist yet. Returns whether the pin was exported.
+fn export(base: &Path, gpio_num: u16) -> io::Result<bool> {
+    let pin_dir = base.join(format!("gpio{}", gpio_num));
+    if pin_dir.exists() {
+        return Ok(false);
+    }
+    fs::write(base.join("export"), format!("{}", gpio_num))?;
+    // The kernel and udev may take a moment to set up the pin's directory and its permissions.
+    let start = time::Instant::now();
+    while !pin_dir.exists() && start.elapsed() < time::Duration::from_secs(1) {
+        thread::sleep(time::Duration::from_millis(10));
+    }
+    Ok(true)
+}
+
+fn unexport(base: &Path, gpio_num: u16) {
+    // Best effort, failures are ignored.
+    let _ = fs::write(base.join("unexport"), format!("{}", gpio_num));
+}
+
+/// A GPIO pin configured as output through the Linux sysfs interface.
+///
+/// The value file is kept open so toggling the pin only costs a single write.
+pub struct SysFsGpioOutput {
+    base: PathBuf,
+    gpio_num: u16,
+    // Whether the pin was exported by us and should be unexported when dropped.
+    exported: bool,
+    value: fs::File,
+}
+
+impl SysFsGpioOutput {
+    /// Opens a pin that has already been exported.
+    pub fn new(base: impl AsRef<Path>, gpio_num: u16) -> io::Result<SysFsGpioOutput> {
+        let base = base.as_ref();
+        let pin_dir = base.join(format!("gpio{}", gpio_num));
+        // Ensure that we're using '0' as low.
+        fs::write(pin_dir.join("active_low"), b"0")?;
+        fs::write(pin_dir.join("direction"), b"out")?;
+        let value = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(pin_dir.join("value"))?;
+        Ok(SysFsGpioOutput {
+            base: base.to_path_buf(),
+            gpio_num,
+            exported: false,
+            value,
+        })
+    }
+
+    /// Exports the pin if that has not been done already and opens it. A pin that was exported by
+    /// this function is unexported again when the output is dropped.
+    pub fn exported_new(base: impl AsRef<Path>, gpio_num: u16) -> io::Result<SysFsGpioOutput> {
+        let exported = export(base.as_ref(), gpio_num)?;
+        let mut gpio = SysFsGpioOutput::new(base, gpio_num)?;
+        gpio.exported = exported;
+        Ok(gpio)
+    }
+}
+
+impl Drop for SysFsGpioOutput {
+    fn drop(&mut self) {
+        if self.exported {
+            unexport(&self.base, self.gpio_num);
+        }
+    }
+}
+
+impl gpio::GpioOut for SysFsGpioOutput {
+    type Error = io::Error;
+
+    fn set_low(&mut self) -> io::Result<()> {
+        self.value.write_all_at(b"0", 0)
+    }
+
+    fn set_high(&mut self) -> io::Result<()> {
+        self.value.write_all_at(b"1", 0)
+    }
+}
+
+/// A GPIO pin configured as input through the Linux sysfs interface.
+///
+/// Like the output, the value file is kept open and read from the start each time.
+pub struct SysFsGpioInput {
+    base: PathBuf,
+    gpio_num: u16,
+    // Whether the pin was exported by us and should be unexported when dropped.
+    exported: bool,
+    value: fs::File,
+}
+
+impl SysFsGpioInput {
+    /// Opens a pin that has already been exported.
+    pub fn new(base: impl AsRef<Path>, gpio_num: u16) -> io::Result<SysFsGpioInput> {
+        let base = base.as_ref();
+        let pin_dir = base.join(format!("gpio{}", gpio_num));
+        fs::write(pin_dir.join("active_low"), b"0")?;
+        fs::write(pin_dir.join("direction"), b"in")?;
+        let value = fs::File::open(pin_dir.join("value"))?;
+        Ok(SysFsGpioInput {
+            base: base.to_path_buf(),
+            gpio_num,
+            exported: false,
+            value,
+        })
+    }
+
+    /// Exports the pin if that has not been done already and opens it. A pin that was exported by
+    /// this function is unexported again when the input is dropped.
+    pub fn exported_new(base: impl AsRef<Path>, gpio_num: u16) -> io::Result<SysFsGpioInput> {
+        let exported = export(base.as_ref(), gpio_num)?;
+        let mut gpio = SysFsGpioInput::new(base, gpio_num)?;
+        gpio.exported = exported;
+        Ok(gpio)
+    }
+
+    /// Makes the kernel signal both rising and falling edges, so `wait_for_edge` can be used.
+    pub fn with_edge_detection(self) -> io::Result<SysFsGpioInput> {
+        let pin_dir = self.base.join(format!("gpio{}", self.gpio_num));
+        fs::write(pin_dir.join("edge"), b"both")?;
+        Ok(self)
+    }
+
+    /// Blocks until the value of the pin changes or the timeout expires. Edge detection must have
+    /// been enabled.
+    pub fn wait_for_edge(&mut self, timeout: time::Duration) -> io::Result<()> {
+        let mut fds = [PollFd::new(self.value.as_fd(), PollFlags::POLLPRI)];
+        let timeout = PollTimeout::try_from(timeout).unwrap_or(PollTimeout::MAX);
+        io_err!(poll(&mut fds, timeout))?;
+        Ok(())
+    }
+}
+
+impl Drop for SysFsGpioInput {
+    fn drop(&mut self) {
+        if self.exported {
+            unexport(&self.base, self.gpio_num);
+        }
+    }
+}
+
+impl gpio::GpioIn for SysFsGpioInput {
+    type Error = io::Error;
+
+    fn read_value(&mut self) -> io::Result<gpio::GpioValue> {
+        let mut buf = [0; 1];
+        self.value.read_exact_at(&mut buf, 0)?;
+        match buf[0] {
+            b'0' => Ok(gpio::GpioValue::Low),
+            b'1' => Ok(gpio::GpioValue::High),
+            b => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unexpected GPIO value {:?}", b as char),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gpio::GpioOut;
+    use tempfile::tempdir;
+
+    #[test]
+    fn write_exported_pin() {
+        let tmp = tempdir().unwrap();
+        let pin_dir = tmp.path().join("gpio17");
+        fs::create_dir(&pin_dir).unwrap();
+
+        let mut gpio = SysFsGpioOutput::exported_new(tmp.path(), 17).unwrap();
+        assert_eq!(
+            "out",
+            fs::read_to_string(pin_dir.join("direction")).unwrap()
+        );
+        assert_eq!("0", fs::read_to_string(pin_dir.join("active_low")).unwrap());
+        gpio.set_high().unwrap();
+        assert_eq!("1", fs::read_to_string(pin_dir.join("value")).unwrap());
+        gpio.set_low().unwrap();
+        assert_eq!("0", fs::read_to_string(pin_dir.join("value")).unwrap());
+
+        // The pin was already exported, so it should be left alone.
+        drop(gpio);
+        assert!(!tmp.path().join("unexport").exists());
+    }
+
+    #[test]
+    fn export_and_unexport_pin() {
+        let tmp = tempdir().unwrap();
+        let base = tmp.path().to_path_buf();
+
+        // Emulate the kernel creating the pin's directory once it has been exported.
+        let kernel_base = base.clone();
+        let kernel = thread::spawn(move || loop {
+            let num = fs::read_to_string(kernel_base.join("export")).unwrap_or_default();
+            if !num.is_empty() {
+                fs::create_dir(kernel_base.join(format!("gpio{}", num))).unwrap();
+                return;
+            }
+            thread::sleep(time::Duration::from_millis(1));
+        });
+
+        let mut gpio = SysFsGpioOutput::exported_new(&base, 18).unwrap();
+        kernel.join().unwrap();
+        assert_eq!("18", fs::read_to_string(base.join("export")).unwrap());
+        gpio.set_high().unwrap();
+        assert_eq!("1", fs::read_to_string(base.join("gpio18/value")).unwrap());
+
+        drop(gpio);
+        assert_eq!("18", fs::read_to_string(base.join("unexport")).unwrap());
+    }
+
+    #[test]
+    fn read_input_pin() {
+        use gpio::{GpioIn, GpioValue};
+        let tmp = tempdir().unwrap();
+        let pin_dir = tmp.path().join("gpio22");
+        fs::create_dir(&pin_dir).unwrap();
+        fs::write(pin_dir.join("value"), b"0\n").unwrap();
+
+        let mut gpio = SysFsGpioInput::exported_new(tmp.path(), 22)
+            .unwrap()
+            .with_edge_detection()
+            .unwrap();
+        assert_eq!("in", fs::read_to_string(pin_dir.join("direction")).unwrap());
+        assert_eq!("both", fs::read_to_string(pin_dir.join("edge")).unwrap());
+        assert_eq!(GpioValue::Low, gpio.read_value().unwrap());
+        // The file is kept open and read from the start again.
+        fs::write(pin_dir.join("value"), b"1\n").unwrap();
+        assert_eq!(GpioValue::High, gpio.read_value().unwrap());
+    }
+}
@@ -4,11 +4,17 @@ use std::fs;
 use std::io;
 use std::path::{Path, PathBuf};
 
+pub mod gpio;
 pub mod serial;
 
 #[allow(clippy::type_complexity)]
 const DRIVER_DETECTORS: &[(&str, fn(&Path) -> bool)] = &[("serial", serial::is_serial)];
 
+/// The names of the drivers that can be detected automatically.
+pub fn detector_names() -> impl Iterator<Item = &'static str> {
+    DRIVER_DETECTORS.iter().map(|(name, _)| *name)
+}
+
 pub fn detect(file: impl AsRef<Path>) -> Option<&'static str> {
     let real_file = read_link_recursive(file).ok()?;
     for (name, probe) in DRIVER_DETECTORS {
@@ -1,27 +1,121 @@
 use crate::driver;
+use nix::libc;
 use nix::sys::termios;
 use std::fs;
+use std::io;
+use std::os::fd::AsRawFd;
 use std::path::Path;
+use std::thread;
+use std::time::Duration;
 
-pub fn open(path: impl AsRef<Path>, baudrate: u32) -> Result<fs::File, driver::Error> {
+nix::ioctl_write_ptr_bad!(tiocmbis, libc::TIOCMBIS, libc::c_int);
+nix::ioctl_write_ptr_bad!(tiocmbic, libc::TIOCMBIC, libc::c_int);
+#[cfg(target_os = "linux")]
+nix::ioctl_read_bad!(tcgets2, libc::TCGETS2, libc::termios2);
+#[cfg(target_os = "linux")]
+nix::ioctl_write_ptr_bad!(tcsets2, libc::TCSETS2, libc::termios2);
+
+/// Options controls the modem control lines, which some devices use to reset themselves.
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    /// Asserts or deasserts DTR after opening. Many Arduino boards reset when DTR is toggled.
+    pub dtr: Option<bool>,
+    /// Asserts or deasserts RTS after opening.
+    pub rts: Option<bool>,
+    /// The time to wait after opening before the port is used, e.g. to let a bootloader finish.
+    pub reset_delay: Option<Duration>,
+}
+
+pub fn open(
+    path: impl AsRef<Path>,
+    baudrate: u32,
+    opts: &Options,
+) -> Result<fs::File, driver::Error> {
     let tty = fs::OpenOptions::new().write(true).read(true).open(path)?;
     let mut tio = termios::tcgetattr(&tty)?;
     tio.input_flags &= !(termios::InputFlags::ICRNL | termios::InputFlags::BRKINT);
     tio.output_flags &= !(termios::OutputFlags::OPOST | termios::OutputFlags::ONLCR);
     tio.local_flags &=
         !(termios::LocalFlags::ICANON | termios::LocalFlags::ISIG | termios::LocalFlags::ECHO);
-    termios::cfsetspeed(&mut tio, map_baudrate(baudrate))?;
+    if opts.dtr == Some(false) {
+        // Keep DTR from dropping when the port is closed, which would reset the device the next
+        // time the port is opened.
+        tio.control_flags &= !termios::ControlFlags::HUPCL;
+    }
+    let (standard, standard_rate) = map_baudrate(baudrate);
+    termios::cfsetspeed(&mut tio, standard)?;
     termios::tcsetattr(&tty, termios::SetArg::TCSANOW, &tio)?;
+    if standard_rate != baudrate {
+        set_custom_baudrate(&tty, baudrate)?;
+    }
+
+    for (line, state) in [(libc::TIOCM_DTR, opts.dtr), (libc::TIOCM_RTS, opts.rts)] {
+        match state {
+            Some(true) => unsafe { tiocmbis(tty.as_raw_fd(), &line) }?,
+            Some(false) => unsafe { tiocmbic(tty.as_raw_fd(), &line) }?,
+            None => 0,
+        };
+    }
+    if let Some(delay) = opts.reset_delay {
+        thread::sleep(delay);
+    }
     Ok(tty)
 }
 
+/// FrameGap waits for each frame to be transmitted when the port is flushed and then pauses, for
+/// devices that can not receive frames back to back.
+pub struct FrameGap {
+    tty: fs::File,
+    delay: Duration,
+}
+
+impl FrameGap {
+    pub fn new(tty: fs::File, delay: Duration) -> FrameGap {
+        FrameGap { tty, delay }
+    }
+}
+
+impl io::Write for FrameGap {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.tty.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.tty.flush()?;
+        io_err!(termios::tcdrain(&self.tty))?;
+        thread::sleep(self.delay);
+        Ok(())
+    }
+}
+
 pub fn is_serial(path: &Path) -> bool {
     path.to_str()
         .map(|p| p.starts_with("/dev/tty"))
         .unwrap_or(false)
 }
 
-fn map_baudrate(b: u32) -> termios::BaudRate {
+/// Sets a baudrate that is not one of the standard rates.
+#[cfg(target_os = "linux")]
+fn set_custom_baudrate(tty: &fs::File, baudrate: u32) -> Result<(), driver::Error> {
+    let mut tio = unsafe { std::mem::zeroed::<libc::termios2>() };
+    unsafe { tcgets2(tty.as_raw_fd(), &mut tio) }?;
+    tio.c_cflag &= !libc::CBAUD;
+    tio.c_cflag |= libc::BOTHER;
+    tio.c_ispeed = baudrate;
+    tio.c_ospeed = baudrate;
+    unsafe { tcsets2(tty.as_raw_fd(), &tio) }?;
+    Ok(())
+}
+
+/// Custom rates are only supported on Linux, elsewhere the nearest standard rate below it is used.
+#[cfg(not(target_os = "linux"))]
+fn set_custom_baudrate(_: &fs::File, _: u32) -> Result<(), driver::Error> {
+    Ok(())
+}
+
+/// Finds the highest standard baudrate that does not exceed the requested rate. The rate itself is
+/// returned as well, so it can be checked whether it matches exactly.
+fn map_baudrate(b: u32) -> (termios::BaudRate, u32) {
     let map = [
         #[cfg(target_os = "linux")]
         (4_000_000, termios::BaudRate::B4000000),
@@ -76,8 +170,20 @@ fn map_baudrate(b: u32) -> termios::BaudRate {
     ];
     for &(num, br) in &map {
         if b >= num {
-            return br;
+            return (br, num);
         }
     }
-    termios::BaudRate::B0
+    (termios::BaudRate::B0, 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn standard_baudrates() {
+        assert_eq!((termios::BaudRate::B115200, 115_200), map_baudrate(115_200));
+        assert_eq!((termios::BaudRate::B115200, 115_200), map_baudrate(200_000));
+        assert_eq!((termios::BaudRate::B0, 0), map_baudrate(10));
+    }
 }
@@ -0,0 +1,117 @@
+use std::io;
+
+/// Reader splits a byte stream into frames that are terminated by a delimiter instead of being
+/// separated by a fixed length.
+///
+/// Each frame is padded with zeroes or truncated so it is exactly `frame_size` bytes when it is
+/// read back. Bytes trailing the last delimiter in the stream are discarded at EOF.
+pub struct Reader<R> {
+    inner: R,
+    delimiter: Vec<u8>,
+    frame_size: usize,
+    // Bytes that have been read from the inner reader but are not yet part of a complete frame.
+    pending: Vec<u8>,
+    // The current frame selected for output.
+    current: io::Cursor<Vec<u8>>,
+}
+
+impl<R: io::Read> Reader<R> {
+    pub fn new(inner: R, delimiter: Vec<u8>, frame_size: usize) -> Reader<R> {
+        assert_ne!(delimiter.len(), 0);
+        Reader {
+            inner,
+            delimiter,
+            frame_size,
+            pending: Vec::new(),
+            current: io::Cursor::new(Vec::new()),
+        }
+    }
+
+    fn next_frame(&mut self) -> io::Result<Option<Vec<u8>>> {
+        // Only the part of the buffer that has not been searched yet needs to be scanned, taking
+        // care of delimiters that are split across two reads.
+        let mut search_from = 0;
+        loop {
+            let found = self.pending[search_from..]
+                .windows(self.delimiter.len())
+                .position(|w| w == self.delimiter.as_slice());
+            if let Some(pos) = found {
+                let end = search_from + pos;
+                let mut frame: Vec<u8> = self.pending.drain(..end).collect();
+                self.pending.drain(..self.delimiter.len());
+                frame.resize(self.frame_size, 0);
+                return Ok(Some(frame));
+            }
+            search_from = self.pending.len().saturating_sub(self.delimiter.len() - 1);
+
+            let mut chunk = [0; 4096];
+            let nread = self.inner.read(&mut chunk)?;
+            if nread == 0 {
+                return Ok(None);
+            }
+            self.pending.extend_from_slice(&chunk[..nread]);
+        }
+    }
+}
+
+impl<R: io::Read> io::Read for Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.current.position() == self.current.get_ref().len() as u64 {
+            // The end of the current frame has been reached, fetch the next one.
+            match self.next_frame()? {
+                Some(frame) => self.current = io::Cursor::new(frame),
+                None => return Ok(0),
+            }
+        }
+        self.current.read(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Yields the bytes of the inner reader one at a time to simulate slow writers.
+    struct Trickle<R>(R);
+
+    impl<R: io::Read> io::Read for Trickle<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(1);
+            self.0.read(&mut buf[..len])
+        }
+    }
+
+    fn read_frames(mut reader: impl io::Read, frame_size: usize) -> Vec<Vec<u8>> {
+        let mut frames = Vec::new();
+        loop {
+            let mut buf = vec![0; frame_size];
+            match reader.read_exact(&mut buf) {
+                Ok(()) => frames.push(buf),
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return frames,
+                Err(err) => panic!("{}", err),
+            }
+        }
+    }
+
+    #[test]
+    fn delimited_stream() {
+        let stream = b"abc\r\nde\r\nfghij\r\n\r\ntrailing".to_vec();
+        let reader = Reader::new(io::Cursor::new(stream), b"\r\n".to_vec(), 3);
+        assert_eq!(
+            vec![
+                b"abc".to_vec(),
+                b"de\0".to_vec(),
+                b"fgh".to_vec(),
+                b"\0\0\0".to_vec(),
+            ],
+            read_frames(reader, 3)
+        );
+    }
+
+    #[test]
+    fn delimiter_split_across_reads() {
+        let stream = b"ab--cd--".to_vec();
+        let reader = Reader::new(Trickle(io::Cursor::new(stream)), b"--".to_vec(), 2);
+        assert_eq!(vec![b"ab".to_vec(), b"cd".to_vec()], read_frames(reader, 2));
+    }
+}
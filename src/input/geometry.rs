@@ -32,6 +32,40 @@ impl str::FromStr for Dimensions {
     }
 }
 
+/// The environment variable that is used if the geometry is not set on the command line.
+pub const GEOMETRY_ENV: &str = "LEDCAT_GEOMETRY";
+
+/// Parses the value of the geometry environment variable.
+///
+/// Shell configurations tend to leave whitespace and quotes in place, so these are ignored.
+pub fn parse_env_geometry(s: &str) -> Result<Dimensions, String> {
+    s.trim()
+        .trim_matches(|c| c == '"' || c == '\'')
+        .trim()
+        .parse()
+}
+
+/// The display geometry as it can be specified on the command line.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Geometry {
+    Dimensions(Dimensions),
+    /// Fill the terminal. This is only supported by the simulator.
+    Terminal,
+    /// Read the geometry from the environment.
+    Env,
+}
+
+impl str::FromStr for Geometry {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "terminal" => Ok(Geometry::Terminal),
+            "env" => Ok(Geometry::Env),
+            s => s.parse().map(Geometry::Dimensions),
+        }
+    }
+}
+
 pub enum Axis {
     X,
     Y,
@@ -39,24 +73,41 @@ pub enum Axis {
 
 pub trait Transposition {
     fn transpose(&self, index: usize) -> usize;
+
+    /// The dimensions of the coordinate space the transposed indices are in.
+    fn output_dimensions(&self, input: Dimensions) -> Dimensions {
+        input
+    }
 }
 
 impl<T: Transposition> Transposition for &T {
     fn transpose(&self, index: usize) -> usize {
         (*self).transpose(index)
     }
+
+    fn output_dimensions(&self, input: Dimensions) -> Dimensions {
+        (*self).output_dimensions(input)
+    }
 }
 
 impl Transposition for Box<dyn Transposition> {
     fn transpose(&self, index: usize) -> usize {
         self.as_ref().transpose(index)
     }
+
+    fn output_dimensions(&self, input: Dimensions) -> Dimensions {
+        self.as_ref().output_dimensions(input)
+    }
 }
 
 impl<T: Transposition> Transposition for Vec<T> {
     fn transpose(&self, index: usize) -> usize {
         self.iter().fold(index, |index, tr| tr.transpose(index))
     }
+
+    fn output_dimensions(&self, input: Dimensions) -> Dimensions {
+        self.iter().fold(input, |d, tr| tr.output_dimensions(d))
+    }
 }
 
 pub struct Reverse {
@@ -101,7 +152,7 @@ impl Transposition for Zigzag {
             Axis::X => {
                 let x = index % self.width;
                 let y = index / self.width;
-                if x % 2 == 0 {
+                if x.is_multiple_of(2) {
                     x * self.height + y
                 } else {
                     x * self.height + self.height - y - 1
@@ -109,7 +160,7 @@ impl Transposition for Zigzag {
             }
             Axis::Y => {
                 let y = index / self.width;
-                let x = if y % 2 == 0 {
+                let x = if y.is_multiple_of(2) {
                     index % self.width
                 } else {
                     self.width - index % self.width - 1
@@ -120,6 +171,199 @@ impl Transposition for Zigzag {
     }
 }
 
+/// A clockwise rotation of a 2D image.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Rotation {
+    Deg90,
+    Deg180,
+    Deg270,
+}
+
+/// Rotate turns the image clockwise. Rotating a WxH image by 90 or 270 degrees results in an HxW
+/// image.
+pub struct Rotate {
+    pub width: usize,
+    pub height: usize,
+    pub rotation: Rotation,
+}
+
+impl Transposition for Rotate {
+    fn transpose(&self, index: usize) -> usize {
+        assert!(index < self.width * self.height);
+        let x = index % self.width;
+        let y = index / self.width;
+        match self.rotation {
+            Rotation::Deg90 => x * self.height + (self.height - y - 1),
+            Rotation::Deg180 => self.width * (self.height - y - 1) + (self.width - x - 1),
+            Rotation::Deg270 => (self.width - x - 1) * self.height + y,
+        }
+    }
+
+    fn output_dimensions(&self, input: Dimensions) -> Dimensions {
+        match self.rotation {
+            Rotation::Deg180 => input,
+            Rotation::Deg90 | Rotation::Deg270 => Dimensions {
+                w: input.h,
+                h: input.w,
+            },
+        }
+    }
+}
+
+/// Tile splits a display into equally sized panels that are chained one after another, row by row.
+///
+/// The pixels within each panel are moved by the sub-transposition, which operates on a single
+/// panel.
+pub struct Tile<T> {
+    width: usize,
+    tile: Dimensions,
+    sub: T,
+}
+
+impl<T: Transposition> Tile<T> {
+    pub fn new(width: usize, height: usize, tile: Dimensions, sub: T) -> Result<Tile<T>, String> {
+        if !width.is_multiple_of(tile.w) || !height.is_multiple_of(tile.h) {
+            return Err(format!(
+                "a {}x{} display can not be divided into {}x{} tiles",
+                width, height, tile.w, tile.h
+            ));
+        }
+        Ok(Tile { width, tile, sub })
+    }
+}
+
+impl<T: Transposition> Transposition for Tile<T> {
+    fn transpose(&self, index: usize) -> usize {
+        let x = index % self.width;
+        let y = index / self.width;
+        let tiles_per_row = self.width / self.tile.w;
+        let tile_index = (y / self.tile.h) * tiles_per_row + x / self.tile.w;
+        let local_index = (y % self.tile.h) * self.tile.w + x % self.tile.w;
+        tile_index * self.tile.size() + self.sub.transpose(local_index)
+    }
+}
+
+/// Affine remaps pixels using an integer matrix and offset.
+///
+/// The pixel at (x, y) is moved to (a*x + b*y + tx, c*x + d*y + ty).
+pub struct Affine {
+    width: usize,
+    matrix: [i64; 4],
+    offset: [i64; 2],
+}
+
+impl Affine {
+    /// Checks that the transformation maps the grid onto itself without dropping or overlapping
+    /// pixels.
+    pub fn new(
+        width: usize,
+        height: usize,
+        matrix: [i64; 4],
+        offset: [i64; 2],
+    ) -> Result<Affine, String> {
+        let [a, b, c, d] = matrix;
+        // An integer matrix is only invertible over the integers if its determinant is ±1.
+        let det = a
+            .checked_mul(d)
+            .zip(b.checked_mul(c))
+            .and_then(|(ad, bc)| ad.checked_sub(bc))
+            .ok_or("affine parameters out of range")?;
+        if det.abs() != 1 {
+            return Err("the affine matrix must have a determinant of 1 or -1".to_string());
+        }
+        let affine = Affine {
+            width,
+            matrix,
+            offset,
+        };
+        // The grid is convex, so if all corners map inside the grid, all other pixels do as well.
+        let (w, h) = (width as i64, height as i64);
+        for (x, y) in [(0, 0), (w - 1, 0), (0, h - 1), (w - 1, h - 1)] {
+            let (x, y) = affine.map(x, y).ok_or("affine parameters out of range")?;
+            if x < 0 || x >= w || y < 0 || y >= h {
+                return Err(format!(
+                    "the affine transformation moves pixels outside of the {}x{} display",
+                    width, height
+                ));
+            }
+        }
+        Ok(affine)
+    }
+
+    /// Returns None if the computation overflows.
+    fn map(&self, x: i64, y: i64) -> Option<(i64, i64)> {
+        let [a, b, c, d] = self.matrix;
+        let [tx, ty] = self.offset;
+        let row = |m: i64, n: i64, t: i64| {
+            m.checked_mul(x)?
+                .checked_add(n.checked_mul(y)?)?
+                .checked_add(t)
+        };
+        Some((row(a, b, tx)?, row(c, d, ty)?))
+    }
+}
+
+impl Transposition for Affine {
+    fn transpose(&self, index: usize) -> usize {
+        // The corners were checked by Affine::new, the pixels in between can not overflow.
+        let (x, y) = self
+            .map((index % self.width) as i64, (index / self.width) as i64)
+            .unwrap();
+        y as usize * self.width + x as usize
+    }
+}
+
+/// Lookup moves pixels according to an arbitrary table, for displays that are wired in a way
+/// that none of the other transpositions describe.
+pub struct Lookup {
+    /// The output position of each input pixel.
+    table: Vec<usize>,
+}
+
+impl Lookup {
+    /// Parses a list of source indices, one for each destination position. Empty lines and lines
+    /// starting with `#` are ignored.
+    pub fn parse(text: &str, size: usize) -> Result<Lookup, String> {
+        let sources = text
+            .lines()
+            .enumerate()
+            .map(|(lineno, line)| (lineno, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+            .map(|(lineno, line)| {
+                line.parse::<usize>()
+                    .map_err(|_| format!("line {}: invalid index \"{}\"", lineno + 1, line))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if sources.len() != size {
+            return Err(format!(
+                "the lookup table has {} entries, but the display has {} pixels",
+                sources.len(),
+                size
+            ));
+        }
+        let mut table = vec![usize::MAX; size];
+        for (dest, &src) in sources.iter().enumerate() {
+            if src >= size {
+                return Err(format!(
+                    "index {} is out of range for a display of {} pixels",
+                    src, size
+                ));
+            }
+            if table[src] != usize::MAX {
+                return Err(format!("index {} is listed more than once", src));
+            }
+            table[src] = dest;
+        }
+        Ok(Lookup { table })
+    }
+}
+
+impl Transposition for Lookup {
+    fn transpose(&self, index: usize) -> usize {
+        self.table[index]
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -153,6 +397,28 @@ mod tests {
         );
     }
 
+    #[test]
+    fn geometry_parse() {
+        assert_eq!(Ok(Geometry::Terminal), "terminal".parse::<Geometry>());
+        assert_eq!(
+            Ok(Geometry::Dimensions(Dimensions { w: 4, h: 20 })),
+            "4x20".parse::<Geometry>()
+        );
+        assert_eq!(Ok(Geometry::Env), "env".parse::<Geometry>());
+        assert!("term".parse::<Geometry>().is_err());
+    }
+
+    #[test]
+    fn env_geometry_parse() {
+        let d = Dimensions { w: 32, h: 16 };
+        assert_eq!(Ok(d), parse_env_geometry("32x16"));
+        assert_eq!(Ok(d), parse_env_geometry(" 32x16 "));
+        assert_eq!(Ok(d), parse_env_geometry("'32x16'"));
+        assert_eq!(Ok(d), parse_env_geometry("\" 32x16\"\n"));
+        assert!(parse_env_geometry("32 x 16").is_err());
+        assert!(parse_env_geometry("").is_err());
+    }
+
     #[test]
     fn dimensions_size() {
         assert_eq!(42, Dimensions { w: 42, h: 1 }.size());
@@ -240,4 +506,154 @@ mod tests {
             transpose_all(&zz, 0..12)
         );
     }
+
+    #[test]
+    fn rotate_90() {
+        let rot = Rotate {
+            width: 3,
+            height: 2,
+            rotation: Rotation::Deg90,
+        };
+        // 0 1 2      3 0
+        // 3 4 5  ->  4 1
+        //            5 2
+        assert_eq!(vec![1, 3, 5, 0, 2, 4], transpose_all(&rot, 0..6));
+        assert_eq!(
+            Dimensions { w: 2, h: 3 },
+            rot.output_dimensions(Dimensions { w: 3, h: 2 })
+        );
+    }
+
+    #[test]
+    fn rotate_180() {
+        let rot = Rotate {
+            width: 3,
+            height: 2,
+            rotation: Rotation::Deg180,
+        };
+        assert_eq!(vec![5, 4, 3, 2, 1, 0], transpose_all(&rot, 0..6));
+        assert_eq!(
+            Dimensions { w: 3, h: 2 },
+            rot.output_dimensions(Dimensions { w: 3, h: 2 })
+        );
+    }
+
+    #[test]
+    fn rotate_270() {
+        let rot = Rotate {
+            width: 3,
+            height: 2,
+            rotation: Rotation::Deg270,
+        };
+        // 0 1 2      2 5
+        // 3 4 5  ->  1 4
+        //            0 3
+        assert_eq!(vec![4, 2, 0, 5, 3, 1], transpose_all(&rot, 0..6));
+
+        // Rotating back and forth ends up where it started.
+        let back = Rotate {
+            width: 2,
+            height: 3,
+            rotation: Rotation::Deg90,
+        };
+        assert_eq!(
+            (0..6).collect::<Vec<_>>(),
+            transpose_all(vec![&rot, &back], 0..6)
+        );
+    }
+
+    #[test]
+    fn tile() {
+        // Two 2x2 panels next to each other, each wired in a zigzag.
+        let zz = Zigzag {
+            width: 2,
+            height: 2,
+            major_axis: Axis::X,
+        };
+        let tile = Tile::new(4, 2, Dimensions { w: 2, h: 2 }, zz).unwrap();
+        // 0 1 | 2 3      0 3 | 4 7
+        // 4 5 | 6 7  ->  1 2 | 5 6
+        assert_eq!(vec![0, 3, 4, 7, 1, 2, 5, 6], transpose_all(&tile, 0..8));
+
+        // Without a sub-transposition, panels are filled row by row.
+        let tile = Tile::new(4, 4, Dimensions { w: 2, h: 2 }, Vec::<Reverse>::new()).unwrap();
+        assert_eq!(
+            vec![0, 1, 4, 5, 2, 3, 6, 7, 8, 9, 12, 13, 10, 11, 14, 15],
+            transpose_all(&tile, 0..16)
+        );
+
+        assert!(Tile::new(5, 4, Dimensions { w: 2, h: 2 }, Vec::<Reverse>::new()).is_err());
+    }
+
+    #[test]
+    fn affine_rotate_90() {
+        // (x, y) -> (h - 1 - y, x), rotating clockwise.
+        let rot = Affine::new(3, 3, [0, -1, 1, 0], [2, 0]).unwrap();
+        assert_eq!(vec![2, 5, 8, 1, 4, 7, 0, 3, 6], transpose_all(&rot, 0..9));
+
+        // Rotating twice is the same as mirroring over both axes.
+        let mirror: Vec<Box<dyn Transposition>> = vec![
+            Box::new(Mirror {
+                width: 3,
+                height: 3,
+                axis: Axis::X,
+            }),
+            Box::new(Mirror {
+                width: 3,
+                height: 3,
+                axis: Axis::Y,
+            }),
+        ];
+        assert_eq!(
+            transpose_all(&mirror, 0..9),
+            transpose_all(vec![&rot, &rot], 0..9)
+        );
+    }
+
+    #[test]
+    fn affine_rejects_non_bijections() {
+        // Scaling collides pixels.
+        assert!(Affine::new(4, 4, [2, 0, 0, 1], [0, 0]).is_err());
+        // Rotating a non-square display moves pixels outside of it.
+        assert!(Affine::new(4, 2, [0, -1, 1, 0], [1, 0]).is_err());
+        // Translating without wrapping around too.
+        assert!(Affine::new(4, 4, [1, 0, 0, 1], [1, 0]).is_err());
+        // Shearing only fits in the grid if there's room.
+        assert!(Affine::new(4, 4, [1, 1, 0, 1], [0, 0]).is_err());
+        assert!(Affine::new(4, 4, [1, 0, 0, 1], [0, 0]).is_ok());
+    }
+
+    #[test]
+    fn affine_out_of_range() {
+        let out_of_range = Err("affine parameters out of range".to_string());
+        // The determinant would wrap around to 1.
+        let big = 1 << 32;
+        assert_eq!(
+            out_of_range,
+            Affine::new(4, 4, [big, 1, -1, big], [0, 0]).map(|_| ())
+        );
+        assert_eq!(
+            out_of_range,
+            Affine::new(4, 4, [i64::MAX, 0, 0, i64::MAX], [0, 0]).map(|_| ())
+        );
+        assert_eq!(
+            out_of_range,
+            Affine::new(4, 4, [1, i64::MAX, 0, 1], [0, 0]).map(|_| ())
+        );
+        assert_eq!(
+            out_of_range,
+            Affine::new(4, 4, [1, 0, i64::MIN + 1, 1], [0, 0]).map(|_| ())
+        );
+    }
+
+    #[test]
+    fn lookup() {
+        let l = Lookup::parse("# A custom layout.\n2\n0\n\n1\n", 3).unwrap();
+        // Destination 0 shows the pixel at source 2, so source 2 moves to position 0.
+        assert_eq!(vec![1, 2, 0], transpose_all(&l, 0..3));
+        assert!(Lookup::parse("0\n1\n", 3).is_err());
+        assert!(Lookup::parse("0\n1\n3\n", 3).is_err());
+        assert!(Lookup::parse("0\n1\n1\n", 3).is_err());
+        assert!(Lookup::parse("0\n1\nx\n", 3).is_err());
+    }
 }
@@ -0,0 +1,154 @@
+use crate::geometry::*;
+use crate::input::*;
+use image::AnimationDecoder;
+use image::ImageDecoder;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Browsers show frames with a delay of 10ms or less for 100ms, animations rely on this.
+const MIN_DELAY: Duration = Duration::from_millis(20);
+const DEFAULT_DELAY: Duration = Duration::from_millis(100);
+
+/// Checks whether an input should be decoded as a GIF animation rather than read as raw RGB24.
+pub fn is_gif(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("gif"))
+}
+
+/// Gif plays back the frames of an animated GIF with the same dimensions as the display.
+pub struct Gif {
+    /// The fully composed frames and the time each of them should be shown.
+    frames: Vec<(Vec<Pixel>, Duration)>,
+    next_index: usize,
+    /// Set if frames should be delayed as specified by the animation.
+    use_delays: bool,
+    looping: bool,
+    /// The time at which the next frame should be emitted.
+    next_at: Option<Instant>,
+}
+
+impl Gif {
+    pub fn decode(
+        r: impl io::BufRead + io::Seek,
+        dimensions: Dimensions,
+        use_delays: bool,
+        looping: bool,
+    ) -> io::Result<Gif> {
+        let decoder = image::codecs::gif::GifDecoder::new(r).map_err(io::Error::other)?;
+        let (w, h) = decoder.dimensions();
+        if (Dimensions {
+            w: w as usize,
+            h: h as usize,
+        }) != dimensions
+        {
+            return Err(io::Error::other(format!(
+                "the animation is {}x{}, but the geometry is {}x{}",
+                w, h, dimensions.w, dimensions.h
+            )));
+        }
+        let frames = decoder
+            .into_frames()
+            .map(|frame| {
+                let frame = frame.map_err(io::Error::other)?;
+                let delay = match Duration::from(frame.delay()) {
+                    d if d < MIN_DELAY => DEFAULT_DELAY,
+                    d => d,
+                };
+                // Transparent parts of the animation are shown as black.
+                let pixels = frame
+                    .buffer()
+                    .pixels()
+                    .map(|p| {
+                        let blend = |c: u8| (u16::from(c) * u16::from(p[3]) / 255) as u8;
+                        Pixel {
+                            r: blend(p[0]),
+                            g: blend(p[1]),
+                            b: blend(p[2]),
+                        }
+                    })
+                    .collect();
+                Ok((pixels, delay))
+            })
+            .collect::<io::Result<_>>()?;
+        Ok(Gif {
+            frames,
+            next_index: 0,
+            use_delays,
+            looping,
+            next_at: None,
+        })
+    }
+}
+
+impl Input for Gif {
+    fn next_frame(&mut self) -> io::Result<Option<Vec<Pixel>>> {
+        if self.next_index == self.frames.len() {
+            if !self.looping || self.frames.is_empty() {
+                return Ok(None);
+            }
+            self.next_index = 0;
+        }
+        let (frame, delay) = &self.frames[self.next_index];
+        self.next_index += 1;
+
+        if self.use_delays {
+            let now = Instant::now();
+            if let Some(d) = self.next_at.and_then(|t| t.checked_duration_since(now)) {
+                thread::sleep(d);
+            }
+            // Schedule relative to the previous deadline so the animation does not drift.
+            self.next_at = Some(self.next_at.unwrap_or(now).max(now) + *delay);
+        }
+        Ok(Some(frame.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::codecs::gif::GifEncoder;
+    use image::{Delay, Frame, RgbaImage};
+
+    fn encode(frames: &[(u8, u32)]) -> io::Cursor<Vec<u8>> {
+        let mut buf = Vec::new();
+        {
+            let mut encoder = GifEncoder::new(&mut buf);
+            for &(v, ms) in frames {
+                let img = RgbaImage::from_pixel(2, 1, image::Rgba([v, v, v, 255]));
+                let delay = Delay::from_numer_denom_ms(ms, 1);
+                encoder
+                    .encode_frame(Frame::from_parts(img, 0, 0, delay))
+                    .unwrap();
+            }
+        }
+        io::Cursor::new(buf)
+    }
+
+    #[test]
+    fn frame_timing() {
+        let dimensions = Dimensions { w: 2, h: 1 };
+        let data = encode(&[(0, 50), (255, 30), (0, 0)]);
+        let mut input = Gif::decode(data.clone(), dimensions, true, false).unwrap();
+
+        let start = Instant::now();
+        let mut frames = Vec::new();
+        while let Some(frame) = input.next_frame().unwrap() {
+            frames.push(frame[0].r);
+        }
+        assert_eq!(vec![0, 255, 0], frames);
+        // The last frame is emitted after the delays of the frames before it.
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(80), "{:?}", elapsed);
+
+        let mut input = Gif::decode(data.clone(), dimensions, false, true).unwrap();
+        let start = Instant::now();
+        for _ in 0..7 {
+            assert!(input.next_frame().unwrap().is_some());
+        }
+        assert!(start.elapsed() < Duration::from_millis(50));
+
+        assert!(Gif::decode(data, Dimensions { w: 1, h: 2 }, true, false).is_err());
+    }
+}
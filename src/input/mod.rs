@@ -1,2 +1,88 @@
+use crate::color::*;
+use std::io;
+use std::ops::DerefMut;
+
+pub mod delimit;
 pub mod geometry;
+pub mod gif;
+pub mod pattern;
+pub mod png;
+pub mod replay;
 pub mod select;
+
+/// An input is a source of frames.
+///
+/// Byte streams are decoded by the `Raw` adapter, other sources may produce frames in whatever way
+/// suits them.
+pub trait Input: Send {
+    /// Fetches the next frame. `None` is returned once the input is exhausted.
+    fn next_frame(&mut self) -> io::Result<Option<Vec<Pixel>>>;
+}
+
+impl Input for Box<dyn Input> {
+    fn next_frame(&mut self) -> io::Result<Option<Vec<Pixel>>> {
+        self.deref_mut().next_frame()
+    }
+}
+
+/// Raw reads frames of RGB24 pixels from a byte stream.
+pub struct Raw<R> {
+    reader: R,
+    num_pixels: usize,
+}
+
+impl<R: io::Read + Send> Raw<R> {
+    pub fn new(reader: R, num_pixels: usize) -> Raw<R> {
+        Raw { reader, num_pixels }
+    }
+}
+
+impl<R: io::Read + Send> Input for Raw<R> {
+    fn next_frame(&mut self) -> io::Result<Option<Vec<Pixel>>> {
+        // Read a full frame into a buffer. This prevents half frames being written to a
+        // potentially timing sensitive output if the input blocks.
+        let mut buf = vec![0; self.num_pixels * 3];
+        match self.reader.read_exact(&mut buf) {
+            Ok(()) => (),
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let frame = buf
+            .chunks(3)
+            .map(|bin| Pixel {
+                r: bin[0],
+                g: bin[1],
+                b: bin[2],
+            })
+            .collect();
+        Ok(Some(frame))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raw_frames() {
+        let data = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13];
+        let mut input = Raw::new(io::Cursor::new(data), 2);
+        assert_eq!(
+            Some(vec![Pixel { r: 1, g: 2, b: 3 }, Pixel { r: 4, g: 5, b: 6 }]),
+            input.next_frame().unwrap()
+        );
+        assert_eq!(
+            Some(vec![
+                Pixel { r: 7, g: 8, b: 9 },
+                Pixel {
+                    r: 10,
+                    g: 11,
+                    b: 12
+                }
+            ]),
+            input.next_frame().unwrap()
+        );
+        // The trailing partial frame is discarded.
+        assert_eq!(None, input.next_frame().unwrap());
+    }
+}
@@ -0,0 +1,182 @@
+use crate::geometry::*;
+use crate::input::*;
+use std::str;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The prefix of --input values that select a generated pattern.
+pub const PREFIX: &str = "pattern:";
+
+/// The interval between frames if no frame rate is set.
+const DEFAULT_INTERVAL: Duration = Duration::from_millis(1000 / 30);
+
+/// The kinds of patterns that can be generated.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Kind {
+    /// All pixels have the same color.
+    Solid(Pixel),
+    /// Red increases along the X-axis and green along the Y-axis.
+    Gradient,
+    /// A rainbow scrolling along the X-axis.
+    Rainbow,
+    /// A single white pixel moving through the pixels in order.
+    Chase,
+    /// Each pixel shows its index as a 24-bit big endian number in its color channels.
+    Index,
+}
+
+impl str::FromStr for Kind {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gradient" => Ok(Kind::Gradient),
+            "rainbow" => Ok(Kind::Rainbow),
+            "chase" => Ok(Kind::Chase),
+            "index" => Ok(Kind::Index),
+            s => match s.strip_prefix("solid:").map(crate::util::parse_hex) {
+                Some(Ok(rgb)) => match rgb[..] {
+                    [r, g, b] => Ok(Kind::Solid(Pixel { r, g, b })),
+                    _ => Err(format!("expected a color as RRGGBB: {}", s)),
+                },
+                Some(Err(err)) => Err(err),
+                None => Err(format!(
+                    "unknown pattern: {}, expected one of solid:RRGGBB, gradient, rainbow, chase or index",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+impl Kind {
+    /// Renders frame number `n` of the pattern.
+    fn render(self, dimensions: Dimensions, n: usize) -> Vec<Pixel> {
+        // Scales a position along an axis of length `len` into 0..=255.
+        let ramp = |pos: usize, len: usize| (pos * 255 / len.saturating_sub(1).max(1)) as u8;
+        (0..dimensions.size())
+            .map(|i| {
+                let (x, y) = (i % dimensions.w, i / dimensions.w);
+                match self {
+                    Kind::Solid(pix) => pix,
+                    Kind::Gradient => Pixel {
+                        r: ramp(x, dimensions.w),
+                        g: ramp(y, dimensions.h),
+                        b: 0,
+                    },
+                    Kind::Rainbow => {
+                        let hue = (x + n) % dimensions.w * 360 / dimensions.w;
+                        Pixel::from_hsv(hue as f32, 1.0, 1.0)
+                    }
+                    Kind::Chase if i == n % dimensions.size() => Pixel {
+                        r: 255,
+                        g: 255,
+                        b: 255,
+                    },
+                    Kind::Chase => Pixel::default(),
+                    Kind::Index => Pixel {
+                        r: (i >> 16) as u8,
+                        g: (i >> 8) as u8,
+                        b: i as u8,
+                    },
+                }
+            })
+            .collect()
+    }
+}
+
+/// Pattern generates frames for testing a display without having to provide an input.
+pub struct Pattern {
+    kind: Kind,
+    dimensions: Dimensions,
+    n: usize,
+    /// Set if the pattern should be paced by itself because no frame rate is set.
+    pace: bool,
+    /// The time at which the next frame should be emitted.
+    next_at: Option<Instant>,
+}
+
+impl Pattern {
+    pub fn new(kind: Kind, dimensions: Dimensions, pace: bool) -> Pattern {
+        Pattern {
+            kind,
+            dimensions,
+            n: 0,
+            pace,
+            next_at: None,
+        }
+    }
+}
+
+impl Input for Pattern {
+    fn next_frame(&mut self) -> io::Result<Option<Vec<Pixel>>> {
+        if self.pace {
+            let now = Instant::now();
+            if let Some(d) = self.next_at.and_then(|t| t.checked_duration_since(now)) {
+                thread::sleep(d);
+            }
+            self.next_at = Some(self.next_at.unwrap_or(now).max(now) + DEFAULT_INTERVAL);
+        }
+        let frame = self.kind.render(self.dimensions, self.n);
+        self.n = self.n.wrapping_add(1);
+        Ok(Some(frame))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_kind() {
+        assert_eq!(
+            Ok(Kind::Solid(Pixel { r: 255, g: 8, b: 0 })),
+            "solid:ff0800".parse()
+        );
+        assert_eq!(Ok(Kind::Chase), "chase".parse());
+        assert!("solid:ff08".parse::<Kind>().is_err());
+        assert!("solid".parse::<Kind>().is_err());
+        assert!("plaid".parse::<Kind>().is_err());
+    }
+
+    #[test]
+    fn render() {
+        let d = Dimensions { w: 3, h: 2 };
+        let black = Pixel::default();
+        let white = Pixel {
+            r: 255,
+            g: 255,
+            b: 255,
+        };
+        assert_eq!(
+            vec![black, black, black, black, white, black],
+            Kind::Chase.render(d, 10)
+        );
+        assert_eq!(
+            Pixel { r: 0, g: 1, b: 4 },
+            Kind::Index.render(Dimensions { w: 300, h: 1 }, 0)[260]
+        );
+        assert_eq!(
+            vec![
+                Pixel { r: 0, g: 0, b: 0 },
+                Pixel { r: 127, g: 0, b: 0 },
+                Pixel { r: 255, g: 0, b: 0 },
+                Pixel { r: 0, g: 255, b: 0 },
+                Pixel {
+                    r: 127,
+                    g: 255,
+                    b: 0
+                },
+                Pixel {
+                    r: 255,
+                    g: 255,
+                    b: 0
+                },
+            ],
+            Kind::Gradient.render(d, 0)
+        );
+        // The rainbow scrolls by one pixel each frame.
+        let rainbow = Kind::Rainbow.render(d, 1);
+        assert_eq!(Kind::Rainbow.render(d, 0)[1], rainbow[0]);
+        assert_eq!(Pixel { r: 255, g: 0, b: 0 }, rainbow[2]);
+    }
+}
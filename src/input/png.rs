@@ -0,0 +1,77 @@
+use crate::geometry::*;
+use crate::input::*;
+use std::path::Path;
+
+/// Checks whether an input should be decoded as a PNG image rather than read as raw RGB24.
+pub fn is_png(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("png"))
+}
+
+/// Png produces a single frame from a PNG image with the same dimensions as the display.
+pub struct Png {
+    frame: Option<Vec<Pixel>>,
+}
+
+impl Png {
+    pub fn decode(r: impl io::BufRead + io::Seek, dimensions: Dimensions) -> io::Result<Png> {
+        let img = image::ImageReader::with_format(r, image::ImageFormat::Png)
+            .decode()
+            .map_err(io::Error::other)?
+            .into_rgb8();
+        let (w, h) = (img.width() as usize, img.height() as usize);
+        if (Dimensions { w, h }) != dimensions {
+            return Err(io::Error::other(format!(
+                "the image is {}x{}, but the geometry is {}x{}",
+                w, h, dimensions.w, dimensions.h
+            )));
+        }
+        let frame = img
+            .pixels()
+            .map(|p| Pixel {
+                r: p[0],
+                g: p[1],
+                b: p[2],
+            })
+            .collect();
+        Ok(Png { frame: Some(frame) })
+    }
+}
+
+impl Input for Png {
+    fn next_frame(&mut self) -> io::Result<Option<Vec<Pixel>>> {
+        Ok(self.frame.take())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(w: u32, h: u32, data: &[u8]) -> io::Cursor<Vec<u8>> {
+        let mut buf = io::Cursor::new(Vec::new());
+        image::RgbImage::from_raw(w, h, data.to_vec())
+            .unwrap()
+            .write_to(&mut buf, image::ImageFormat::Png)
+            .unwrap();
+        buf.set_position(0);
+        buf
+    }
+
+    #[test]
+    fn decode_single_frame() {
+        assert!(is_png("foo/bar.png"));
+        assert!(is_png("BAR.PNG"));
+        assert!(!is_png("/dev/stdin"));
+
+        let data: Vec<u8> = (0..2 * 3 * 3).collect();
+        let mut input = Png::decode(encode(2, 3, &data), Dimensions { w: 2, h: 3 }).unwrap();
+        let frame = input.next_frame().unwrap().unwrap();
+        assert_eq!(Pixel { r: 3, g: 4, b: 5 }, frame[1]);
+        assert_eq!(Pixel { r: 6, g: 7, b: 8 }, frame[2]);
+        assert_eq!(None, input.next_frame().unwrap());
+
+        assert!(Png::decode(encode(2, 3, &data), Dimensions { w: 3, h: 2 }).is_err());
+    }
+}
@@ -0,0 +1,125 @@
+use crate::geometry::*;
+use crate::input::*;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The prefix of --input values that should be replayed.
+pub const PREFIX: &str = "replay:";
+
+/// Identifies capture files. The last byte is the version of the format.
+const MAGIC: &[u8; 8] = b"ledcat\x00\x01";
+/// Set in the flags of the header if each frame is preceded by a timestamp.
+const FLAG_TIMESTAMPS: u8 = 1;
+
+/// The header of a capture file.
+///
+/// Capture files start with the following header, all multi-byte values are little endian:
+///
+/// | Offset | Size | Field                       |
+/// |--------|------|-----------------------------|
+/// | 0      | 8    | Magic, `ledcat\0` + version |
+/// | 8      | 4    | Width                       |
+/// | 12     | 4    | Height                      |
+/// | 16     | 1    | Flags                       |
+///
+/// The header is followed by the frames, each consisting of an optional 8 byte timestamp in
+/// microseconds since the first frame and the RGB24 pixels.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub dimensions: Dimensions,
+    pub timestamps: bool,
+}
+
+impl Header {
+    pub fn write(&self, mut w: impl io::Write) -> io::Result<()> {
+        w.write_all(MAGIC)?;
+        w.write_u32::<LittleEndian>(self.dimensions.w as u32)?;
+        w.write_u32::<LittleEndian>(self.dimensions.h as u32)?;
+        w.write_u8(if self.timestamps { FLAG_TIMESTAMPS } else { 0 })
+    }
+
+    fn read(mut r: impl io::Read) -> io::Result<Header> {
+        let mut magic = [0; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a ledcat capture file, or an unsupported version",
+            ));
+        }
+        let w = r.read_u32::<LittleEndian>()? as usize;
+        let h = r.read_u32::<LittleEndian>()? as usize;
+        let flags = r.read_u8()?;
+        Ok(Header {
+            dimensions: Dimensions { w, h },
+            timestamps: flags & FLAG_TIMESTAMPS != 0,
+        })
+    }
+}
+
+/// Writes a single frame to a capture file with the specified header.
+pub fn write_frame(
+    mut w: impl io::Write,
+    header: &Header,
+    timestamp: Duration,
+    frame: &[Pixel],
+) -> io::Result<()> {
+    if header.timestamps {
+        w.write_u64::<LittleEndian>(timestamp.as_micros() as u64)?;
+    }
+    let buf: Vec<u8> = frame.iter().flat_map(|p| [p.r, p.g, p.b]).collect();
+    w.write_all(&buf)
+}
+
+/// Replay plays back the frames of a capture file.
+pub struct Replay<R> {
+    reader: R,
+    header: Header,
+    /// Set if frames should be shown at the time they were captured.
+    use_timestamps: bool,
+    /// The time at which the capture would have started when played back in real time.
+    start: Option<Instant>,
+}
+
+impl<R: io::Read + Send> Replay<R> {
+    /// Reads the header of the capture file. If `use_timestamps` is set and the file contains
+    /// them, frames are delayed to match the timing of the capture.
+    pub fn new(mut reader: R, use_timestamps: bool) -> io::Result<Replay<R>> {
+        let header = Header::read(&mut reader)?;
+        Ok(Replay {
+            reader,
+            header,
+            use_timestamps: use_timestamps && header.timestamps,
+            start: None,
+        })
+    }
+
+    pub fn dimensions(&self) -> Dimensions {
+        self.header.dimensions
+    }
+}
+
+impl<R: io::Read + Send> Input for Replay<R> {
+    fn next_frame(&mut self) -> io::Result<Option<Vec<Pixel>>> {
+        let timestamp = if self.header.timestamps {
+            match self.reader.read_u64::<LittleEndian>() {
+                Ok(us) => Some(Duration::from_micros(us)),
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(err) => return Err(err),
+            }
+        } else {
+            None
+        };
+        let frame = Raw::new(&mut self.reader, self.header.dimensions.size()).next_frame()?;
+
+        if let (true, Some(timestamp), Some(_)) = (self.use_timestamps, timestamp, &frame) {
+            let start = *self.start.get_or_insert_with(|| Instant::now() - timestamp);
+            if let Some(d) = (start + timestamp).checked_duration_since(Instant::now()) {
+                thread::sleep(d);
+            }
+        }
+        Ok(frame)
+    }
+}
@@ -1,12 +1,26 @@
 use nix::{fcntl, poll};
 use std::fs;
 use std::io;
-use std::os::fd::{AsFd, AsRawFd, OwnedFd};
+use std::net;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd};
 use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
 use std::path;
 use std::thread;
 use std::time;
 
+/// The prefix of inputs that receive UDP datagrams on the address that follows it.
+pub const UDP_PREFIX: &str = "udp://";
+/// The prefix of inputs that accept a TCP connection on the address that follows it.
+pub const TCP_LISTEN_PREFIX: &str = "tcp-listen://";
+
+/// Listener accepts the connections of a TCP input, one at a time.
+struct Listener {
+    socket: net::TcpListener,
+    // Set while a client is connected. Otherwise, the listening socket is polled in place of the
+    // input.
+    connected: bool,
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum ExitCondition {
     Never,
@@ -18,8 +32,16 @@ pub struct Reader {
     exit_condition: ExitCondition,
 
     inputs: Vec<OwnedFd>,
+    // The paths of the inputs, retained so they can be reopened after reaching EOF. Empty if the
+    // inputs should not be reopened.
+    retry_paths: Vec<path::PathBuf>,
+    // The listener of each input that accepts TCP connections, None for other inputs.
+    listeners: Vec<Option<Listener>>,
     // The number of bytes after which another input is selected.
     switch_after: usize,
+    // A byte sequence that precedes each frame. Bytes are discarded until the marker is found, so
+    // the reader recovers from writers that are misaligned. Empty if frames are not marked.
+    marker: Vec<u8>,
     // A buffer for each input to be used for partially received content.
     buffers: Vec<Vec<u8>>,
     // The current buffer selected for output.
@@ -34,45 +56,45 @@ impl Reader {
         switch_after: usize,
         exit_condition: ExitCondition,
         clear_timeout: Option<time::Duration>,
+        retry: bool,
+        marker: Option<Vec<u8>>,
     ) -> io::Result<Reader>
     where
         P: AsRef<path::Path>,
     {
-        let files: io::Result<Vec<OwnedFd>> = filenames
-            .into_iter()
+        let mut listeners = Vec::new();
+        let files = filenames
+            .iter()
             .map(|filename| {
-                let mut open_opts = fs::OpenOptions::new();
-                open_opts.read(true);
-
-                let is_fifo = fs::metadata(&filename)?.file_type().is_fifo();
-                if is_fifo {
-                    // A FIFO will block the call to open() until the other end has been opened. This
-                    // means that when multiple FIFO's are used, they all have to be open at once
-                    // before this program can continue.
-                    // Opening the file with O_NONBLOCK will ensure that we don't have to wait.
-                    // After the file has been opened, there is no need to make reads block again since
-                    // poll(2) is used to check whether data is available.
-                    open_opts.custom_flags(fcntl::OFlag::O_NONBLOCK.bits());
-
-                    if exit_condition == ExitCondition::Never {
-                        // When the first program writing to the FIFO closes the writing end, poll will
-                        // immediately return with a POLLHUP for the respective reading end because all
-                        // writing ends have been closed. If we open the FIFO for writing ourselves,
-                        // there will always be writers. This ensures that poll never returnes POLLHUP.
-                        open_opts.write(true);
-                    }
-                }
-
-                let file = open_opts.open(&filename)?;
-                Ok(file.into())
+                let tcp_addr = filename
+                    .as_ref()
+                    .to_str()
+                    .and_then(|f| f.strip_prefix(TCP_LISTEN_PREFIX));
+                let Some(addr) = tcp_addr else {
+                    listeners.push(None);
+                    return open_input(filename.as_ref(), exit_condition);
+                };
+                let socket = net::TcpListener::bind(addr)?;
+                let fd = socket.try_clone()?.into();
+                listeners.push(Some(Listener {
+                    socket,
+                    connected: false,
+                }));
+                Ok(fd)
             })
-            .collect();
-        Ok(Reader::from(
-            files?,
-            switch_after,
-            exit_condition,
-            clear_timeout,
-        ))
+            .collect::<io::Result<Vec<OwnedFd>>>()?;
+        let mut reader = Reader::from(files, switch_after, exit_condition, clear_timeout);
+        reader.listeners = listeners;
+        if retry {
+            reader.retry_paths = filenames
+                .iter()
+                .map(|filename| filename.as_ref().to_path_buf())
+                .collect();
+        }
+        if let Some(marker) = marker {
+            reader.marker = marker;
+        }
+        Ok(reader)
     }
 
     pub fn from(
@@ -85,15 +107,89 @@ impl Reader {
         let buffers = (0..inputs.len())
             .map(|_| Vec::with_capacity(switch_after))
             .collect();
+        let listeners = (0..inputs.len()).map(|_| None).collect();
         Reader {
             switch_after,
+            marker: Vec::new(),
             buffers,
             exit_condition,
             inputs,
+            retry_paths: Vec::new(),
+            listeners,
             current: io::Cursor::new(Vec::new()),
             clear_timeout,
         }
     }
+
+    /// Replaces the input at the specified index by opening its file again.
+    fn reopen(&mut self, index: usize) -> io::Result<()> {
+        self.inputs[index] = match &mut self.listeners[index] {
+            Some(listener) => {
+                // Wait for the next client.
+                listener.connected = false;
+                listener.socket.try_clone()?.into()
+            }
+            None => open_input(&self.retry_paths[index], self.exit_condition)?,
+        };
+        // Whatever was left of the previous writer's frame is not going to be completed.
+        self.buffers[index].clear();
+        Ok(())
+    }
+
+    /// The number of bytes that are read for each frame, including the marker.
+    fn frame_len(&self) -> usize {
+        self.marker.len() + self.switch_after
+    }
+}
+
+/// Discards bytes from the start of the buffer until it starts with the marker or with the part of
+/// the marker that has been received so far.
+fn align(buf: &mut Vec<u8>, marker: &[u8]) {
+    let start = (0..buf.len())
+        .find(|&i| {
+            let n = marker.len().min(buf.len() - i);
+            buf[i..i + n] == marker[..n]
+        })
+        .unwrap_or(buf.len());
+    buf.drain(..start);
+}
+
+fn open_input(filename: &path::Path, exit_condition: ExitCondition) -> io::Result<OwnedFd> {
+    if let Some(addr) = filename.to_str().and_then(|f| f.strip_prefix(UDP_PREFIX)) {
+        // Each datagram is read as a whole, its contents are appended to the frame that is being
+        // received like any other input.
+        return Ok(net::UdpSocket::bind(addr)?.into());
+    }
+
+    let mut open_opts = fs::OpenOptions::new();
+    open_opts.read(true);
+
+    let is_fifo = fs::metadata(filename)?.file_type().is_fifo();
+    if is_fifo {
+        // A FIFO will block the call to open() until the other end has been opened. This
+        // means that when multiple FIFO's are used, they all have to be open at once
+        // before this program can continue.
+        // Opening the file with O_NONBLOCK will ensure that we don't have to wait.
+        // After the file has been opened, there is no need to make reads block again since
+        // poll(2) is used to check whether data is available.
+        open_opts.custom_flags(fcntl::OFlag::O_NONBLOCK.bits());
+
+        if exit_condition == ExitCondition::Never {
+            // When the first program writing to the FIFO closes the writing end, poll will
+            // immediately return with a POLLHUP for the respective reading end because all
+            // writing ends have been closed. If we open the FIFO for writing ourselves,
+            // there will always be writers. This ensures that poll never returnes POLLHUP.
+            open_opts.write(true);
+        }
+    }
+
+    let file = open_opts.open(filename)?;
+    Ok(file.into())
+}
+
+fn is_datagram_socket(fd: BorrowedFd<'_>) -> bool {
+    use nix::sys::socket::{getsockopt, sockopt, SockType};
+    getsockopt(&fd, sockopt::SockType).is_ok_and(|ty| ty == SockType::Datagram)
 }
 
 impl io::Read for Reader {
@@ -118,47 +214,83 @@ impl io::Read for Reader {
                     }
                 }
 
-                let mut num_open = poll_fds.len();
+                let num_inputs = poll_fds.len();
                 let mut ready_index = None;
+                // The inputs that have reached EOF or were closed by the other end.
+                let mut hung_up = Vec::new();
+                // The TCP inputs that have a client waiting to be accepted.
+                let mut accepted = Vec::new();
+                let frame_len = self.frame_len();
                 for (i, p) in poll_fds.iter().enumerate() {
                     let rev = p.revents().unwrap();
-                    if rev.contains(poll::PollFlags::POLLIN) {
+                    let accepting = self.listeners[i].as_ref().is_some_and(|l| !l.connected);
+                    if accepting && rev.contains(poll::PollFlags::POLLIN) {
+                        accepted.push(i);
+                    } else if rev.contains(poll::PollFlags::POLLIN) {
                         let buf = &mut self.buffers[i];
                         let buf_used = buf.len();
-                        assert_ne!(buf_used, self.switch_after);
+                        assert_ne!(buf_used, frame_len);
                         // Resize the buffer so there is just enough space for the remainder of the
                         // frame.
-                        buf.resize(self.switch_after, 0);
+                        buf.resize(frame_len, 0);
 
                         let nread = nix::unistd::read(p.as_fd().as_raw_fd(), &mut buf[buf_used..])?;
                         buf.resize(buf_used + nread, 0);
-                        assert!(buf.len() <= self.switch_after);
+                        if !self.marker.is_empty() {
+                            align(buf, &self.marker);
+                        }
+                        assert!(buf.len() <= frame_len);
                         if nread == 0 {
-                            // EOF
-                            num_open -= 1;
-                        } else if buf.len() == self.switch_after {
-                            ready_index = Some(i);
-                            break;
+                            // EOF, unless the input received an empty datagram.
+                            if !is_datagram_socket(p.as_fd()) {
+                                hung_up.push(i);
+                            }
+                        } else if buf.len() == frame_len {
+                            // Inputs are ordered by priority, so the first complete frame wins.
+                            // Frames of lower priority inputs that complete in the same poll are
+                            // discarded below.
+                            ready_index.get_or_insert(i);
                         }
                     } else if rev.intersects(
                         poll::PollFlags::POLLHUP
                             | poll::PollFlags::POLLNVAL
                             | poll::PollFlags::POLLERR,
                     ) {
+                        hung_up.push(i);
+                    }
+                }
+                drop(poll_fds);
+
+                for i in accepted {
+                    let listener = self.listeners[i].as_mut().unwrap();
+                    let (stream, _) = listener.socket.accept()?;
+                    listener.connected = true;
+                    self.inputs[i] = stream.into();
+                }
+
+                let mut num_open = num_inputs;
+                for &i in &hung_up {
+                    // TCP inputs keep listening for the next client if the input should never
+                    // be closed.
+                    let listening =
+                        self.listeners[i].is_some() && self.exit_condition == ExitCondition::Never;
+                    if listening || !self.retry_paths.is_empty() {
+                        self.reopen(i)?;
+                    } else {
                         num_open -= 1;
                     }
                 }
 
                 let close = match self.exit_condition {
                     ExitCondition::Never => false,
-                    ExitCondition::OneClosed => num_open < poll_fds.len() && ready_index.is_none(),
+                    ExitCondition::OneClosed => num_open < num_inputs && ready_index.is_none(),
                     ExitCondition::AllClosed => num_open == 0,
                 };
                 if close {
                     return Ok(0);
                 }
 
-                if num_open == 0 {
+                if num_open == 0 || (!hung_up.is_empty() && ready_index.is_none()) {
                     // Prevent a busy wait for inputs that make poll return immediately.
                     let wait = self
                         .clear_timeout
@@ -167,12 +299,19 @@ impl io::Read for Reader {
                 }
 
                 if let Some(i) = ready_index {
+                    for buf in &mut self.buffers[i + 1..] {
+                        if buf.len() == frame_len {
+                            buf.clear();
+                        }
+                    }
                     break i;
                 }
             };
-            let tail = self.buffers[ready_index].split_off(self.switch_after);
+            let frame_len = self.frame_len();
+            let tail = self.buffers[ready_index].split_off(frame_len);
             self.buffers.push(tail); // Later moved to index i by swap_remove.
-            let buf = self.buffers.swap_remove(ready_index);
+            let mut buf = self.buffers.swap_remove(ready_index);
+            buf.drain(..self.marker.len());
             self.current = io::Cursor::new(buf);
         }
         self.current.read(buf)
@@ -234,7 +373,7 @@ mod tests {
         let testdata: Vec<u8> = (1..num + 1)
             .fold(
                 Box::from(iter::empty()) as Box<dyn iter::Iterator<Item = _>>,
-                |ch, i| Box::from(ch.chain(iter::repeat(i as u8).take(len))),
+                |ch, i| Box::from(ch.chain(iter::repeat_n(i as u8, len))),
             )
             .collect();
 
@@ -261,20 +400,22 @@ mod tests {
         let len = 100;
         let num = 16;
 
+        // Input i has i frames, all of which are available at once.
         let mut reader = Reader::from(
             (1..num + 1)
-                .map(|i| new_iter_reader(iter::repeat(i).take(len)))
+                .map(|i| new_iter_reader(iter::repeat_n(i, len * i as usize)))
                 .collect(),
             len,
             ExitCondition::AllClosed,
             None,
         );
 
+        // The first input that has a frame wins, the frames of the other inputs that were
+        // completed at the same time are discarded.
         for i in 1..num + 1 {
             let mut rd_buf = vec![0; len];
             reader.read_exact(&mut rd_buf).unwrap();
-            let expected: Vec<u8> = iter::repeat(i).take(len).collect();
-            assert_eq!(expected, rd_buf);
+            assert_eq!(vec![i; len], rd_buf);
         }
         timeout!(time::Duration::from_secs(10), {
             assert_eq!(0, io::copy(&mut reader, &mut io::sink()).unwrap());
@@ -347,6 +488,8 @@ mod tests {
             len,
             ExitCondition::AllClosed,
             None,
+            false,
+            None,
         )
         .unwrap();
         let mut fifo1 = fs::OpenOptions::new()
@@ -361,10 +504,10 @@ mod tests {
         let mut rd_buf = vec![0; len];
 
         // Send a partial frame over fifo 1...
-        copy_iter(&mut fifo1, iter::repeat(pat1).take(len - 1));
+        copy_iter(&mut fifo1, iter::repeat_n(pat1, len - 1));
 
         // Send and receive a full frame over fifo 2.
-        let testdata: Vec<u8> = iter::repeat(pat2).take(len).collect();
+        let testdata: Vec<u8> = iter::repeat_n(pat2, len).collect();
         copy_iter(&mut fifo2, testdata.clone().into_iter());
         reader.read_exact(&mut rd_buf).unwrap();
         assert_eq!(testdata, rd_buf);
@@ -373,7 +516,7 @@ mod tests {
         // ...and complete that first frame over fifo 1.
         copy_iter(&mut fifo1, iter::once(pat1));
         reader.read_exact(&mut rd_buf).unwrap();
-        let expected: Vec<u8> = iter::repeat(pat1).take(len).collect();
+        let expected: Vec<u8> = iter::repeat_n(pat1, len).collect();
         assert_eq!(expected, rd_buf);
 
         drop(fifo1);
@@ -385,6 +528,166 @@ mod tests {
         tmp.close().unwrap();
     }
 
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn read_fifo_retry() {
+        let len = 10;
+        let (pat1, pat2) = (12, 42);
+
+        let tmp = tempdir().unwrap();
+        let fifo_path = tmp.path().join("fifo");
+        unistd::mkfifo(&fifo_path, Mode::from_bits(0o666).unwrap()).unwrap();
+        let mut reader = Reader::from_files(
+            vec![&fifo_path],
+            len,
+            ExitCondition::AllClosed,
+            None,
+            true,
+            None,
+        )
+        .unwrap();
+
+        // Send a frame and disconnect the writer.
+        let mut fifo = fs::OpenOptions::new().write(true).open(&fifo_path).unwrap();
+        copy_iter(&mut fifo, iter::repeat_n(pat1, len));
+        drop(fifo);
+        let mut rd_buf = vec![0; len];
+        reader.read_exact(&mut rd_buf).unwrap();
+        assert_eq!(vec![pat1; len], rd_buf);
+
+        // A new writer connects some time later, the reader should not have given up.
+        let writer_path = fifo_path.clone();
+        let writer = thread::spawn(move || {
+            thread::sleep(time::Duration::from_millis(100));
+            let mut fifo = fs::OpenOptions::new()
+                .write(true)
+                .open(&writer_path)
+                .unwrap();
+            copy_iter(&mut fifo, iter::repeat_n(pat2, len));
+        });
+        timeout!(time::Duration::from_secs(10), {
+            let mut rd_buf = vec![0; len];
+            reader.read_exact(&mut rd_buf).unwrap();
+            assert_eq!(vec![pat2; len], rd_buf);
+        });
+        writer.join().unwrap();
+
+        tmp.close().unwrap();
+    }
+
+    #[test]
+    fn align_marker() {
+        let marker = [0xaa, 0xbb];
+        let mut buf = vec![1, 2, 0xaa, 0xbb, 3];
+        align(&mut buf, &marker);
+        assert_eq!(vec![0xaa, 0xbb, 3], buf);
+
+        // A marker that is cut off at the end of the buffer is kept.
+        let mut buf = vec![1, 0xbb, 0xaa];
+        align(&mut buf, &marker);
+        assert_eq!(vec![0xaa], buf);
+
+        let mut buf = vec![1, 2, 3];
+        align(&mut buf, &marker);
+        assert!(buf.is_empty());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn read_marker_resync() {
+        let len = 4;
+        let marker = vec![0xaa, 0xbb];
+        let testdata = [
+            // The tail of a frame from a writer that started mid-stream.
+            &[5, 5, 5][..],
+            &marker,
+            &[1; 4],
+            &marker,
+            &[2; 4],
+        ]
+        .concat();
+        let mut reader = Reader::from(
+            vec![new_iter_reader(testdata.into_iter())],
+            len,
+            ExitCondition::AllClosed,
+            None,
+        );
+        reader.marker = marker;
+
+        for i in 1..3 {
+            let mut rd_buf = vec![0; len];
+            reader.read_exact(&mut rd_buf).unwrap();
+            assert_eq!(vec![i; len], rd_buf);
+        }
+        timeout!(time::Duration::from_secs(10), {
+            assert_eq!(0, io::copy(&mut reader, &mut io::sink()).unwrap());
+        });
+    }
+
+    #[test]
+    fn read_udp() {
+        let len = 6;
+        let socket = net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let mut reader = Reader::from(vec![socket.into()], len, ExitCondition::AllClosed, None);
+
+        let sender = net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        // A frame may be split over multiple datagrams, empty datagrams are skipped.
+        for datagram in [&[1; 6][..], &[2; 4], &[], &[2; 2]] {
+            sender.send_to(datagram, addr).unwrap();
+        }
+        timeout!(time::Duration::from_secs(10), {
+            for i in 1..3 {
+                let mut rd_buf = vec![0; len];
+                reader.read_exact(&mut rd_buf).unwrap();
+                assert_eq!(vec![i; len], rd_buf);
+            }
+        });
+    }
+
+    #[test]
+    fn read_tcp_listen() {
+        let len = 4;
+        for exit_condition in [ExitCondition::Never, ExitCondition::AllClosed] {
+            let mut reader = Reader::from_files(
+                vec!["tcp-listen://127.0.0.1:0"],
+                len,
+                exit_condition,
+                None,
+                false,
+                None,
+            )
+            .unwrap();
+            let addr = reader.listeners[0]
+                .as_ref()
+                .unwrap()
+                .socket
+                .local_addr()
+                .unwrap();
+
+            net::TcpStream::connect(addr)
+                .unwrap()
+                .write_all(&[1; 4])
+                .unwrap();
+            timeout!(time::Duration::from_secs(10), {
+                let mut rd_buf = vec![0; len];
+                reader.read_exact(&mut rd_buf).unwrap();
+                assert_eq!(vec![1; len], rd_buf);
+                if exit_condition == ExitCondition::Never {
+                    // The next client is accepted after the first one disconnected.
+                    net::TcpStream::connect(addr)
+                        .unwrap()
+                        .write_all(&[2; 4])
+                        .unwrap();
+                    reader.read_exact(&mut rd_buf).unwrap();
+                    assert_eq!(vec![2; len], rd_buf);
+                } else {
+                    assert_eq!(0, io::copy(&mut reader, &mut io::sink()).unwrap());
+                }
+            });
+        }
+    }
+
     #[test]
     fn clear_timeout() {
         let len = 10;
@@ -398,6 +701,8 @@ mod tests {
             len,
             ExitCondition::AllClosed,
             Some(timeout),
+            false,
+            None,
         )
         .unwrap();
         let mut fifo = fs::OpenOptions::new().write(true).open(&fifo_path).unwrap();
@@ -410,12 +715,12 @@ mod tests {
         });
 
         // Send a partial frame over the fifo.
-        copy_iter(&mut fifo, iter::repeat(1).take(len - 1));
+        copy_iter(&mut fifo, iter::repeat_n(1, len - 1));
 
         // Wait for the clear timeout to expire and the partial frame to be discarded.
         thread::sleep(timeout * 2);
         // Send a full frame over the fifo.
-        copy_iter(&mut fifo, iter::repeat(2).take(len));
+        copy_iter(&mut fifo, iter::repeat_n(2, len));
 
         thread.join().unwrap();
         tmp.close().unwrap();
@@ -1,9 +1,12 @@
 #[macro_use]
 mod util;
+mod calibrate;
 mod color;
+mod config;
 mod device;
 mod driver;
 mod input;
+mod pipeline;
 
 use crate::color::*;
 use crate::device::*;
@@ -15,19 +18,21 @@ use std::env;
 use std::error::Error;
 use std::fmt;
 use std::fs;
-use std::io;
+use std::io::{self, Read};
 use std::iter;
+use std::os::fd::IntoRawFd;
 use std::path::PathBuf;
 use std::process;
+use std::sync::atomic::{AtomicI32, Ordering};
 use std::sync::mpsc;
 use std::thread;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
 fn main() -> Result<(), Box<dyn Error>> {
     let mut cli = clap::command!()
         .arg(clap::arg!(-o --output <file> "The output file to write to. Use - for stdout.")
             .default_value("-"))
-        .arg(clap::arg!(-i --input <file> ... "The inputs to read from. Read the manual for how inputs are read and prioritized.")
+        .arg(clap::arg!(-i --input <file> ... "The inputs to read from, in order of priority. Read the manual for how inputs are read and prioritized.")
             .default_value("-"))
         .arg(clap::arg!(-e --exit <value> "Set the exit condition. \"one\" and \"all\" indicate the number of files that should be closed to trigger")
             .value_parser(["never", "one", "all"])
@@ -35,32 +40,189 @@ fn main() -> Result<(), Box<dyn Error>> {
         .arg(clap::arg!(--"clear-timeout" <value> "Sets a timeout in milliseconds after which partially read frames are deleted. If a framerate is set, a timeout is calculated automatically.")
             .value_parser(clap::value_parser!(u32))
             .conflicts_with("framerate"))
-        .arg(clap::arg!(-g --geometry <value> "Specify the size of the display. Can be either a number for 1D, WxH for 2D, or \"env\" to load the LEDCAT_GEOMETRY environment variable.")
+        .arg(clap::arg!(--"input-retry" "Reopen inputs after they reach EOF, e.g. to survive restarts of the program writing to a FIFO. Regular files are played in a loop"))
+        .arg(clap::arg!(--"frame-delimiter" <hex> "Split frames on a delimiter byte sequence instead of by length. Frames are padded or truncated to fit the display. Only one input can be used")
+            .value_parser(util::parse_hex))
+        .arg(clap::arg!(--"frame-marker" <hex> "Expect each frame to be preceded by a marker byte sequence. Input is discarded until the marker is found, so misaligned frames are recovered from")
+            .value_parser(util::parse_hex)
+            .conflicts_with("frame-delimiter"))
+        .arg(clap::arg!(-g --geometry <value> "Specify the size of the display. Can be either a number for 1D, WxH for 2D, \"terminal\" to fill the terminal when using show, or \"env\" to load the LEDCAT_GEOMETRY environment variable.")
             .alias("num-pixels")
+            .value_parser(clap::value_parser!(Geometry)))
+        .arg(clap::arg!(--"input-geometry" <value> "The size of the frames read from the input as WxH, if larger than the display. Frames are scaled down to the geometry of the display by averaging blocks of pixels")
             .value_parser(clap::value_parser!(Dimensions)))
-        .arg(clap::arg!(-t --transpose <value> ... "Apply one or more transpositions to the output")
-            .value_parser(["reverse", "zigzag_x", "zigzag_y", "mirror_x", "mirror_y"]))
-        .arg(clap::arg!(-c --"color-correction" <value> "Override the default color correction. The default is determined per device.")
-            .value_parser(["none", "srgb"]))
-        .arg(clap::arg!(--dim <value> "Apply a global grayscale before the collor correction. The value should be between 0 and 1.0 inclusive")
+        .arg(clap::arg!(-t --transpose <value> ... "Apply one or more transpositions to the output. Multiple operations can be chained in a single value separated by commas. One of reverse, zigzag_x, zigzag_y, mirror_x, mirror_y, rotate_90, rotate_180, rotate_270, tile:WxH[:OPERATION], affine:a,b,c,d,tx,ty or map:FILE"))
+        .arg(clap::arg!(--"detect-layout" "Interactively find the transpositions that match the wiring of a 2D display")
+            .conflicts_with_all(["transpose", "reverse", "skip-every"]))
+        .arg(clap::arg!(--reverse "Reverse the order of the pixels, this is applied before any other transposition"))
+        .arg(clap::arg!(--"skip-every" <n> "Insert a black pixel after every n-1 pixels for strips where every nth pixel is not connected. The geometry includes the pixels that are not connected")
+            .value_parser(clap::value_parser!(u32).range(2..)))
+        .arg(clap::arg!(-c --"color-correction" <value> "Override the default color correction. One of none, srgb, gamma22, gamma24, gamma28 or gamma:VALUE. The default is determined per device.")
+            .value_parser(|s: &str| -> Result<String, String> {
+                match s {
+                    "none" | "srgb" | "gamma22" | "gamma24" | "gamma28" => Ok(s.to_string()),
+                    _ => match s.strip_prefix("gamma:").map(str::parse::<f64>) {
+                        Some(Ok(gamma)) if gamma > 0.0 => Ok(s.to_string()),
+                        Some(_) => Err("the gamma must be a positive number".to_string()),
+                        None => Err(format!("unknown color correction \"{}\"", s)),
+                    },
+                }
+            }))
+        .arg(clap::arg!(--curve <points> "Use a color correction curve that is interpolated from control points, e.g. \"0:0,128:40,255:255\"")
+            .value_parser(|s: &str| -> Result<Vec<(u8, u8)>, String> {
+                s.split(',')
+                    .map(|point| {
+                        let (x, y) = point
+                            .split_once(':')
+                            .ok_or_else(|| format!("\"{}\" is not formatted as in:out", point))?;
+                        let parse = |v: &str| v.trim().parse::<u8>().map_err(|err| err.to_string());
+                        Ok((parse(x)?, parse(y)?))
+                    })
+                    .collect()
+            })
+            .conflicts_with("color-correction"))
+        .arg(clap::arg!(--dim <value> "Scale the brightness of the output after the color correction. The value should be between 0 and 1.0 inclusive")
+            .default_value("1.0")
+            .value_parser(clap::value_parser!(f32)))
+        .arg(clap::arg!(--"hue-shift" <degrees> "Rotate the hue of all colors")
+            .default_value("0")
+            .value_parser(clap::value_parser!(f32))
+            .allow_negative_numbers(true))
+        .arg(clap::arg!(--saturation <factor> "Multiply the saturation of all colors")
             .default_value("1.0")
             .value_parser(clap::value_parser!(f32)))
+        .arg(clap::arg!(--value <factor> "Multiply the value (brightness in HSV) of all colors. Unlike --dim, values above 1 are allowed")
+            .default_value("1.0")
+            .value_parser(clap::value_parser!(f32)))
+        .arg(clap::arg!(--"white-balance" <kelvin> "Shift the white point to a color temperature in Kelvin. 6500 is neutral, lower values are warmer")
+            .value_parser(clap::value_parser!(u32).range(1000..=40000)))
+        .arg(clap::arg!(--"black-level" <value> "Adjust the black level after color correction. A positive value lifts black to that level, a negative value clips everything below it to black")
+            .value_parser(clap::value_parser!(i32).range(-255..=255))
+            .allow_negative_numbers(true))
+        .arg(clap::arg!(--"max-channel" <rgb> "Never output more than these values for the red, green and blue channels, e.g. 255,255,200")
+            .value_parser(parse_rgb))
+        .arg(clap::arg!(--"color-order" <order> "The order of the color channels of the LEDs, e.g. grb or bgr, for when the colors of the display appear swapped")
+            .value_parser(clap::value_parser!(ColorOrder)))
+        .arg(clap::arg!(--dither "Carry the rounding error of the color correction over to the next frame to reduce banding at low brightness. Works best at high frame rates"))
+        .arg(clap::arg!(--"white-mode" <mode> "How the white channel of RGBW devices is extracted from the color")
+            .value_parser(["none", "min", "accurate"])
+            .default_value("min"))
+        .arg(clap::arg!(--"white-point" <rgb> "The color of the white LED of RGBW devices as R,G,B, used by --white-mode accurate")
+            .value_parser(parse_rgb)
+            .default_value("255,255,255"))
+        .arg(clap::arg!(--"max-power" <watts> "Dim frames that are estimated to draw more power than this")
+            .value_parser(clap::value_parser!(f64)))
+        .arg(clap::arg!(--"channel-current" <mA> "The current drawn by a single color channel at full brightness, used by --max-power")
+            .value_parser(clap::value_parser!(f64))
+            .default_value("20"))
+        .arg(clap::arg!(--"led-voltage" <volts> "The voltage the LEDs are powered with, used by --max-power")
+            .value_parser(clap::value_parser!(f64))
+            .default_value("5"))
+        .arg(clap::arg!(--"pause-gpio" <pin> "Hold back frames while this GPIO pin is high, e.g. to pause with a button")
+            .value_parser(clap::value_parser!(u16)))
         .arg(clap::arg!(--driver <value> "The driver to use for the output. If this is not specified, the driver is automaticaly detected based on the output"))
         .arg(clap::arg!(--"serial-baudrate" <value>  "If serial is used as driver, use this to set the baudrate")
             .value_parser(clap::value_parser!(u32))
             .default_value("1152000"))
+        .arg(clap::arg!(--"serial-dtr" <state> "Set the DTR line of the serial port after opening it. Many Arduino boards reset when it is toggled, \"off\" also keeps it from dropping on exit")
+            .value_parser(["on", "off"]))
+        .arg(clap::arg!(--"serial-rts" <state> "Set the RTS line of the serial port after opening it")
+            .value_parser(["on", "off"]))
+        .arg(clap::arg!(--"serial-frame-delay" <ms> "Wait until each frame has been transmitted over the serial port and then pause for this number of milliseconds, for devices that can not keep up")
+            .value_parser(clap::value_parser!(u64)))
+        .arg(clap::arg!(--"serial-reset-delay" <ms> "Wait this number of milliseconds after opening the serial port, e.g. to let the bootloader of the device finish")
+            .value_parser(clap::value_parser!(u64)))
         .arg(clap::arg!(-f --framerate <value> "Limit the number of frames per second")
             .value_parser(clap::value_parser!(u32)))
+        .arg(clap::arg!(--"drop-frames" "When the output can not keep up with the input, skip to the most recent frame instead of slowing down the input"))
+        .arg(clap::arg!(--"buffer-frames" <n> "The number of frames that can be queued up for the output. Higher values absorb jitter at the cost of latency")
+            .value_parser(clap::value_parser!(u32).range(1..))
+            .default_value("1")
+            .conflicts_with("drop-frames"))
+        .arg(clap::arg!(--"stdin-timeout" <ms> "Exit with an error if no frame has been read from the input within this number of milliseconds after starting")
+            .value_parser(clap::value_parser!(u64)))
+        .arg(clap::arg!(--"blank-on-timeout" <ms> "Show --blank-color when no frame has been received for this number of milliseconds, until frames arrive again")
+            .value_parser(clap::value_parser!(u64).range(1..)))
+        .arg(clap::arg!(--"blank-color" <rgb> "The color shown by --blank-on-timeout as R,G,B")
+            .value_parser(parse_rgb)
+            .default_value("0,0,0"))
+        .arg(clap::arg!(--profile "Print the time spent reading, mapping and outputting frames on exit"))
+        .arg(clap::arg!(--"input-fps-report" "Periodically print the rate at which the input produces frames"))
+        .arg(clap::arg!(--"fps-log" "Periodically print the rate at which frames are sent to the output"))
+        .arg(clap::arg!(--stats "Print the output frame rate, the number of dropped frames and the output latency every second"))
+        .arg(clap::arg!(--preview "Also render each frame to the terminal attached to stderr"))
+        .arg(clap::arg!(--"preview-scale" <n> "Shrink the preview by averaging blocks of n by n pixels")
+            .value_parser(clap::value_parser!(u32).range(1..))
+            .default_value("1")
+            .requires("preview"))
+        .arg(clap::arg!(--snapshot <file> "Write each frame to an image after transposition and color correction, as PNG if the file ends in .png and as PPM otherwise. Use with --one to inspect the output")
+            .value_parser(clap::value_parser!(PathBuf)))
+        .arg(clap::arg!(-v --verbose ... "Print more diagnostic information. Can be repeated"))
+        .arg(clap::arg!(--loop "Play animated GIF inputs in a loop"))
         .arg(clap::arg!(-'1' --one "Send a single frame to the output and exit")
-            .conflicts_with("framerate"));
+            .conflicts_with("framerate"))
+        .arg(clap::arg!(--"no-clear-on-exit" "Keep showing the last frame when interrupted by SIGINT or SIGTERM instead of turning the display off"))
+        .arg(clap::arg!(--config <file> "Load default values for the options from a TOML file. Options on the command line take precedence"))
+        .arg(clap::arg!(--"list-devices" "Print the names of the device subcommands, one per line, and exit"))
+        .arg(clap::arg!(--"list-drivers" "Print the names of the drivers that can be detected, one per line, and exit"));
+
+    // Global options can also be set through LEDCAT_* environment variables. The geometry is
+    // excluded, since LEDCAT_GEOMETRY is read by env_dimensions.
+    let ids: Vec<String> = cli
+        .get_arguments()
+        .map(|arg| arg.get_id().to_string())
+        .filter(|id| !["geometry", "list-devices", "list-drivers"].contains(&id.as_str()))
+        .collect();
+    for id in ids {
+        cli = cli.mut_arg(&id, |arg| {
+            let arg = arg.env(env_var_name(&id));
+            match arg.get_action() {
+                // Accept the usual ways of writing booleans, like 1, yes and on.
+                clap::ArgAction::SetTrue => {
+                    arg.value_parser(clap::builder::BoolishValueParser::new())
+                }
+                _ => arg,
+            }
+        });
+    }
 
     let mut device_constructors = BTreeMap::new();
     for (command, from_command) in device::devices() {
         device_constructors.insert(command.get_name().to_string(), from_command);
         cli = cli.subcommand(command);
     }
+    // Added after the devices, so the completions cover all subcommands.
+    cli = cli.subcommand(
+        clap::Command::new("completions")
+            .about("Print a shell completion script to stdout")
+            .hide(true)
+            .arg(
+                clap::arg!(<shell> "The shell to generate completions for")
+                    .value_parser(clap::value_parser!(clap_complete::Shell)),
+            ),
+    );
+
+    let config_path = config::path_from_args(env::args().skip(1))
+        .or_else(|| env::var(env_var_name("config")).ok());
+    if let Some(path) = config_path {
+        cli = config::load(cli, &path)
+            .map_err(|err| GenericError::new(format!("could not load {}: {}", path, err)))?;
+    }
 
     let matches = cli.clone().get_matches();
+    if matches.get_flag("list-devices") || matches.get_flag("list-drivers") {
+        if matches.get_flag("list-devices") {
+            for name in device_constructors.keys() {
+                println!("{}", name);
+            }
+        }
+        if matches.get_flag("list-drivers") {
+            for name in driver::detector_names() {
+                println!("{}", name);
+            }
+        }
+        return Ok(());
+    }
     let (sub_name, sub_matches) = match matches.subcommand() {
         Some(v) => v,
         None => {
@@ -70,6 +232,14 @@ fn main() -> Result<(), Box<dyn Error>> {
             process::exit(1);
         }
     };
+    if sub_name == "completions" {
+        let shell = *sub_matches
+            .get_one::<clap_complete::Shell>("shell")
+            .unwrap();
+        let name = cli.get_name().to_string();
+        clap_complete::generate(shell, &mut cli, name, &mut io::stdout());
+        return Ok(());
+    }
 
     let gargs = GlobalArgs {
         output_file: {
@@ -81,9 +251,30 @@ fn main() -> Result<(), Box<dyn Error>> {
         },
         // Don't require the display geomtry to be set just yet, a non-outputting subcommand may
         // not need it anyway.
-        dimensions: matches.get_one::<Dimensions>("geometry").copied(),
+        dimensions: match matches.get_one::<Geometry>("geometry") {
+            Some(Geometry::Dimensions(d)) => Some(*d),
+            Some(Geometry::Env) => env_dimensions(),
+            Some(Geometry::Terminal) if sub_name == "show" => Some(simulator::terminal_dimensions(
+                simulator::Density::from_args(sub_matches),
+            )?),
+            Some(Geometry::Terminal) => {
+                return Err(
+                    GenericError::new("--geometry terminal can only be used with show").into(),
+                )
+            }
+            None if sub_name == "show" => env_dimensions().or_else(|| {
+                simulator::terminal_dimensions(simulator::Density::from_args(sub_matches)).ok()
+            }),
+            None => env_dimensions(),
+        },
+        white_mode: match matches.get_one::<String>("white-mode").unwrap().as_str() {
+            "none" => WhiteMode::None,
+            "accurate" => WhiteMode::Accurate(*matches.get_one::<[u8; 3]>("white-point").unwrap()),
+            _ => WhiteMode::Min,
+        },
+        verbosity: matches.get_count("verbose"),
     };
-    let output: Box<dyn Output> = {
+    let mut output: Box<dyn Output> = {
         let from_command = device_constructors[sub_name](sub_matches, &gargs)?;
         match from_command {
             FromCommand::Device(dev) => {
@@ -102,7 +293,22 @@ fn main() -> Result<(), Box<dyn Error>> {
                     ),
                     "serial" => {
                         let baudrate = matches.get_one::<u32>("serial-baudrate").unwrap();
-                        Box::new(serial::open(&gargs.output_file, *baudrate).unwrap())
+                        let line =
+                            |name| matches.get_one::<String>(name).map(|state| state == "on");
+                        let opts = serial::Options {
+                            dtr: line("serial-dtr"),
+                            rts: line("serial-rts"),
+                            reset_delay: matches
+                                .get_one::<u64>("serial-reset-delay")
+                                .map(|ms| Duration::from_millis(*ms)),
+                        };
+                        let tty = serial::open(&gargs.output_file, *baudrate, &opts).unwrap();
+                        match matches.get_one::<u64>("serial-frame-delay") {
+                            Some(&ms) => {
+                                Box::new(serial::FrameGap::new(tty, Duration::from_millis(ms)))
+                            }
+                            None => Box::new(tty),
+                        }
                     }
                     d => return Err(GenericError::new(format!("unknown driver {}", d)).into()),
                 };
@@ -112,13 +318,59 @@ fn main() -> Result<(), Box<dyn Error>> {
             FromCommand::SubcommandHandled => return Ok(()),
         }
     };
-    let dimensions = gargs.dimensions()?;
+    let dimensions = gargs
+        .dimensions()
+        .or_else(|err| output.default_dimensions().ok_or(err))?;
 
-    let transposition = match matches.get_many::<String>("transpose") {
-        Some(v) => transposition_table(&dimensions, v.map(|s| s.as_str())),
-        None => transposition_table(&dimensions, iter::empty()),
-    }?;
+    // --reverse is applied before the transpositions set with --transpose.
+    let transpose_ops = iter::once("reverse")
+        .filter(|_| matches.get_flag("reverse"))
+        .chain(
+            matches
+                .get_many::<String>("transpose")
+                .into_iter()
+                .flatten()
+                .flat_map(|chain| split_chain(chain)),
+        );
+    // The geometry is that of the display, the input only provides the pixels that are connected.
+    let output_size = dimensions.size();
+    let skip = matches.get_one::<u32>("skip-every").map(|&n| n as usize);
+    let dimensions = match skip {
+        Some(n) => connected_dimensions(&dimensions, n)?,
+        None => dimensions,
+    };
+    let transposition = transposition_table(&dimensions, transpose_ops.clone())?;
     assert_eq!(dimensions.size(), transposition.len());
+    let transposition = match skip {
+        Some(n) => skip_every(transposition, n),
+        None => transposition,
+    };
+
+    if matches.get_flag("preview") {
+        let scale = *matches.get_one::<u32>("preview-scale").unwrap() as usize;
+        output = Box::new(simulator::Preview::new(
+            output,
+            dimensions,
+            transposition.clone(),
+            scale,
+        )?);
+    }
+
+    if let Some(path) = matches.get_one::<PathBuf>("snapshot") {
+        output = Box::new(snapshot::Snapshot::new(
+            output,
+            path.clone(),
+            transposed_dimensions(&dimensions, transpose_ops)?,
+        ));
+    }
+
+    if let Some(pin) = matches.get_one::<u16>("pause-gpio") {
+        let pin = driver::gpio::sysfs::SysFsGpioInput::exported_new(
+            driver::gpio::sysfs::DEFAULT_BASE,
+            *pin,
+        )?;
+        output = Box::new(Paused::new(output, pin)?);
+    }
 
     let color_correction = matches
         .get_one::<String>("color-correction")
@@ -126,16 +378,70 @@ fn main() -> Result<(), Box<dyn Error>> {
         .and_then(|name| match name {
             "none" => Some(Correction::none()),
             "srgb" => Some(Correction::srgb(255, 255, 255)),
-            _ => None,
+            "gamma22" => Some(Correction::gamma(255, 255, 255, 2.2)),
+            "gamma24" => Some(Correction::gamma(255, 255, 255, 2.4)),
+            "gamma28" => Some(Correction::gamma(255, 255, 255, 2.8)),
+            name => name
+                .strip_prefix("gamma:")
+                .map(|gamma| Correction::gamma(255, 255, 255, gamma.parse().unwrap())),
         })
         .unwrap_or_else(|| output.color_correction());
-    let dim = (matches.get_one::<f32>("dim").unwrap().clamp(0.0, 1.0) * 255.0).round() as u8;
+    let color_correction = match matches.get_one::<Vec<(u8, u8)>>("curve") {
+        Some(points) => Correction::from_points(points).map_err(GenericError::new)?,
+        None => color_correction,
+    };
+    // Dimming is applied last so it scales the brightness of the corrected output.
+    let dim = f64::from(matches.get_one::<f32>("dim").unwrap().clamp(0.0, 1.0));
+    let mut builder = CorrectionBuilder::from(color_correction);
+    if let Some(&kelvin) = matches.get_one::<u32>("white-balance") {
+        builder = builder.white_balance(f64::from(kelvin));
+    }
+    if let Some(&[r, g, b]) = matches.get_one::<[u8; 3]>("max-channel") {
+        builder = builder.channel_max(r, g, b);
+    }
+    let color_correction = builder.dim(dim).build();
+    // The black level is applied to the final output, so it is not affected by dimming.
+    let color_correction = match matches.get_one::<i32>("black-level").copied() {
+        Some(level) if level > 0 => color_correction.with_black_level(level as u8),
+        Some(level) if level < 0 => color_correction.with_black_clip(-level as u8),
+        _ => color_correction,
+    };
+
+    if matches.get_flag("detect-layout") {
+        if dimensions.h < 2 {
+            return Err(GenericError::new("--detect-layout requires a 2D --geometry").into());
+        }
+        // Stdin and stdout may be in use for frames, so the user is asked through the terminal.
+        let tty = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/tty")?;
+        calibrate::detect_layout(
+            &mut output,
+            dimensions,
+            &color_correction,
+            io::BufReader::new(tty.try_clone()?),
+            tty,
+        )?;
+        return Ok(());
+    }
 
     let frame_interval = matches
         .get_one::<u32>("framerate")
         .map(|fps| Duration::from_secs(1) / *fps);
-    let single_frame = matches.get_flag("one");
+    let mut single_frame = matches.get_flag("one");
 
+    let input_dimensions = match matches.get_one::<Dimensions>("input-geometry") {
+        Some(d) if d.w < dimensions.w || d.h < dimensions.h => {
+            return Err(GenericError::new(format!(
+                "--input-geometry {}x{} is smaller than the geometry {}x{}",
+                d.w, d.h, dimensions.w, dimensions.h
+            ))
+            .into())
+        }
+        Some(d) => *d,
+        None => dimensions,
+    };
     let input = {
         let exit_condition = {
             match matches.get_one::<String>("exit").map(String::as_str) {
@@ -152,7 +458,7 @@ fn main() -> Result<(), Box<dyn Error>> {
                 "-" => "/dev/stdin",
                 f => f,
             })
-            .collect();
+            .collect::<Vec<_>>();
         let clear_timeout = frame_interval.map(|t| t * 2).unwrap_or_else(|| {
             let ms = matches
                 .get_one::<u32>("clear-timeout")
@@ -160,130 +466,281 @@ fn main() -> Result<(), Box<dyn Error>> {
                 .unwrap_or(100);
             Duration::from_millis(ms as u64)
         });
-        select::Reader::from_files(
-            files,
-            dimensions.size() * 3,
-            exit_condition,
-            Some(clear_timeout),
-        )
-        .unwrap()
+        let replay_path = match files[..] {
+            [file] => file.strip_prefix(replay::PREFIX),
+            _ if files.iter().any(|f| f.starts_with(replay::PREFIX)) => {
+                return Err(GenericError::new("a replay can only be used as the only input").into())
+            }
+            _ => None,
+        };
+        let pattern = match files[..] {
+            [file] => file.strip_prefix(pattern::PREFIX),
+            _ if files.iter().any(|f| f.starts_with(pattern::PREFIX)) => {
+                return Err(
+                    GenericError::new("a pattern can only be used as the only input").into(),
+                )
+            }
+            _ => None,
+        };
+        let is_image = |f: &str| png::is_png(f) || gif::is_gif(f);
+        let image_path = match files[..] {
+            _ if replay_path.is_some() || pattern.is_some() => None,
+            [file] if is_image(file) => Some(file),
+            _ if files.iter().any(|f| is_image(f)) => {
+                return Err(GenericError::new("an image can only be used as the only input").into())
+            }
+            _ => None,
+        };
+        if let Some(kind) = pattern {
+            let kind = kind.parse().map_err(GenericError::new)?;
+            Box::new(pattern::Pattern::new(
+                kind,
+                input_dimensions,
+                frame_interval.is_none(),
+            )) as Box<dyn Input>
+        } else if let Some(path) = image_path {
+            let file = io::BufReader::new(fs::File::open(path)?);
+            if gif::is_gif(path) {
+                Box::new(gif::Gif::decode(
+                    file,
+                    input_dimensions,
+                    frame_interval.is_none(),
+                    matches.get_flag("loop"),
+                )?) as Box<dyn Input>
+            } else {
+                // There is only one frame to show.
+                single_frame = true;
+                Box::new(png::Png::decode(file, input_dimensions)?)
+            }
+        } else if let Some(path) = replay_path {
+            let replay = replay::Replay::new(
+                io::BufReader::new(fs::File::open(path)?),
+                frame_interval.is_none(),
+            )?;
+            if replay.dimensions().size() != input_dimensions.size() {
+                return Err(GenericError::new(format!(
+                    "the replay has {} pixels, but the geometry has {}",
+                    replay.dimensions().size(),
+                    input_dimensions.size()
+                ))
+                .into());
+            }
+            Box::new(replay) as Box<dyn Input>
+        } else {
+            let input_retry = matches.get_flag("input-retry");
+            let reader = match matches.get_one::<Vec<u8>>("frame-delimiter") {
+                Some(delimiter) => {
+                    if files.len() != 1 {
+                        return Err(GenericError::new(
+                            "--frame-delimiter can only be used with a single input",
+                        )
+                        .into());
+                    }
+                    // Frame boundaries are determined by the delimiter, so the select reader should
+                    // hand out bytes as soon as they arrive.
+                    let reader = select::Reader::from_files(
+                        files,
+                        1,
+                        exit_condition,
+                        Some(clear_timeout),
+                        input_retry,
+                        None,
+                    )?;
+                    Box::new(delimit::Reader::new(
+                        reader,
+                        delimiter.clone(),
+                        input_dimensions.size() * 3,
+                    )) as Box<dyn io::Read + Send>
+                }
+                None => Box::new(select::Reader::from_files(
+                    files,
+                    input_dimensions.size() * 3,
+                    exit_condition,
+                    Some(clear_timeout),
+                    input_retry,
+                    matches.get_one::<Vec<u8>>("frame-marker").cloned(),
+                )?),
+            };
+            Box::new(Raw::new(reader, input_dimensions.size()))
+        }
     };
 
-    let _ = pipe_frames(
+    pipeline::pipe_frames(
         input,
         output,
-        transposition,
-        color_correction,
-        dim,
-        single_frame,
-        frame_interval,
-    );
+        pipeline::Options {
+            transposition,
+            output_size,
+            correction: color_correction,
+            hsv_adjust: Some(HsvAdjust {
+                hue_shift: *matches.get_one::<f32>("hue-shift").unwrap(),
+                saturation: *matches.get_one::<f32>("saturation").unwrap(),
+                value: *matches.get_one::<f32>("value").unwrap(),
+            })
+            // Converting to HSV and back is not free, so only do it when needed.
+            .filter(|adjust| {
+                adjust.hue_shift != 0.0 || adjust.saturation != 1.0 || adjust.value != 1.0
+            }),
+            color_order: matches.get_one::<ColorOrder>("color-order").copied(),
+            single_frame,
+            frame_interval,
+            drop_frames: matches.get_flag("drop-frames"),
+            buffer_frames: *matches.get_one::<u32>("buffer-frames").unwrap() as usize,
+            first_frame_timeout: matches
+                .get_one::<u64>("stdin-timeout")
+                .map(|ms| Duration::from_millis(*ms)),
+            profile: matches.get_flag("profile"),
+            input_fps_report: matches.get_flag("input-fps-report"),
+            fps_log: matches.get_flag("fps-log"),
+            stats: matches.get_flag("stats"),
+            power_limit: matches.get_one::<f64>("max-power").map(|&max_watts| {
+                pipeline::PowerLimit {
+                    max_watts,
+                    milliamps_per_channel: *matches.get_one::<f64>("channel-current").unwrap(),
+                    volts: *matches.get_one::<f64>("led-voltage").unwrap(),
+                }
+            }),
+            dither: matches.get_flag("dither"),
+            blank: matches.get_one::<u64>("blank-on-timeout").map(|&ms| {
+                let &[r, g, b] = matches.get_one::<[u8; 3]>("blank-color").unwrap();
+                (Duration::from_millis(ms), Pixel { r, g, b })
+            }),
+            downscale: (input_dimensions != dimensions).then_some((input_dimensions, dimensions)),
+            shutdown: if matches.get_flag("no-clear-on-exit") {
+                None
+            } else {
+                Some(exit_signals()?)
+            },
+            verbosity: gargs.verbosity,
+        },
+    )?;
     Ok(())
 }
 
-fn pipe_frames(
-    mut input: impl io::Read + Send + 'static,
-    mut dev: impl Output + 'static,
-    transposition: Vec<usize>,
-    correction: Correction,
-    dim: u8,
-    single_frame: bool,
-    frame_interval: Option<Duration>,
-) -> io::Result<()> {
-    let (err_tx, err_rx) = mpsc::channel();
-    macro_rules! try_or_send {
-        ($tx:expr, $expression:expr) => {
-            match $expression {
-                Ok(val) => val,
-                Err(err) => {
-                    $tx.send(Err(err)).unwrap();
-                    return;
-                }
-            }
-        };
-    }
+/// The write end of the pipe that the exit signal handler wakes `exit_signals` up with.
+static EXIT_SIGNAL_PIPE: AtomicI32 = AtomicI32::new(-1);
 
-    let local_err_tx = err_tx.clone();
-    let num_pixels = transposition.len();
-    let (input_tx, input_rx) = mpsc::sync_channel(1);
-    thread::spawn(move || {
-        loop {
-            // Read a full frame into a buffer. This prevents half frames being written to a
-            // potentially timing sensitive output if the input blocks and lets us apply the
-            // transpositions.
-            let mut bin_buffer = vec![0; num_pixels * 3];
-            try_or_send!(local_err_tx, input.read_exact(&mut bin_buffer));
-            input_tx.send(bin_buffer).unwrap();
-            if single_frame {
-                break;
-            }
-        }
-    });
+extern "C" fn handle_exit_signal(_: nix::libc::c_int) {
+    // Only async-signal-safe functions can be used here.
+    let fd = EXIT_SIGNAL_PIPE.load(Ordering::Relaxed);
+    unsafe { nix::libc::write(fd, [0u8].as_ptr().cast(), 1) };
+}
 
-    let (map_tx, map_rx) = mpsc::sync_channel(1);
+/// Installs handlers for SIGINT and SIGTERM. The returned receiver gets a message once one of
+/// them is received, after which the default handlers are restored so a second signal terminates
+/// right away.
+fn exit_signals() -> io::Result<mpsc::Receiver<()>> {
+    use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+    let (pipe_rx, pipe_tx) = io_err!(nix::unistd::pipe())?;
+    EXIT_SIGNAL_PIPE.store(pipe_tx.into_raw_fd(), Ordering::Relaxed);
+    let install = |handler| -> io::Result<()> {
+        let action = SigAction::new(handler, SaFlags::SA_RESTART, SigSet::empty());
+        for signal in [Signal::SIGINT, Signal::SIGTERM] {
+            io_err!(unsafe { sigaction(signal, &action) })?;
+        }
+        Ok(())
+    };
+    install(SigHandler::Handler(handle_exit_signal))?;
+    let (tx, rx) = mpsc::channel();
     thread::spawn(move || {
-        for bin_buffer in input_rx.into_iter() {
-            let mut buffer = vec![Pixel { r: 0, g: 0, b: 0 }; transposition.len()];
-            for (transpose_mapped, bin) in transposition.iter().zip(bin_buffer.chunks(3)) {
-                // Load the pixel.
-                let pix = Pixel {
-                    r: bin[0],
-                    g: bin[1],
-                    b: bin[2],
-                };
-                // Apply dimming.
-                let pix = {
-                    let dim16 = u16::from(dim);
-                    Pixel {
-                        r: ((u16::from(pix.r) * dim16) / 0xff) as u8,
-                        g: ((u16::from(pix.g) * dim16) / 0xff) as u8,
-                        b: ((u16::from(pix.b) * dim16) / 0xff) as u8,
-                    }
-                };
-                // Apply color correction.
-                let pix = correction.correct(pix);
-                // Apply transposition and store the pixel in the output buffer.
-                buffer[*transpose_mapped] = pix;
-            }
-            map_tx.send(buffer).unwrap();
+        if fs::File::from(pipe_rx).read(&mut [0]).is_ok() {
+            let _ = install(SigHandler::SigDfl);
+            let _ = tx.send(());
         }
     });
+    Ok(rx)
+}
 
-    thread::spawn(move || loop {
-        let start = Instant::now();
+/// Parses three comma separated channel values.
+fn parse_rgb(s: &str) -> Result<[u8; 3], String> {
+    let v = s
+        .split(',')
+        .map(|v| v.trim().parse::<u8>().map_err(|err| err.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    v.try_into()
+        .map_err(|_| "expected three values: R,G,B".to_string())
+}
 
-        let buffer = match map_rx.recv() {
-            Ok(v) => v,
-            Err(_) => break,
-        };
-        try_or_send!(err_tx, dev.output_frame(&buffer));
+/// The name of the environment variable that sets a global option.
+fn env_var_name(id: &str) -> String {
+    format!("LEDCAT_{}", id.to_uppercase().replace('-', "_"))
+}
 
-        if let Some(interval) = frame_interval {
-            let el = start.elapsed();
-            if interval >= el {
-                thread::sleep(interval - el);
-            }
+/// Reads the display geometry from the environment. A warning is printed if it is set but invalid.
+fn env_dimensions() -> Option<Dimensions> {
+    let value = env::var(GEOMETRY_ENV).ok()?;
+    match parse_env_geometry(&value) {
+        Ok(d) => Some(d),
+        Err(err) => {
+            eprintln!("warning: ignoring {}: {}", GEOMETRY_ENV, err);
+            None
         }
-    });
+    }
+}
 
-    match err_rx.recv() {
-        Ok(err) => err,
-        Err(mpsc::RecvError) => Ok(()),
+/// Splits a comma separated chain of transpositions. A comma that is followed by a number continues
+/// the parameters of the previous operation, like those of affine.
+fn split_chain(chain: &str) -> Vec<&str> {
+    let mut ops = Vec::new();
+    let mut start = 0;
+    for (i, _) in chain.match_indices(',') {
+        let next = chain[i + 1..].split(',').next().unwrap();
+        if next.trim().parse::<f64>().is_ok() {
+            continue;
+        }
+        ops.push(chain[start..i].trim());
+        start = i + 1;
     }
+    ops.push(chain[start..].trim());
+    ops
+}
+
+/// Computes the dimensions of the display after the transpositions, which differ from the
+/// original if it is rotated.
+fn transposed_dimensions<'a>(
+    dimensions: &Dimensions,
+    mut operations: impl Iterator<Item = &'a str>,
+) -> Result<Dimensions, String> {
+    operations.try_fold(*dimensions, |current, name| {
+        Ok(map_transposition(&current, name)?.output_dimensions(current))
+    })
 }
 
 fn transposition_table<'a>(
     dimensions: &Dimensions,
     operations: impl Iterator<Item = &'a str>,
 ) -> Result<Vec<usize>, String> {
+    // Rotations change the dimensions of the image that the next operations work on.
+    let mut current = *dimensions;
     let transpositions = operations
-        .map(|name| map_transposition(dimensions, name))
-        .collect::<Result<Vec<_>, _>>()?;
+        .map(|name| {
+            let tr = map_transposition(&current, name)?;
+            current = tr.output_dimensions(current);
+            Ok(tr)
+        })
+        .collect::<Result<Vec<_>, String>>()?;
     Ok((0..dimensions.size())
         .map(|index| transpositions.transpose(index))
         .collect())
 }
 
+/// Computes the number of pixels of a strip that remain when every nth pixel is not connected.
+fn connected_dimensions(dimensions: &Dimensions, n: usize) -> Result<Dimensions, GenericError> {
+    if dimensions.h != 1 {
+        return Err(GenericError::new("--skip-every requires a 1D --geometry"));
+    }
+    Ok(Dimensions {
+        w: dimensions.w - dimensions.w / n,
+        h: 1,
+    })
+}
+
+/// Moves the pixels of a transposition table so every nth position of the output is left empty.
+fn skip_every(transposition: Vec<usize>, n: usize) -> Vec<usize> {
+    assert!(n >= 2);
+    transposition.into_iter().map(|i| i + i / (n - 1)).collect()
+}
+
 fn map_transposition(
     dimensions: &Dimensions,
     name: &str,
@@ -310,6 +767,49 @@ fn map_transposition(
                 _ => unreachable!(),
             },
         })),
+        "rotate_90" | "rotate_180" | "rotate_270" => Ok(Box::new(Rotate {
+            width: dimensions.w,
+            height: dimensions.h,
+            rotation: match name {
+                "rotate_90" => Rotation::Deg90,
+                "rotate_180" => Rotation::Deg180,
+                _ => Rotation::Deg270,
+            },
+        })),
+        name if name.starts_with("tile:") => {
+            // The sub-transposition may contain colons itself, so only split off the size.
+            let mut parts = name["tile:".len()..].splitn(2, ':');
+            let tile: Dimensions = parts.next().unwrap().parse()?;
+            let sub = match parts.next() {
+                Some(sub) => map_transposition(&tile, sub)?,
+                None => Box::new(Vec::<Box<dyn Transposition>>::new()),
+            };
+            Ok(Box::new(Tile::new(dimensions.w, dimensions.h, tile, sub)?))
+        }
+        name if name.starts_with("affine:") => {
+            let params = name["affine:".len()..]
+                .split(',')
+                .map(|v| v.trim().parse::<i64>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|err| format!("invalid affine parameter: {}", err))?;
+            let [a, b, c, d, tx, ty] = params[..] else {
+                return Err("affine takes 6 parameters: a,b,c,d,tx,ty".to_string());
+            };
+            Ok(Box::new(Affine::new(
+                dimensions.w,
+                dimensions.h,
+                [a, b, c, d],
+                [tx, ty],
+            )?))
+        }
+        name if name.starts_with("map:") => {
+            let path = &name["map:".len()..];
+            let text = fs::read_to_string(path)
+                .map_err(|err| format!("could not read {}: {}", path, err))?;
+            let lookup = Lookup::parse(&text, dimensions.size())
+                .map_err(|err| format!("invalid lookup table {}: {}", path, err))?;
+            Ok(Box::new(lookup))
+        }
         name => Err(format!("unknown transposition: {}", name)),
     }
 }
@@ -332,3 +832,101 @@ impl fmt::Display for GenericError {
 }
 
 impl Error for GenericError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::tests::{options, Frames, Recorder};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn reverse_before_transpositions() {
+        let dimensions = Dimensions { w: 3, h: 2 };
+        assert_eq!(
+            Ok(vec![5, 4, 3, 2, 1, 0]),
+            transposition_table(&dimensions, ["reverse"].into_iter())
+        );
+        // Reversing first and then mirroring over X flips the rows.
+        assert_eq!(
+            Ok(vec![3, 4, 5, 0, 1, 2]),
+            transposition_table(&dimensions, ["reverse", "mirror_x"].into_iter())
+        );
+    }
+
+    #[test]
+    fn transposition_chain() {
+        assert_eq!(
+            vec!["zigzag_y", "mirror_x", "reverse"],
+            split_chain("zigzag_y,mirror_x, reverse")
+        );
+        assert_eq!(
+            vec![
+                "affine:-1,0,0,1,1,0",
+                "reverse",
+                "tile:2x2:affine:0,1,1,0,0,0"
+            ],
+            split_chain("affine:-1,0,0,1,1,0,reverse,tile:2x2:affine:0,1,1,0,0,0")
+        );
+        assert_eq!(vec!["rotate_90"], split_chain("rotate_90"));
+    }
+
+    #[test]
+    fn tile_sub_transposition() {
+        let dimensions = Dimensions { w: 4, h: 2 };
+        assert_eq!(
+            Ok(vec![0, 3, 4, 7, 1, 2, 5, 6]),
+            transposition_table(&dimensions, ["tile:2x2:zigzag_x"].into_iter())
+        );
+        // Parameters of the sub-transposition are passed on as is.
+        assert_eq!(
+            transposition_table(&dimensions, ["tile:2x2:mirror_x"].into_iter()),
+            transposition_table(&dimensions, ["tile:2x2:affine:-1,0,0,1,1,0"].into_iter())
+        );
+        assert!(transposition_table(&dimensions, ["tile:3x2"].into_iter()).is_err());
+    }
+
+    #[test]
+    fn skip_every_4() {
+        let dimensions = Dimensions { w: 7, h: 1 };
+        let table = transposition_table(&dimensions, iter::empty()).unwrap();
+        // Positions 3 and 7 are not connected.
+        assert_eq!(vec![0, 1, 2, 4, 5, 6, 8], skip_every(table, 4));
+
+        let table = transposition_table(&dimensions, ["reverse"].into_iter()).unwrap();
+        assert_eq!(vec![8, 6, 5, 4, 2, 1, 0], skip_every(table, 4));
+
+        assert_eq!(
+            Dimensions { w: 7, h: 1 },
+            connected_dimensions(&Dimensions { w: 9, h: 1 }, 4).unwrap()
+        );
+        assert!(connected_dimensions(&Dimensions { w: 4, h: 4 }, 4).is_err());
+    }
+
+    #[test]
+    fn skip_every_output_size() {
+        // Every 4th pixel of a strip of 8 is not connected, the last position is one of them.
+        let geometry = Dimensions { w: 8, h: 1 };
+        let dimensions = connected_dimensions(&geometry, 4).unwrap();
+        let table = transposition_table(&dimensions, iter::empty()).unwrap();
+        let on = Pixel {
+            r: 255,
+            g: 255,
+            b: 255,
+        };
+        let off = Pixel { r: 0, g: 0, b: 0 };
+        let recorded = Arc::new(Mutex::new(Vec::new()));
+        pipeline::pipe_frames(
+            Frames(vec![vec![on; dimensions.size()]].into_iter()),
+            Recorder(recorded.clone()),
+            pipeline::Options {
+                output_size: geometry.size(),
+                ..options(skip_every(table, 4))
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            vec![vec![on, on, on, off, on, on, on, off]],
+            *recorded.lock().unwrap()
+        );
+    }
+}
@@ -0,0 +1,1028 @@
+use crate::color::*;
+use crate::device::*;
+use crate::input::geometry::Dimensions;
+use crate::input::*;
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The period over which the frame rate is averaged when reporting it.
+const FPS_REPORT_PERIOD: Duration = Duration::from_secs(1);
+
+/// Options controls how frames are processed between the input and the output.
+pub struct Options {
+    pub transposition: Vec<usize>,
+    /// The number of pixels sent to the output. Positions that are not in the transposition are
+    /// left black, e.g. for pixels that are not connected.
+    pub output_size: usize,
+    pub correction: Correction,
+    /// Transforms the colors of the input before color correction.
+    pub hsv_adjust: Option<HsvAdjust>,
+    /// The order of the color channels of the display, if it differs from what the output
+    /// sends.
+    pub color_order: Option<ColorOrder>,
+    pub single_frame: bool,
+    pub frame_interval: Option<Duration>,
+    /// When the output can not keep up, skip to the most recent frame instead of blocking the
+    /// input.
+    pub drop_frames: bool,
+    /// The number of frames that can be queued up for the output. Unused when dropping frames.
+    pub buffer_frames: usize,
+    /// Give up if the first frame does not arrive in time.
+    pub first_frame_timeout: Option<Duration>,
+    /// Measure the time spent in each stage and print it when done.
+    pub profile: bool,
+    /// Periodically print the rate at which frames are read from the input.
+    pub input_fps_report: bool,
+    /// Periodically print the rate at which frames are written to the output.
+    pub fps_log: bool,
+    /// Periodically print the output rate, the number of dropped frames and the output latency.
+    pub stats: bool,
+    /// Scales frames down if they would draw more power than allowed.
+    pub power_limit: Option<PowerLimit>,
+    /// Carry the rounding error of the color correction over to the next frame.
+    pub dither: bool,
+    /// Shows a solid color when no frame arrives within the timeout, until frames arrive again.
+    pub blank: Option<(Duration, Pixel)>,
+    /// Shrinks frames of the first dimensions down to the second, which are those of the display.
+    pub downscale: Option<(Dimensions, Dimensions)>,
+    /// Stops the pipeline and sends a black frame to the output once a message is received.
+    pub shutdown: Option<mpsc::Receiver<()>>,
+    pub verbosity: u8,
+}
+
+/// PowerLimit caps the power the display is estimated to draw.
+///
+/// The current drawn by each channel is assumed to be linear to its value.
+pub struct PowerLimit {
+    pub max_watts: f64,
+    /// The current drawn by a single channel at full brightness.
+    pub milliamps_per_channel: f64,
+    pub volts: f64,
+}
+
+impl PowerLimit {
+    /// Estimates the power in watts drawn by a frame.
+    fn estimate(&self, frame: &[Pixel]) -> f64 {
+        let sum: u64 = frame
+            .iter()
+            .map(|pix| u64::from(pix.r) + u64::from(pix.g) + u64::from(pix.b))
+            .sum();
+        let full = sum as f64 / 255.0;
+        full * self.milliamps_per_channel / 1000.0 * self.volts
+    }
+
+    /// Computes the brightness, where 255 is unchanged, that keeps the frame within the limit.
+    fn level(&self, frame: &[Pixel]) -> u8 {
+        let watts = self.estimate(frame);
+        if watts <= self.max_watts {
+            return 255;
+        }
+        // Round down so the limit is never exceeded.
+        (255.0 * self.max_watts / watts).floor() as u8
+    }
+}
+
+/// Scales the brightness of a frame by `level / 255`, used when a frame exceeds the power budget.
+fn scale_frame(frame: &mut [Pixel], level: u8) {
+    let level = u16::from(level);
+    for pix in frame {
+        *pix = Pixel {
+            r: ((u16::from(pix.r) * level) / 0xff) as u8,
+            g: ((u16::from(pix.g) * level) / 0xff) as u8,
+            b: ((u16::from(pix.b) * level) / 0xff) as u8,
+        };
+    }
+}
+
+/// The sending half of the channel between the mapping and output stages.
+#[derive(Clone)]
+enum FrameSender {
+    /// Blocks when the output is busy, applying backpressure to the input.
+    Bounded(mpsc::SyncSender<io::Result<Vec<Pixel>>>),
+    /// Never blocks, the output is responsible for dropping stale frames.
+    Unbounded(mpsc::Sender<io::Result<Vec<Pixel>>>),
+}
+
+impl FrameSender {
+    fn send(
+        &self,
+        frame: io::Result<Vec<Pixel>>,
+    ) -> Result<(), mpsc::SendError<io::Result<Vec<Pixel>>>> {
+        match self {
+            FrameSender::Bounded(tx) => tx.send(frame),
+            FrameSender::Unbounded(tx) => tx.send(frame),
+        }
+    }
+}
+
+/// Pacer schedules frames on a grid of absolute deadlines.
+///
+/// Sleeping for the remainder of the interval after each frame lets scheduling jitter and the time
+/// spent outside of the timed region accumulate into drift. Instead, the n-th frame is timed
+/// relative to the first one so playback stays locked to the requested frame rate.
+struct Pacer {
+    interval: Duration,
+    deadline: Option<Instant>,
+}
+
+impl Pacer {
+    fn new(interval: Duration) -> Pacer {
+        Pacer {
+            interval,
+            deadline: None,
+        }
+    }
+
+    /// Blocks until the deadline of the next frame.
+    fn wait(&mut self) {
+        if let Some(d) = self.delay(Instant::now()) {
+            thread::sleep(d);
+        }
+    }
+
+    /// Advances to the next deadline and computes how long to wait from `now` to reach it.
+    fn delay(&mut self, now: Instant) -> Option<Duration> {
+        let mut deadline = match self.deadline {
+            Some(prev) => prev + self.interval,
+            None => now + self.interval,
+        };
+        if now > deadline + self.interval {
+            // We have fallen behind by more than a frame, e.g. because the input stalled. Skip
+            // the missed deadlines instead of bursting out frames to catch up, while keeping in
+            // phase with the original grid.
+            let behind = (now - deadline).as_nanos() / self.interval.as_nanos();
+            deadline += self.interval * behind as u32;
+        }
+        self.deadline = Some(deadline);
+        deadline.checked_duration_since(now)
+    }
+}
+
+/// Profile accumulates the time spent in each stage of the pipeline.
+#[derive(Default)]
+struct Profile {
+    read: AtomicU64,
+    map: AtomicU64,
+    output: AtomicU64,
+    frames: AtomicU64,
+}
+
+impl Profile {
+    /// Runs the function and adds the time it took to the counter. Nothing is measured if
+    /// profiling is disabled.
+    fn time<T>(
+        profile: &Option<Arc<Profile>>,
+        stage: fn(&Profile) -> &AtomicU64,
+        f: impl FnOnce() -> T,
+    ) -> T {
+        let Some(profile) = profile else {
+            return f();
+        };
+        let start = Instant::now();
+        let v = f();
+        stage(profile).fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        v
+    }
+
+    fn report(&self) -> String {
+        let frames = self.frames.load(Ordering::Relaxed).max(1);
+        let stages = [
+            ("read", &self.read),
+            ("map", &self.map),
+            ("output", &self.output),
+        ];
+        let total: u64 = stages.iter().map(|(_, t)| t.load(Ordering::Relaxed)).sum();
+        let mut report = format!(
+            "profile of {} frames:\n",
+            self.frames.load(Ordering::Relaxed)
+        );
+        for (name, t) in stages {
+            let t = t.load(Ordering::Relaxed);
+            report += &format!(
+                "  {: <6} {:>10.3}ms total {:>8.3}ms/frame {:>5.1}%\n",
+                name,
+                t as f64 / 1e6,
+                t as f64 / 1e6 / frames as f64,
+                t as f64 * 100.0 / total.max(1) as f64,
+            );
+        }
+        report
+    }
+}
+
+/// Stats counts the frames passing through the output so they can be reported while running.
+#[derive(Default)]
+struct Stats {
+    frames: AtomicU64,
+    dropped: AtomicU64,
+    /// The total time spent in `output_frame`.
+    output_nanos: AtomicU64,
+}
+
+impl Stats {
+    fn load(&self) -> [u64; 3] {
+        [
+            self.frames.load(Ordering::Relaxed),
+            self.dropped.load(Ordering::Relaxed),
+            self.output_nanos.load(Ordering::Relaxed),
+        ]
+    }
+
+    /// Spawns a thread that prints the stats of each period to stderr. The thread stops once the
+    /// stats are dropped.
+    fn spawn_reporter(stats: &Arc<Stats>, period: Duration) {
+        let stats = Arc::downgrade(stats);
+        thread::spawn(move || {
+            let mut prev = [0; 3];
+            let mut prev_time = Instant::now();
+            loop {
+                thread::sleep(period);
+                let Some(stats) = stats.upgrade() else {
+                    return;
+                };
+                let (now, now_time) = (stats.load(), Instant::now());
+                eprintln!("{}", Stats::report(prev, now, now_time - prev_time));
+                (prev, prev_time) = (now, now_time);
+            }
+        });
+    }
+
+    /// Formats the difference between two loads of the counters, taken `elapsed` apart.
+    fn report(prev: [u64; 3], now: [u64; 3], elapsed: Duration) -> String {
+        let [frames, dropped, nanos] = [0, 1, 2].map(|i| now[i] - prev[i]);
+        format!(
+            "output fps: {:.1}, dropped: {}, latency: {:.3}ms",
+            frames as f64 / elapsed.as_secs_f64(),
+            dropped,
+            nanos as f64 / 1e6 / frames.max(1) as f64,
+        )
+    }
+}
+
+/// RateMeter measures the rate of frames passing through a stage of the pipeline.
+struct RateMeter {
+    period: Duration,
+    start: Option<Instant>,
+    frames: u32,
+}
+
+impl RateMeter {
+    fn new(period: Duration) -> RateMeter {
+        RateMeter {
+            period,
+            start: None,
+            frames: 0,
+        }
+    }
+
+    /// Counts a frame at `now`. Returns the average rate in frames per second once a full period
+    /// has passed since the first frame of the period.
+    fn tick(&mut self, now: Instant) -> Option<f64> {
+        let Some(start) = self.start else {
+            // Measure the intervals between frames, starting at the first one.
+            self.start = Some(now);
+            return None;
+        };
+        self.frames += 1;
+        let elapsed = now - start;
+        if elapsed < self.period {
+            return None;
+        }
+        let fps = f64::from(self.frames) / elapsed.as_secs_f64();
+        self.start = Some(now);
+        self.frames = 0;
+        Some(fps)
+    }
+
+    /// Counts a frame and prints the rate to stderr when it is due.
+    fn tick_and_report(meter: &mut Option<RateMeter>, name: &str) {
+        if let Some(fps) = meter.as_mut().and_then(|m| m.tick(Instant::now())) {
+            eprintln!("{} fps: {:.1}", name, fps);
+        }
+    }
+}
+
+/// Applies color correction and transposition to a frame. Positions in the output of
+/// `output_size` pixels that are not in the transposition table are left black.
+fn map_frame(
+    frame: Vec<Pixel>,
+    transposition: &[usize],
+    output_size: usize,
+    hsv_adjust: Option<&HsvAdjust>,
+    correction: &Correction,
+    mut dither_error: Option<&mut [(i16, i16, i16)]>,
+) -> Vec<Pixel> {
+    let mut buffer = vec![Pixel { r: 0, g: 0, b: 0 }; output_size];
+    for (transpose_mapped, pix) in transposition.iter().zip(frame) {
+        let pix = match hsv_adjust {
+            Some(adjust) => adjust.apply(pix),
+            None => pix,
+        };
+        // Apply color correction.
+        let pix = match &mut dither_error {
+            Some(error) => correction.correct_dithered(pix, &mut error[*transpose_mapped]),
+            None => correction.correct(pix),
+        };
+        // Apply transposition and store the pixel in the output buffer.
+        buffer[*transpose_mapped] = pix;
+    }
+    buffer
+}
+
+/// Shrinks a frame by averaging blocks of pixels. The average is taken over the light emitted by
+/// the display, so the overall brightness is preserved.
+fn downscale_frame(
+    frame: &[Pixel],
+    from: Dimensions,
+    to: Dimensions,
+    correction: &Correction,
+) -> Vec<Pixel> {
+    let mut buffer = Vec::with_capacity(to.size());
+    for y in 0..to.h {
+        let rows = y * from.h / to.h..(y + 1) * from.h / to.h;
+        for x in 0..to.w {
+            let cols = x * from.w / to.w..(x + 1) * from.w / to.w;
+            let mut sum = [0u64; 3];
+            for row in rows.clone() {
+                for pix in &frame[row * from.w + cols.start..row * from.w + cols.end] {
+                    for (sum, light) in sum.iter_mut().zip(correction.linear(*pix)) {
+                        *sum += u64::from(light);
+                    }
+                }
+            }
+            let n = (rows.len() * cols.len()) as u64;
+            buffer.push(correction.invert_linear(sum.map(|sum| ((sum + n / 2) / n) as u16)));
+        }
+    }
+    buffer
+}
+
+/// Lengthens the frame interval so frames are not sent faster than the output can show them.
+fn limit_interval(interval: Option<Duration>, max_frame_rate: Option<u32>) -> Option<Duration> {
+    match (interval, max_frame_rate) {
+        (Some(interval), Some(max)) if max > 0 => Some(interval.max(Duration::from_secs(1) / max)),
+        (interval, _) => interval,
+    }
+}
+
+pub fn pipe_frames(
+    mut input: impl Input + 'static,
+    mut dev: impl Output + 'static,
+    opts: Options,
+) -> io::Result<()> {
+    let Options {
+        transposition,
+        output_size,
+        correction,
+        hsv_adjust,
+        color_order,
+        single_frame,
+        frame_interval,
+        drop_frames,
+        buffer_frames,
+        first_frame_timeout,
+        profile,
+        input_fps_report,
+        fps_log,
+        stats,
+        power_limit,
+        dither,
+        blank,
+        downscale,
+        shutdown,
+        verbosity,
+    } = opts;
+    let profile = profile.then(|| Arc::new(Profile::default()));
+    let stats = stats.then(|| Arc::new(Stats::default()));
+    let color_order = color_order
+        .map(|order| order.relative_to(dev.color_order()))
+        .filter(|&order| order != ColorOrder::RGB);
+    if let Some(stats) = &stats {
+        Stats::spawn_reporter(stats, FPS_REPORT_PERIOD);
+    }
+
+    let frame_interval = {
+        let limited = limit_interval(frame_interval, dev.max_frame_rate());
+        if limited != frame_interval && verbosity >= 1 {
+            eprintln!(
+                "the framerate is limited to {} by the output",
+                dev.max_frame_rate().unwrap()
+            );
+        }
+        limited
+    };
+
+    // Errors from the input are sent through the pipeline in place of a frame so the frames that
+    // were read before it are still shown.
+    let num_pixels = transposition.len();
+    let input_pixels = downscale.map_or(num_pixels, |(from, _)| from.size());
+    assert!(transposition.iter().all(|&i| i < output_size));
+    // The blank frame is prepared up front, it is mapped like any other frame.
+    let blank = blank.map(|(timeout, color)| {
+        let mut frame = vec![color; num_pixels];
+        if let Some(limit) = &power_limit {
+            let level = limit.level(&frame);
+            scale_frame(&mut frame, level);
+        }
+        let mut buffer = map_frame(frame, &transposition, output_size, None, &correction, None);
+        if let Some(order) = color_order {
+            for pix in &mut buffer {
+                *pix = order.remap(*pix);
+            }
+        }
+        (timeout, buffer)
+    });
+    let (input_tx, input_rx) = mpsc::sync_channel(1);
+    let local_profile = profile.clone();
+    thread::spawn(move || {
+        let mut meter = input_fps_report.then(|| RateMeter::new(FPS_REPORT_PERIOD));
+        loop {
+            let frame = match Profile::time(&local_profile, |p| &p.read, || input.next_frame()) {
+                Ok(Some(frame)) => frame,
+                Ok(None) => break,
+                Err(err) => {
+                    let _ = input_tx.send(Err(err));
+                    break;
+                }
+            };
+            RateMeter::tick_and_report(&mut meter, "input");
+            assert_eq!(input_pixels, frame.len());
+            // The receiving end is gone if the output failed.
+            if input_tx.send(Ok(frame)).is_err() || single_frame {
+                break;
+            }
+        }
+    });
+
+    let (map_tx, map_rx) = if drop_frames {
+        let (tx, rx) = mpsc::channel();
+        (FrameSender::Unbounded(tx), rx)
+    } else {
+        let (tx, rx) = mpsc::sync_channel(buffer_frames);
+        (FrameSender::Bounded(tx), rx)
+    };
+    let stopped = Arc::new(AtomicBool::new(false));
+    // The sender used to wake up the output is taken away once the map thread is done, so it does
+    // not keep the channel open.
+    let waker = Arc::new(Mutex::new(Some(map_tx.clone())));
+    if let Some(shutdown) = shutdown {
+        let stopped = stopped.clone();
+        let waker = waker.clone();
+        thread::spawn(move || {
+            if shutdown.recv().is_ok() {
+                stopped.store(true, Ordering::Relaxed);
+                // Wake up the output if it is waiting for a frame. The message itself is never
+                // looked at.
+                if let Some(tx) = &*waker.lock().unwrap() {
+                    let _ = tx.send(Err(io::ErrorKind::Interrupted.into()));
+                }
+            }
+        });
+    }
+    let local_profile = profile.clone();
+    thread::spawn(move || {
+        let mut last_throttle_log: Option<Instant> = None;
+        // The rounding error of each pixel of the output buffer.
+        let mut dither_error = dither.then(|| vec![(0, 0, 0); output_size]);
+        for frame in input_rx.into_iter() {
+            let buffer = frame.map(|frame| {
+                Profile::time(
+                    &local_profile,
+                    |p| &p.map,
+                    || {
+                        let mut frame = match downscale {
+                            Some((from, to)) => downscale_frame(&frame, from, to, &correction),
+                            None => frame,
+                        };
+                        if let Some(limit) = &power_limit {
+                            // The frame is scaled before the color correction, so the rounding
+                            // error that is carried over when dithering is that of the frame
+                            // that is shown.
+                            let level = limit.level(&frame);
+                            if level < 255 {
+                                scale_frame(&mut frame, level);
+                                let log_due = last_throttle_log
+                                    .is_none_or(|t| t.elapsed() >= Duration::from_secs(1));
+                                if log_due {
+                                    eprintln!(
+                                        "power limit of {}W reached, dimming to {:.0}%",
+                                        limit.max_watts,
+                                        f64::from(level) * 100.0 / 255.0
+                                    );
+                                    last_throttle_log = Some(Instant::now());
+                                }
+                            }
+                        }
+                        let mut buffer = map_frame(
+                            frame,
+                            &transposition,
+                            output_size,
+                            hsv_adjust.as_ref(),
+                            &correction,
+                            dither_error.as_deref_mut(),
+                        );
+                        if let Some(order) = color_order {
+                            for pix in &mut buffer {
+                                *pix = order.remap(*pix);
+                            }
+                        }
+                        buffer
+                    },
+                )
+            });
+            if map_tx.send(buffer).is_err() {
+                break;
+            }
+        }
+        waker.lock().unwrap().take();
+    });
+
+    // The output runs on the calling thread, so by the time we return, every frame that was read
+    // has been written and the output has been dropped.
+    let mut num_dropped: u64 = 0;
+    let mut output = || -> io::Result<()> {
+        let mut pacer = frame_interval.map(Pacer::new);
+        let mut meter = fps_log.then(|| RateMeter::new(FPS_REPORT_PERIOD));
+        let mut first_frame_timeout = first_frame_timeout;
+        let mut blanked = false;
+        loop {
+            let received = match first_frame_timeout.take() {
+                // Reading may block indefinitely, so the first frame is waited for with a
+                // timeout.
+                Some(timeout) => match map_rx.recv_timeout(timeout) {
+                    Err(mpsc::RecvTimeoutError::Timeout) => {
+                        let msg = format!("no data received within {}ms", timeout.as_millis());
+                        return Err(io::Error::new(io::ErrorKind::TimedOut, msg));
+                    }
+                    received => received.ok(),
+                },
+                // Once blanked, there is nothing to do until the next frame arrives.
+                None => match blank.as_ref().filter(|_| !blanked) {
+                    Some((timeout, frame)) => match map_rx.recv_timeout(*timeout) {
+                        Err(mpsc::RecvTimeoutError::Timeout) => {
+                            dev.output_frame(frame)?;
+                            blanked = true;
+                            continue;
+                        }
+                        received => received.ok(),
+                    },
+                    None => map_rx.recv().ok(),
+                },
+            };
+            blanked = false;
+            let Some(received) = received else {
+                return Ok(());
+            };
+            if stopped.load(Ordering::Relaxed) {
+                return Ok(());
+            }
+            let mut buffer = received?;
+            let mut pending_err = None;
+            if drop_frames {
+                // Frames that were queued up while the output was busy are stale, only the
+                // latest one is shown.
+                while let Ok(newer) = map_rx.try_recv() {
+                    match newer {
+                        Ok(newer) => buffer = newer,
+                        Err(err) => {
+                            pending_err = Some(err);
+                            break;
+                        }
+                    }
+                    num_dropped += 1;
+                }
+            }
+            let start = Instant::now();
+            Profile::time(&profile, |p| &p.output, || dev.output_frame(&buffer))?;
+            if let Some(stats) = &stats {
+                let nanos = start.elapsed().as_nanos() as u64;
+                stats.output_nanos.fetch_add(nanos, Ordering::Relaxed);
+                stats.frames.fetch_add(1, Ordering::Relaxed);
+                stats.dropped.store(num_dropped, Ordering::Relaxed);
+            }
+            RateMeter::tick_and_report(&mut meter, "output");
+            if let Some(profile) = &profile {
+                profile.frames.fetch_add(1, Ordering::Relaxed);
+            }
+            if let Some(err) = pending_err {
+                return Err(err);
+            }
+
+            if let Some(pacer) = &mut pacer {
+                pacer.wait();
+            }
+        }
+    };
+    let result = output();
+    let result = if stopped.load(Ordering::Relaxed) {
+        dev.output_frame(&vec![Pixel { r: 0, g: 0, b: 0 }; output_size])
+    } else {
+        result
+    };
+
+    if drop_frames && verbosity >= 1 {
+        eprintln!("dropped {} frames", num_dropped);
+    }
+    if let Some(profile) = profile {
+        eprint!("{}", profile.report());
+    }
+    result
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    pub(crate) struct Frames(pub(crate) std::vec::IntoIter<Vec<Pixel>>);
+
+    impl Input for Frames {
+        fn next_frame(&mut self) -> io::Result<Option<Vec<Pixel>>> {
+            Ok(self.0.next())
+        }
+    }
+
+    /// Records the frames it receives while being slow to mimic bursty timing.
+    pub(crate) struct Recorder(pub(crate) Arc<Mutex<Vec<Vec<Pixel>>>>);
+
+    impl Output for Recorder {
+        fn output_frame(&mut self, frame: &[Pixel]) -> io::Result<()> {
+            thread::sleep(Duration::from_millis(1));
+            self.0.lock().unwrap().push(frame.to_vec());
+            Ok(())
+        }
+    }
+
+    /// The options of a pipeline that passes frames on as is.
+    pub(crate) fn options(transposition: Vec<usize>) -> Options {
+        Options {
+            output_size: transposition.len(),
+            transposition,
+            correction: Correction::none(),
+            hsv_adjust: None,
+            color_order: None,
+            single_frame: false,
+            frame_interval: None,
+            drop_frames: false,
+            buffer_frames: 1,
+            first_frame_timeout: None,
+            profile: false,
+            input_fps_report: false,
+            fps_log: false,
+            stats: false,
+            power_limit: None,
+            dither: false,
+            blank: None,
+            downscale: None,
+            shutdown: None,
+            verbosity: 0,
+        }
+    }
+
+    #[test]
+    fn buffered_frames_keep_order() {
+        let frames: Vec<_> = (0..50)
+            .map(|i| vec![Pixel { r: i, g: 0, b: 0 }; 2])
+            .collect();
+        let recorded = Arc::new(Mutex::new(Vec::new()));
+        pipe_frames(
+            Frames(frames.clone().into_iter()),
+            Recorder(recorded.clone()),
+            Options {
+                buffer_frames: 3,
+                ..options(vec![0, 1])
+            },
+        )
+        .unwrap();
+        assert_eq!(frames, *recorded.lock().unwrap());
+    }
+
+    #[test]
+    fn single_frame_is_drained() {
+        let frames: Vec<_> = (0..5)
+            .map(|i| vec![Pixel { r: i, g: 0, b: 0 }; 2])
+            .collect();
+        let recorded = Arc::new(Mutex::new(Vec::new()));
+        pipe_frames(
+            Frames(frames.clone().into_iter()),
+            Recorder(recorded.clone()),
+            Options {
+                single_frame: true,
+                ..options(vec![0, 1])
+            },
+        )
+        .unwrap();
+        assert_eq!(frames[..1], *recorded.lock().unwrap());
+    }
+
+    #[test]
+    fn frames_before_input_error_are_drained() {
+        struct Failing(Frames);
+        impl Input for Failing {
+            fn next_frame(&mut self) -> io::Result<Option<Vec<Pixel>>> {
+                match self.0.next_frame()? {
+                    Some(frame) => Ok(Some(frame)),
+                    None => Err(io::Error::other("broken")),
+                }
+            }
+        }
+
+        let frames: Vec<_> = (0..5)
+            .map(|i| vec![Pixel { r: i, g: 0, b: 0 }; 1])
+            .collect();
+        let recorded = Arc::new(Mutex::new(Vec::new()));
+        let err = pipe_frames(
+            Failing(Frames(frames.clone().into_iter())),
+            Recorder(recorded.clone()),
+            options(vec![0]),
+        )
+        .unwrap_err();
+        assert_eq!("broken", err.to_string());
+        assert_eq!(frames, *recorded.lock().unwrap());
+    }
+
+    #[test]
+    fn shutdown_clears_display() {
+        /// Produces a single frame and then blocks, like an idle input.
+        struct Idle(Option<Vec<Pixel>>, mpsc::Receiver<()>);
+        impl Input for Idle {
+            fn next_frame(&mut self) -> io::Result<Option<Vec<Pixel>>> {
+                if let Some(frame) = self.0.take() {
+                    return Ok(Some(frame));
+                }
+                let _ = self.1.recv();
+                Ok(None)
+            }
+        }
+
+        let frame = vec![Pixel { r: 1, g: 2, b: 3 }; 2];
+        let (_idle_tx, idle_rx) = mpsc::channel();
+        let (shutdown_tx, shutdown_rx) = mpsc::channel();
+        let recorded = Arc::new(Mutex::new(Vec::new()));
+        let local_recorded = recorded.clone();
+        thread::spawn(move || {
+            while local_recorded.lock().unwrap().is_empty() {
+                thread::sleep(Duration::from_millis(1));
+            }
+            shutdown_tx.send(()).unwrap();
+        });
+        pipe_frames(
+            Idle(Some(frame.clone()), idle_rx),
+            Recorder(recorded.clone()),
+            Options {
+                shutdown: Some(shutdown_rx),
+                ..options(vec![0, 1])
+            },
+        )
+        .unwrap();
+        let black = vec![Pixel { r: 0, g: 0, b: 0 }; 2];
+        assert_eq!(vec![frame, black], *recorded.lock().unwrap());
+    }
+
+    #[test]
+    fn shutdown_does_not_keep_pipeline_open() {
+        let frames = vec![vec![Pixel { r: 1, g: 2, b: 3 }]; 3];
+        let (_shutdown_tx, shutdown_rx) = mpsc::channel();
+        let recorded = Arc::new(Mutex::new(Vec::new()));
+        pipe_frames(
+            Frames(frames.clone().into_iter()),
+            Recorder(recorded.clone()),
+            Options {
+                shutdown: Some(shutdown_rx),
+                ..options(vec![0])
+            },
+        )
+        .unwrap();
+        assert_eq!(frames, *recorded.lock().unwrap());
+    }
+
+    #[test]
+    fn blank_on_timeout() {
+        struct Channel(mpsc::Receiver<Vec<Pixel>>);
+        impl Input for Channel {
+            fn next_frame(&mut self) -> io::Result<Option<Vec<Pixel>>> {
+                Ok(self.0.recv().ok())
+            }
+        }
+
+        let frames: Vec<_> = (1..3).map(|i| vec![Pixel { r: i, g: 0, b: 0 }]).collect();
+        let blank = vec![Pixel { r: 0, g: 0, b: 9 }];
+        let (frame_tx, frame_rx) = mpsc::channel();
+        let recorded = Arc::new(Mutex::new(Vec::new()));
+        let local_recorded = recorded.clone();
+        let local_frames = frames.clone();
+        thread::spawn(move || {
+            frame_tx.send(local_frames[0].clone()).unwrap();
+            while local_recorded.lock().unwrap().len() < 2 {
+                thread::sleep(Duration::from_millis(1));
+            }
+            // The blank frame is only sent once while idle.
+            thread::sleep(Duration::from_millis(50));
+            frame_tx.send(local_frames[1].clone()).unwrap();
+        });
+        pipe_frames(
+            Channel(frame_rx),
+            Recorder(recorded.clone()),
+            Options {
+                blank: Some((Duration::from_millis(10), blank[0])),
+                ..options(vec![0])
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            vec![frames[0].clone(), blank, frames[1].clone()],
+            *recorded.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn downscale() {
+        let gray = |v| Pixel { r: v, g: v, b: v };
+        let frame = [0, 20, 100, 100, 40, 40, 255, 0].map(gray);
+        let from = Dimensions { w: 4, h: 2 };
+        let to = Dimensions { w: 2, h: 1 };
+        assert_eq!(
+            vec![gray(25), gray(114)],
+            downscale_frame(&frame, from, to, &Correction::none())
+        );
+        // Averaged in linear light, half of the pixels being white is brighter than half gray.
+        let frame = [0, 255].map(gray);
+        let from = Dimensions { w: 2, h: 1 };
+        let to = Dimensions { w: 1, h: 1 };
+        let correction = Correction::srgb(255, 255, 255);
+        assert_eq!(
+            vec![gray(188)],
+            downscale_frame(&frame, from, to, &correction)
+        );
+    }
+
+    #[test]
+    fn pacer_does_not_drift() {
+        let interval = Duration::from_millis(10);
+        let t0 = Instant::now();
+        let mut pacer = Pacer::new(interval);
+        let mut now = t0;
+        for n in 1..=1000 {
+            // Emulate a frame that takes some time to output and a sleep that overshoots.
+            now += Duration::from_micros(100 + n % 7 * 300);
+            let delay = pacer.delay(now).unwrap();
+            now += delay + Duration::from_micros(n % 3 * 150);
+        }
+        // A relative sleep would have accumulated all overshoots, over a second in total.
+        let drift = now - (t0 + interval * 1000);
+        assert!(drift < Duration::from_millis(1), "drift: {:?}", drift);
+    }
+
+    #[test]
+    fn pacer_skips_missed_deadlines() {
+        let interval = Duration::from_millis(10);
+        let t0 = Instant::now();
+        let mut pacer = Pacer::new(interval);
+        assert_eq!(Some(interval), pacer.delay(t0));
+        // The input stalls for a while, the frame should be sent immediately.
+        let now = t0 + Duration::from_millis(55);
+        assert_eq!(None, pacer.delay(now));
+        // The next frame should be back in phase instead of being sent right away to catch up.
+        let now = t0 + Duration::from_millis(57);
+        assert_eq!(Some(Duration::from_millis(3)), pacer.delay(now));
+    }
+
+    #[test]
+    fn stats_report() {
+        let report = Stats::report(
+            [10, 1, 5_000_000],
+            [40, 3, 20_000_000],
+            Duration::from_millis(500),
+        );
+        assert_eq!("output fps: 60.0, dropped: 2, latency: 0.500ms", report);
+        // No division by zero when nothing was output.
+        let report = Stats::report([0; 3], [0; 3], Duration::from_secs(1));
+        assert_eq!("output fps: 0.0, dropped: 0, latency: 0.000ms", report);
+    }
+
+    #[test]
+    fn rate_meter() {
+        let t0 = Instant::now();
+        let mut meter = RateMeter::new(Duration::from_secs(1));
+        assert_eq!(None, meter.tick(t0));
+        for i in 1..40 {
+            assert_eq!(None, meter.tick(t0 + Duration::from_millis(25) * i));
+        }
+        assert_eq!(Some(40.0), meter.tick(t0 + Duration::from_secs(1)));
+        // A new period starts at the frame that completed the previous one.
+        assert_eq!(None, meter.tick(t0 + Duration::from_millis(1500)));
+        assert_eq!(Some(2.0), meter.tick(t0 + Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn power_limit() {
+        let limit = PowerLimit {
+            max_watts: 1.5,
+            milliamps_per_channel: 20.0,
+            volts: 5.0,
+        };
+        let white = vec![
+            Pixel {
+                r: 255,
+                g: 255,
+                b: 255
+            };
+            10
+        ];
+        // 10 pixels * 3 channels * 20mA * 5V = 3W
+        assert!((limit.estimate(&white) - 3.0).abs() < 1e-9);
+        assert_eq!(127, limit.level(&white));
+        let mut dimmed = white.clone();
+        scale_frame(&mut dimmed, limit.level(&white));
+        assert!(limit.estimate(&dimmed) <= 1.5);
+        // Frames within the limit are left alone.
+        assert_eq!(255, limit.level(&white[..5]));
+    }
+
+    #[test]
+    fn power_limit_before_correction() {
+        let white = Pixel {
+            r: 255,
+            g: 255,
+            b: 255,
+        };
+        let recorded = Arc::new(Mutex::new(Vec::new()));
+        pipe_frames(
+            Frames(vec![vec![white; 10]].into_iter()),
+            Recorder(recorded.clone()),
+            Options {
+                correction: Correction::gamma(255, 255, 255, 2.2),
+                power_limit: Some(PowerLimit {
+                    max_watts: 1.5,
+                    milliamps_per_channel: 20.0,
+                    volts: 5.0,
+                }),
+                ..options((0..10).collect())
+            },
+        )
+        .unwrap();
+        // The input is scaled to half, after which the color correction is applied.
+        let half = Pixel {
+            r: 127,
+            g: 127,
+            b: 127,
+        };
+        let expected = Correction::gamma(255, 255, 255, 2.2).correct(half);
+        assert_eq!(vec![vec![expected; 10]], *recorded.lock().unwrap());
+    }
+
+    #[test]
+    fn interval_limited_by_output() {
+        let fps = |n| Some(Duration::from_secs(1) / n);
+        assert_eq!(fps(60), limit_interval(fps(100), Some(60)));
+        assert_eq!(fps(30), limit_interval(fps(30), Some(60)));
+        assert_eq!(fps(100), limit_interval(fps(100), None));
+        assert_eq!(None, limit_interval(None, Some(60)));
+    }
+
+    #[test]
+    fn first_frame_timeout() {
+        struct Stalled(mpsc::Receiver<()>);
+        impl Input for Stalled {
+            fn next_frame(&mut self) -> io::Result<Option<Vec<Pixel>>> {
+                let _ = self.0.recv();
+                Ok(None)
+            }
+        }
+
+        let (_stall_tx, stall_rx) = mpsc::channel();
+        let start = Instant::now();
+        let err = pipe_frames(
+            Stalled(stall_rx),
+            Recorder(Arc::new(Mutex::new(Vec::new()))),
+            Options {
+                first_frame_timeout: Some(Duration::from_millis(50)),
+                ..options(vec![0])
+            },
+        )
+        .unwrap_err();
+        assert_eq!(io::ErrorKind::TimedOut, err.kind());
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn profile_report() {
+        let profile = Arc::new(Profile::default());
+        Profile::time(
+            &Some(profile.clone()),
+            |p| &p.output,
+            || thread::sleep(Duration::from_millis(10)),
+        );
+        profile.frames.store(2, Ordering::Relaxed);
+        assert!(profile.output.load(Ordering::Relaxed) >= 10_000_000);
+        let report = profile.report();
+        assert!(report.starts_with("profile of 2 frames:\n"));
+        assert!(report.contains("100.0%"), "{}", report);
+
+        // Disabled profiling still runs the function.
+        assert_eq!(42, Profile::time(&None, |p| &p.read, || 42));
+    }
+}
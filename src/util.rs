@@ -1,5 +1,62 @@
 macro_rules! io_err {
     ($expr:expr) => {
-        $expr.map_err(|err| io::Error::new(io::ErrorKind::Other, err))
+        $expr.map_err(io::Error::other)
     };
 }
+
+/// Parses a string of hexadecimal digits into bytes, e.g. "0d0a".
+pub fn parse_hex(s: &str) -> Result<Vec<u8>, String> {
+    let s = s.strip_prefix("0x").unwrap_or(s);
+    if s.is_empty() || !s.is_ascii() || !s.len().is_multiple_of(2) {
+        return Err(format!("\"{}\" is not an even number of hex digits", s));
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&s[i..i + 2], 16)
+                .map_err(|_| format!("\"{}\" is not a valid hex string", s))
+        })
+        .collect()
+}
+
+/// Formats a buffer as a classic hex dump with 16 bytes per line and an ASCII column.
+pub fn hex_dump(buf: &[u8]) -> String {
+    buf.chunks(16)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let hex: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+            let ascii: String = chunk
+                .iter()
+                .map(|&b| match b {
+                    0x20..=0x7e => b as char,
+                    _ => '.',
+                })
+                .collect();
+            format!("{:04x}  {: <47}  |{}|\n", i * 16, hex.join(" "), ascii)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_hex_bytes() {
+        assert_eq!(Ok(vec![0x0d, 0x0a]), parse_hex("0d0a"));
+        assert_eq!(Ok(vec![0xff]), parse_hex("0xFF"));
+        assert!(parse_hex("").is_err());
+        assert!(parse_hex("abc").is_err());
+        assert!(parse_hex("zz").is_err());
+    }
+
+    #[test]
+    fn hex_dump_lines() {
+        let dump = hex_dump(b"Art-Net\0\x00\x21 and some more");
+        assert_eq!(
+            "0000  41 72 74 2d 4e 65 74 00 00 21 20 61 6e 64 20 73  |Art-Net..! and s|\n\
+             0010  6f 6d 65 20 6d 6f 72 65                          |ome more|\n",
+            dump
+        );
+    }
+}